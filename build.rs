@@ -0,0 +1,23 @@
+// With the `capi` feature, generates the C header for `src/ffi.rs` into `OUT_DIR`. Set
+// `MSC_HEADER_OUT` to also copy it somewhere, `tests/capi/Makefile` uses this to
+// refresh `include/msc.h`. Nothing is written to the source tree otherwise.
+fn main() {
+    #[cfg(feature = "capi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-env-changed=MSC_HEADER_OUT");
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let header = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("msc.h");
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).unwrap();
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{}/src/ffi.rs", dir))
+            .generate()
+            .expect("failed to generate C header")
+            .write_to_file(&header);
+        if let Ok(out) = std::env::var("MSC_HEADER_OUT") {
+            std::fs::copy(&header, &out).unwrap_or_else(|e| panic!("failed to copy C header to {}: {}", out, e));
+        }
+    }
+}
@@ -0,0 +1,206 @@
+#ifndef MSC_H
+#define MSC_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/* Opaque handle to a parsed file */
+typedef struct MscsbFile MscsbFile;
+
+/**
+ * Returned by every fallible function, `MSC_ERROR_SUCCESS` on success
+ */
+typedef enum MscError {
+  MSC_ERROR_SUCCESS = 0,
+  /**
+   * A required pointer was null
+   */
+  MSC_ERROR_NULL_POINTER = 1,
+  /**
+   * The data isn't a valid mscsb file
+   */
+  MSC_ERROR_INVALID_FILE = 2,
+  /**
+   * A script, command or string index past the end
+   */
+  MSC_ERROR_OUT_OF_RANGE = 3,
+  /**
+   * Unknown mnemonic, wrong number of operands or an operand that doesn't fit
+   */
+  MSC_ERROR_BAD_COMMAND = 4,
+  /**
+   * A string that isn't valid UTF-8
+   */
+  MSC_ERROR_BAD_STRING = 5,
+  /**
+   * The output buffer is too small, the needed size has been written out
+   */
+  MSC_ERROR_BUFFER_TOO_SMALL = 6,
+  /**
+   * A bug in the library, the handle should not be used again
+   */
+  MSC_ERROR_PANIC = 7,
+} MscError;
+
+/**
+ * A decoded command. When passed in, `opcode` is ignored and the command is built
+ * from `name` and the first `operand_count` operands.
+ */
+typedef struct MscCommand {
+  /**
+   * NUL terminated mnemonic, as printed by the disassembler
+   */
+  char name[16];
+  uint8_t opcode;
+  bool push_bit;
+  uint32_t position;
+  uint32_t operand_count;
+  uint32_t operands[2];
+} MscCommand;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Static description of an error code
+ */
+const char *msc_error_message(enum MscError error);
+
+/**
+ * Parse `len` bytes into a new handle, free it with `msc_file_free`
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes and `file` must be writable
+ */
+enum MscError msc_file_load(const uint8_t *data, size_t len, MscsbFile **file);
+
+/**
+ * # Safety
+ * `file` must be null or come from `msc_file_load`, and not be used afterwards
+ */
+void msc_file_free(MscsbFile *file);
+
+/**
+ * Serialize into `buf`. `len` is set to the size of the file even if `buf` is too
+ * small, so passing a null `buf` first gives the size to allocate.
+ *
+ * # Safety
+ * `file` must be a valid handle, `buf` null or `cap` writable bytes and `len` writable
+ */
+enum MscError msc_file_write(const MscsbFile *file, uint8_t *buf, size_t cap, size_t *len);
+
+/**
+ * # Safety
+ * `file` must be a valid handle or null
+ */
+size_t msc_file_script_count(const MscsbFile *file);
+
+/**
+ * # Safety
+ * `file` must be a valid handle and `start` and `end` writable
+ */
+enum MscError msc_file_script_bounds(const MscsbFile *file,
+                                     size_t script,
+                                     uint32_t *start,
+                                     uint32_t *end);
+
+/**
+ * # Safety
+ * `file` must be a valid handle and `count` writable
+ */
+enum MscError msc_file_command_count(const MscsbFile *file, size_t script, size_t *count);
+
+/**
+ * # Safety
+ * `file` must be a valid handle and `command` writable
+ */
+enum MscError msc_file_get_command(const MscsbFile *file,
+                                   size_t script,
+                                   size_t index,
+                                   struct MscCommand *command);
+
+/**
+ * Replace a command. Positions and branch targets are left for the caller to keep
+ * consistent, as with editing `Script::commands` directly.
+ *
+ * # Safety
+ * `file` must be a valid handle and `command` readable
+ */
+enum MscError msc_file_set_command(MscsbFile *file,
+                                   size_t script,
+                                   size_t index,
+                                   const struct MscCommand *command);
+
+/**
+ * Insert a command before `index`, which may be the command count to append
+ *
+ * # Safety
+ * `file` must be a valid handle and `command` readable
+ */
+enum MscError msc_file_insert_command(MscsbFile *file,
+                                      size_t script,
+                                      size_t index,
+                                      const struct MscCommand *command);
+
+/**
+ * # Safety
+ * `file` must be a valid handle
+ */
+enum MscError msc_file_remove_command(MscsbFile *file, size_t script, size_t index);
+
+/**
+ * # Safety
+ * `file` must be a valid handle or null
+ */
+size_t msc_file_string_count(const MscsbFile *file);
+
+/**
+ * Copy a string into `buf` with a NUL terminator. `len` is set to the size needed
+ * including the terminator, see `msc_file_write`.
+ *
+ * # Safety
+ * `file` must be a valid handle, `buf` null or `cap` writable bytes and `len` writable
+ */
+enum MscError msc_file_get_string(const MscsbFile *file,
+                                  size_t index,
+                                  char *buf,
+                                  size_t cap,
+                                  size_t *len);
+
+/**
+ * # Safety
+ * `file` must be a valid handle and `string` NUL terminated
+ */
+enum MscError msc_file_set_string(MscsbFile *file, size_t index, const char *string);
+
+/**
+ * Append a string, writing its index to `index` if it isn't null
+ *
+ * # Safety
+ * `file` must be a valid handle, `string` NUL terminated and `index` null or writable
+ */
+enum MscError msc_file_add_string(MscsbFile *file, const char *string, size_t *index);
+
+/**
+ * # Safety
+ * `file` must be a valid handle or null
+ */
+uint32_t msc_file_entrypoint(const MscsbFile *file);
+
+/**
+ * # Safety
+ * `file` must be a valid handle
+ */
+enum MscError msc_file_set_entrypoint(MscsbFile *file, uint32_t entrypoint);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MSC_H */
@@ -0,0 +1,274 @@
+//
+// Parses the body of `msc::msc!` at compile time. `msc!` passes `$crate` as the first
+// token so the expansion can name the library's types.
+//
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Literal, Span, TokenStream, TokenTree};
+use std::collections::HashMap;
+
+#[derive(Copy, Clone)]
+enum Kind {
+    /// Plain value with the largest value its field holds
+    Value(u32),
+    /// Branch target, a label or an absolute position
+    Target,
+}
+
+const U8: Kind = Kind::Value(0xFF);
+const U16: Kind = Kind::Value(0xFFFF);
+const U32: Kind = Kind::Value(0xFFFF_FFFF);
+const VARIABLE: &[Kind] = &[U8, U16];
+
+// Operands of each mnemonic, matching the field widths of `Cmd`
+fn operands(name: &str) -> Option<&'static [Kind]> {
+    Some(match name {
+        "Nop" | "Unk1" | "End" | "Return6" | "Return7" | "Return8" | "Return9" |
+        "ErrorC" | "AddI" | "SubI" | "MultI" | "DivI" | "ModI" | "NegI" | "AndI" |
+        "OrI" | "NotI" | "XorI" | "ShiftL" | "ShiftR" | "Equals" | "NotEquals" |
+        "LessThan" | "LessOrEqual" | "Greater" | "GreaterOrEqual" | "Not" | "Push" |
+        "Pop" | "Error37" | "AddF" | "SubF" | "MultF" | "DivF" | "NegF" | "EqualsF" |
+        "NotEqualsF" | "LessThanF" | "LessOrEqualF" | "GreaterF" | "GreaterOrEqualF" |
+        "Error4C" | "Exit" => &[],
+        "Jump" | "Jump5" | "Try" | "If" | "IfNot" | "Else" => &[Kind::Target],
+        "PushInt" => &[U32],
+        "PushShort" => &[U16],
+        "PrintF" | "CallFunc" | "CallFunc2" | "CallFunc3" | "IntToFloat" | "FloatToInt" => &[U8],
+        "Begin" => &[U16, U16],
+        "Sys" => &[U8, U8],
+        "PushVar" | "IncI" | "DecI" | "SetVar" | "AddVarBy" | "SubVarBy" | "MultVarBy" |
+        "DivVarBy" | "ModVarBy" | "AndVarBy" | "OrVarBy" | "XorVarBy" | "IncF" | "DecF" |
+        "VarSetF" | "AddVarByF" | "SubVarByF" | "MultVarByF" | "DivVarByF" => VARIABLE,
+        _ => return None,
+    })
+}
+
+struct Error {
+    span: Span,
+    message: String,
+}
+
+fn error<T>(span: Span, message: String) -> Result<T, Error> {
+    Err(Error { span, message })
+}
+
+#[doc(hidden)]
+#[proc_macro]
+pub fn script(input: TokenStream) -> TokenStream {
+    let mut tokens = input.into_iter();
+    let krate: TokenStream = tokens
+        .by_ref()
+        .take_while(|token| !is_punct(token, ';'))
+        .collect();
+    match expand(&krate, strip_comments(tokens)) {
+        Ok(expansion) => expansion,
+        Err(Error { span, message }) => {
+            let mut message = Literal::string(&format!("msc!: {}", message));
+            message.set_span(span);
+            let mut arguments = Group::new(Delimiter::Parenthesis, TokenTree::Literal(message).into());
+            arguments.set_span(span);
+            let mut tokens = respan(code("compile_error!"), span);
+            tokens.extend(Some(TokenTree::Group(arguments)));
+            tokens
+        }
+    }
+}
+
+// `;` comments out the rest of its line, like in `Disassembler` output
+fn strip_comments<I: Iterator<Item = TokenTree>>(tokens: I) -> Vec<TokenTree> {
+    let mut comment_line = None;
+    let mut kept = vec![];
+    for token in tokens {
+        let line = token.span().line();
+        if comment_line == Some(line) {
+            continue;
+        }
+        if is_punct(&token, ';') {
+            comment_line = Some(line);
+        } else {
+            kept.push(token);
+        }
+    }
+    kept
+}
+
+fn expand(krate: &TokenStream, tokens: Vec<TokenTree>) -> Result<TokenStream, Error> {
+    let mut body = code("let mut builder =");
+    body.extend(path(krate, "ScriptBuilder::new();"));
+    let mut labels = HashMap::new();
+    let mut branches = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        let name = match tokens[i] {
+            TokenTree::Ident(ref name) => name.clone(),
+            ref token => return error(token.span(), String::from("expected an instruction or label")),
+        };
+        let text = name.to_string();
+        i += 1;
+        if tokens.get(i).is_some_and(|token| is_punct(token, ':')) {
+            if labels.insert(text.clone(), name.span()).is_some() {
+                return error(name.span(), format!("label '{}' defined twice", text));
+            }
+            body.extend(code(&format!("builder.label({:?});", text)));
+            i += 1;
+            continue;
+        }
+        let kinds = match operands(&text) {
+            Some(kinds) => kinds,
+            None => return error(name.span(), format!("unknown instruction '{}'", text)),
+        };
+        let push_bit = tokens.get(i).is_some_and(|token| is_punct(token, '.'));
+        if push_bit {
+            i += 1;
+        }
+        let mut values = TokenStream::new();
+        for (n, &kind) in kinds.iter().enumerate() {
+            if n > 0 {
+                match tokens.get(i) {
+                    Some(token) if is_punct(token, ',') => i += 1,
+                    _ => return error(name.span(), format!("{} takes {} operands", text, kinds.len())),
+                }
+            }
+            let operand = match tokens.get(i) {
+                Some(token) => token,
+                None => return error(name.span(), format!("{} is missing an operand", text)),
+            };
+            i += 1;
+            values.extend(operand_value(krate, &text, kind, operand, &mut branches)?);
+            values.extend(code(","));
+        }
+        let mut args = code(&format!("{:?}, {}, &", text, push_bit));
+        args.extend(Some(TokenTree::Group(Group::new(Delimiter::Bracket, values))));
+        body.extend(code("builder.insn"));
+        body.extend(parens(args));
+        body.extend(code(";"));
+    }
+    for (label, span) in branches {
+        if !labels.contains_key(&label) {
+            return error(span, format!("unknown label '{}'", label));
+        }
+    }
+    body.extend(code("match builder.finish(0x10) { Ok(script) => script, Err(e) => panic!(\"msc!: {}\", e) }"));
+    Ok(TokenTree::Group(Group::new(Delimiter::Brace, body)).into())
+}
+
+fn operand_value(
+    krate: &TokenStream,
+    name: &str,
+    kind: Kind,
+    operand: &TokenTree,
+    branches: &mut Vec<(String, Span)>,
+) -> Result<TokenStream, Error> {
+    let max = match kind {
+        Kind::Value(max) => max,
+        Kind::Target => 0xFFFF_FFFF,
+    };
+    let mut value = path(krate, "Operand::Value");
+    match *operand {
+        TokenTree::Literal(ref literal) => {
+            let number = match parse_int(&literal.to_string()) {
+                Some(number) => number,
+                None => return error(literal.span(), format!("{} takes integer operands", name)),
+            };
+            if number > max as u64 {
+                return error(literal.span(), format!(
+                    "0x{:X} doesn't fit in a {}-bit operand of {}", number, bits(max), name
+                ));
+            }
+            value.extend(parens(TokenTree::Literal(Literal::u32_suffixed(number as u32)).into()));
+        }
+        TokenTree::Ident(ref label) => {
+            if let Kind::Value(_) = kind {
+                return error(label.span(), format!(
+                    "{} can't take label '{}', msc! only resolves branch targets", name, label
+                ));
+            }
+            branches.push((label.to_string(), label.span()));
+            let mut value = path(krate, "Operand::Label");
+            value.extend(parens(code(&format!("{:?}", label.to_string()))));
+            return Ok(value);
+        }
+        // Narrow fields take constant expressions, so they can be range checked here
+        TokenTree::Group(ref group) if group.delimiter() == Delimiter::Parenthesis && max < 0xFFFF_FFFF => {
+            let span = group.span();
+            let mut check = respan(code("let value: u32 ="), span);
+            check.extend(group.stream());
+            check.extend(respan(code(&format!(
+                "; assert!(value <= 0x{:X}, \"msc!: {} operand doesn't fit in {} bits\"); value",
+                max, name, bits(max)
+            )), span));
+            let mut block = Group::new(Delimiter::Brace, check);
+            block.set_span(span);
+            let mut constant = respan(code("const"), span);
+            constant.extend(Some(TokenTree::Group(block)));
+            value.extend(parens(constant));
+        }
+        TokenTree::Group(ref group) if group.delimiter() == Delimiter::Parenthesis => {
+            value.extend(Some(operand.clone()));
+        }
+        ref token => return error(token.span(), format!(
+            "expected an integer, label or parenthesized expression for {}", name
+        )),
+    }
+    Ok(value)
+}
+
+fn bits(max: u32) -> u32 {
+    32 - max.leading_zeros()
+}
+
+/// Integer literal such as `10`, `0x2F` or `0x10_u32`
+fn parse_int(text: &str) -> Option<u64> {
+    let text = text.replace('_', "");
+    let (digits, radix) = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None => (&text[..], 10),
+    };
+    let end = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+    let (digits, suffix) = digits.split_at(end);
+    if digits.is_empty() || !(suffix.is_empty() || suffix.starts_with('u') || suffix.starts_with('i')) {
+        return None;
+    }
+    u64::from_str_radix(digits, radix).ok()
+}
+
+fn is_punct(token: &TokenTree, c: char) -> bool {
+    match *token {
+        TokenTree::Punct(ref punct) => punct.as_char() == c,
+        _ => false,
+    }
+}
+
+fn code(text: &str) -> TokenStream {
+    text.parse().expect("msc!: generated invalid tokens")
+}
+
+// `$crate::<rest>`
+fn path(krate: &TokenStream, rest: &str) -> TokenStream {
+    let mut tokens = krate.clone();
+    tokens.extend(code("::"));
+    tokens.extend(code(rest));
+    tokens
+}
+
+fn parens(tokens: TokenStream) -> Option<TokenTree> {
+    Some(TokenTree::Group(Group::new(Delimiter::Parenthesis, tokens)))
+}
+
+// Point generated code at `span` so errors from it land on the user's tokens
+fn respan(tokens: TokenStream, span: Span) -> TokenStream {
+    tokens
+        .into_iter()
+        .map(|mut token| {
+            if let TokenTree::Group(ref group) = token {
+                let mut inner = Group::new(group.delimiter(), respan(group.stream(), span));
+                inner.set_span(span);
+                token = TokenTree::Group(inner);
+            } else {
+                token.set_span(span);
+            }
+            token
+        })
+        .collect()
+}
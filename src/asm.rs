@@ -0,0 +1,286 @@
+use super::{Cmd, Command, MscsbFile, Platform, Script};
+use super::error::{Error, Result};
+use super::sys_catalog::parse_number;
+use std::collections::HashMap;
+
+// Scripts are laid out the same way `MscsbFile::write` does
+const SCRIPT_DATA_START: u32 = 0x10;
+
+struct Insn<'a> {
+    line: usize,
+    name: &'a str,
+    push_bit: bool,
+    operands: Vec<&'a str>,
+    position: u32,
+}
+
+struct PendingScript<'a> {
+    name: Option<&'a str>,
+    start: u32,
+    labels: HashMap<&'a str, u32>,
+    insns: Vec<Insn<'a>>,
+}
+
+/// Parse assembly text in the format produced by `Disassembler` into an `MscsbFile`.
+///
+/// Labels starting with `loc_` are local to the script they're in, any other label
+/// starts a new script. `PushInt` and branch operands can name either kind of label.
+pub fn assemble(text: &str) -> Result<MscsbFile> {
+    let mut scripts: Vec<PendingScript> = vec![];
+    let mut strings = vec![];
+    let mut entrypoint = None;
+    let mut platform = Platform::default();
+    let mut position = SCRIPT_DATA_START;
+
+    for (number, raw) in text.lines().enumerate() {
+        let line = number + 1;
+        let error = |message: String| Error::Parse { line, message };
+        let text = strip_comment(raw).trim();
+        if text.is_empty() {
+            continue;
+        }
+        if let Some(rest) = text.strip_prefix(".string") {
+            strings.push(parse_string(rest.trim()).ok_or_else(|| error(format!("bad string literal {}", rest.trim())))?);
+        } else if let Some(rest) = text.strip_prefix(".entrypoint") {
+            entrypoint = Some((line, rest.trim()));
+        } else if let Some(rest) = text.strip_prefix(".platform") {
+            platform = rest.trim().parse().map_err(|e: Error| error(e.to_string()))?;
+        } else if let Some(label) = text.strip_suffix(':') {
+            let label = label.trim();
+            if label.is_empty() || label.contains(char::is_whitespace) {
+                return Err(error(format!("bad label '{}'", label)));
+            }
+            if is_local(label) {
+                let script = match scripts.last_mut() {
+                    Some(script) => script,
+                    None => return Err(error(format!("label '{}' is outside of a script", label))),
+                };
+                if script.labels.insert(label, position).is_some() {
+                    return Err(error(format!("label '{}' defined twice", label)));
+                }
+            } else {
+                if scripts.iter().any(|script| script.name == Some(label)) {
+                    return Err(error(format!("script '{}' defined twice", label)));
+                }
+                scripts.push(PendingScript { name: Some(label), start: position, labels: HashMap::new(), insns: vec![] });
+            }
+        } else {
+            let (mnemonic, operands) = match text.find(char::is_whitespace) {
+                Some(split) => (&text[..split], text[split..].trim()),
+                None => (text, ""),
+            };
+            let (name, push_bit) = match mnemonic.strip_suffix('.') {
+                Some(name) => (name, true),
+                None => (mnemonic, false),
+            };
+            let operands: Vec<&str> = if operands.is_empty() {
+                vec![]
+            } else {
+                operands.split(',').map(str::trim).collect()
+            };
+            // Operands don't change the size, so zeros are enough to lay things out
+            let size = Cmd::from_parts(name, &vec![0; operands.len()])
+                .ok_or_else(|| error(format!("unknown instruction '{}' with {} operands", name, operands.len())))?
+                .size();
+            if scripts.is_empty() {
+                scripts.push(PendingScript { name: None, start: position, labels: HashMap::new(), insns: vec![] });
+            }
+            scripts.last_mut().unwrap().insns.push(Insn { line, name, push_bit, operands, position });
+            position += size;
+        }
+    }
+
+    let script_starts: HashMap<&str, u32> =
+        scripts.iter()
+            .filter_map(|script| script.name.map(|name| (name, script.start)))
+            .collect();
+    let mut ends: Vec<u32> = scripts.iter().skip(1).map(|script| script.start).collect();
+    ends.push(position);
+
+    let mut assembled = Vec::with_capacity(scripts.len());
+    for (script, end) in scripts.iter().zip(ends) {
+        let mut commands = Vec::with_capacity(script.insns.len());
+        for insn in script.insns.iter() {
+            let mut values = Vec::with_capacity(insn.operands.len());
+            for operand in insn.operands.iter() {
+                let value = parse_number(operand)
+                    .or_else(|| parse_negative(operand))
+                    .or_else(|| script.labels.get(operand).cloned())
+                    .or_else(|| script_starts.get(operand).cloned())
+                    .ok_or_else(|| Error::Parse {
+                        line: insn.line,
+                        message: format!("unknown label or bad number '{}'", operand),
+                    })?;
+                values.push(value);
+            }
+            let cmd = Cmd::from_parts(insn.name, &values).ok_or_else(|| Error::Parse {
+                line: insn.line,
+                message: format!("operand out of range for {}", insn.name),
+            })?;
+            commands.push(Command { cmd, push_bit: insn.push_bit, position: insn.position });
+        }
+        assembled.push(Script { commands, bounds: (script.start, end) });
+    }
+
+    let entrypoint = match entrypoint {
+        Some((line, value)) => parse_number(value)
+            .or_else(|| script_starts.get(value).cloned())
+            .ok_or_else(|| Error::Parse { line, message: format!("unknown entrypoint '{}'", value) })?,
+        None => SCRIPT_DATA_START,
+    };
+
+    Ok(MscsbFile { scripts: assembled, strings, entrypoint, platform })
+}
+
+/// Assemble the body of a single script starting at `start`
+pub fn assemble_script(text: &str, start: u32) -> Result<Script> {
+    let mut file = assemble(text)?;
+    if file.scripts.len() > 1 {
+        return Err(Error::Format(String::from("expected a single script")));
+    }
+    let mut script = file.scripts.pop().unwrap_or(Script { commands: vec![], bounds: (SCRIPT_DATA_START, SCRIPT_DATA_START) });
+    script.relocate(start);
+    Ok(script)
+}
+
+impl MscsbFile {
+    /// Parse assembly text, see `assemble`
+    pub fn assemble(text: &str) -> Result<MscsbFile> {
+        assemble(text)
+    }
+}
+
+impl Script {
+    /// Move the script so it starts at `start`, fixing up positions and branch targets
+    /// that point inside the script
+    pub fn relocate(&mut self, start: u32) {
+        let (old_start, old_end) = self.bounds;
+        let delta = start.wrapping_sub(old_start);
+        for command in self.commands.iter_mut() {
+            command.position = command.position.wrapping_add(delta);
+            match command.cmd {
+                Cmd::Jump { ref mut loc } | Cmd::Jump5 { ref mut loc } | Cmd::Try { ref mut loc } |
+                Cmd::If { ref mut loc } | Cmd::IfNot { ref mut loc } | Cmd::Else { ref mut loc }
+                    if *loc >= old_start && *loc <= old_end => {
+                    *loc = loc.wrapping_add(delta);
+                }
+                _ => {}
+            }
+        }
+        self.bounds = (start, old_end.wrapping_add(delta));
+    }
+}
+
+fn is_local(label: &str) -> bool {
+    label.starts_with("loc_")
+}
+
+fn parse_negative(text: &str) -> Option<u32> {
+    let value: i32 = text.strip_prefix('-')?.parse::<i32>().ok()?;
+    Some(value.wrapping_neg() as u32)
+}
+
+// Cut a trailing `; comment`, leaving semicolons inside string literals alone
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// Parse a string literal as written by `{:?}`
+fn parse_string(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            '0' => out.push('\0'),
+            '\\' => out.push('\\'),
+            '"' => out.push('"'),
+            '\'' => out.push('\''),
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let end = rest.find('}')?;
+                out.push(std::char::from_u32(u32::from_str_radix(&rest[..end], 16).ok()?)?);
+                chars = rest[end + 1..].chars();
+            }
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = r#"
+.entrypoint main
+.string "count: %d; done\n"
+
+main:
+    Begin 0, 1
+    PushShort. 0x0
+    SetVar 0, 0x0
+loc_top:
+    PushVar. 0, 0x0
+    PushShort. 10
+    LessThan.
+    IfNot loc_end
+    PushInt. helper
+    CallFunc. 0            ; result stays on the stack
+    Pop
+    IncI 0, 0x0
+    Jump loc_top
+loc_end:
+    End
+
+helper:
+    Begin 0, 0
+    PushInt. 0xFFFFFFFF
+    Sys. 1, 0x2F
+    Return6
+"#;
+
+    #[test]
+    fn test_assemble_round_trip() {
+        let file = assemble(SOURCE).unwrap();
+        assert_eq!(file.scripts.len(), 2);
+        assert_eq!(file.strings, vec![String::from("count: %d; done\n")]);
+        assert_eq!(file.entrypoint, 0x10);
+        assert_eq!(file.call_graph().edges(), vec![(0, 1)]);
+
+        let mut bytes = vec![];
+        file.write(&mut bytes);
+        let parsed = MscsbFile::from_bytes(&bytes).unwrap();
+        let text = parsed.disassemble();
+        assert_eq!(text, file.disassemble());
+        assert_eq!(assemble(&text).unwrap().disassemble(), text);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        match assemble("main:\n    Jump loc_nowhere\n") {
+            Err(Error::Parse { line: 2, .. }) => {}
+            other => panic!("expected a parse error on line 2, got {:?}", other.map(|_| ())),
+        }
+        assert!(assemble("main:\n    PushShort. 0x10000\n").is_err());
+        assert!(assemble("main:\n    Begin 0\n").is_err());
+    }
+}
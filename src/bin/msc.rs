@@ -0,0 +1,355 @@
+extern crate msc;
+extern crate serde_json;
+
+use msc::{Disassembler, MscsbFile, MscsbHeader, Pattern, Platform, ScriptChange, SearchMatch, Severity, SymbolMap, SysCatalog, Validator};
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+const USAGE: &str = "\
+usage: msc <command> [options]
+
+commands:
+    info <file>                 header, script count and string table
+    disasm <file>               text listing
+    asm <source> -o <file>      assemble a text listing into an mscsb
+    convert <file> -o <file>    rewrite a file for the platform given with --platform
+    validate <file>             report problems, exits 1 if there are errors
+    diff <old> <new>            compare two files, exits 1 if they differ
+    dump-strings <file>         print the string table
+    search <query> <path>       find instruction patterns in a file or directory
+
+options:
+    --json                      machine-readable output
+    -o, --output <file>         write output to a file instead of stdout
+    --symbols <file>            symbol map (TOML or JSON) used for names
+    --catalog <file>            sys call catalog layered over the built-in one
+    -U, --context <n>           lines of context for diff (default 3)
+    --platform <wiiu|3ds>       platform to write for with asm and convert";
+
+#[derive(Default)]
+struct Options {
+    json: bool,
+    output: Option<String>,
+    symbols: Option<String>,
+    catalog: Option<String>,
+    context: Option<usize>,
+    platform: Option<Platform>,
+    args: Vec<String>,
+}
+
+type CliResult = Result<i32, String>;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let command = match args.next() {
+        Some(command) => command,
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+    let code = match parse_options(args).and_then(|options| run(&command, options)) {
+        Ok(code) => code,
+        Err(message) => {
+            eprintln!("msc: {}", message);
+            2
+        }
+    };
+    exit(code);
+}
+
+fn parse_options<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match &arg[..] {
+            "--json" => options.json = true,
+            "-o" | "--output" => options.output = Some(value(&arg)?),
+            "--symbols" => options.symbols = Some(value(&arg)?),
+            "--catalog" => options.catalog = Some(value(&arg)?),
+            "-U" | "--context" => {
+                let n = value(&arg)?;
+                options.context = Some(n.parse().map_err(|_| format!("bad context '{}'", n))?);
+            }
+            "--platform" => {
+                let platform = value(&arg)?;
+                options.platform = Some(platform.parse().map_err(|e| format!("{}", e))?);
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => return Err(format!("unknown option '{}'", arg)),
+            _ => options.args.push(arg),
+        }
+    }
+    Ok(options)
+}
+
+fn run(command: &str, options: Options) -> CliResult {
+    let expected = match command {
+        "info" | "disasm" | "asm" | "convert" | "validate" | "dump-strings" => 1,
+        "diff" | "search" => 2,
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            return Ok(0);
+        }
+        _ => return Err(format!("unknown command '{}'\n\n{}", command, USAGE)),
+    };
+    if options.args.len() != expected {
+        return Err(format!("{} takes {} file argument(s)\n\n{}", command, expected, USAGE));
+    }
+    match command {
+        "info" => info(&options),
+        "disasm" => disasm(&options),
+        "asm" => asm(&options),
+        "convert" => convert(&options),
+        "validate" => validate(&options),
+        "diff" => diff(&options),
+        "search" => search(&options),
+        _ => dump_strings(&options),
+    }
+}
+
+fn load(path: &str) -> Result<MscsbFile, String> {
+    load_bytes(path).map(|(_, file)| file)
+}
+
+// The parsed file along with the bytes it was read from
+fn load_bytes(path: &str) -> Result<(Vec<u8>, MscsbFile), String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let file = MscsbFile::from_bytes(&bytes).ok_or_else(|| format!("{}: not a valid mscsb file", path))?;
+    Ok((bytes, file))
+}
+
+fn symbols(options: &Options) -> Result<Option<SymbolMap>, String> {
+    match options.symbols {
+        Some(ref path) => SymbolMap::load(path).map(Some).map_err(|e| format!("{}: {}", path, e)),
+        None => Ok(None),
+    }
+}
+
+fn catalog(options: &Options, symbols: Option<&SymbolMap>) -> Result<SysCatalog, String> {
+    let mut catalog = match options.catalog {
+        Some(ref path) => SysCatalog::builtin_with(path).map_err(|e| format!("{}: {}", path, e))?,
+        None => SysCatalog::builtin(),
+    };
+    if let Some(symbols) = symbols {
+        symbols.apply_to_catalog(&mut catalog);
+    }
+    Ok(catalog)
+}
+
+fn emit(options: &Options, text: String) -> Result<(), String> {
+    match options.output {
+        Some(ref path) => fs::write(path, text).map_err(|e| format!("{}: {}", path, e)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+fn emit_json(options: &Options, value: Value) -> Result<(), String> {
+    emit(options, serde_json::to_string_pretty(&value).unwrap() + "\n")
+}
+
+fn info(options: &Options) -> CliResult {
+    let (bytes, file) = load_bytes(&options.args[0])?;
+    let header = MscsbHeader::peek(&bytes)
+        .ok_or_else(|| format!("{}: not a valid mscsb file", options.args[0]))?;
+    let entry = file.get_script_from_loc(file.entrypoint);
+    let bytecode: u32 = file.iter().map(|s| s.bounds.1 - s.bounds.0).sum();
+    if options.json {
+        emit_json(options, json!({
+            "platform": file.platform().to_string(),
+            "header": {
+                "script_data_size": header.script_data_size,
+                "entrypoint": header.entrypoint,
+                "script_count": header.script_count,
+                "unk": header.unk,
+                "string_size": header.string_size,
+                "string_count": header.string_count,
+            },
+            "script_count": file.scripts.len(),
+            "entrypoint": file.entrypoint,
+            "entrypoint_script": entry,
+            "bytecode_size": bytecode,
+            "scripts": file.iter().enumerate().map(|(i, s)| json!({
+                "index": i,
+                "start": s.bounds.0,
+                "end": s.bounds.1,
+                "commands": s.commands.len(),
+            })).collect::<Vec<_>>(),
+            "strings": file.strings,
+        }))?;
+    } else {
+        let mut text = format!("platform:    {}\n", file.platform());
+        text += &format!("scripts:     {}\n", file.scripts.len());
+        match entry {
+            Some(index) => text += &format!("entrypoint:  script_{} (0x{:X})\n", index, file.entrypoint),
+            None => text += &format!("entrypoint:  0x{:X}\n", file.entrypoint),
+        }
+        text += &format!("bytecode:    0x{:X} bytes\n", bytecode);
+        text += &format!("script data: 0x{:X} bytes\n", header.script_data_size);
+        text += &format!("unk:         0x{:X}\n", header.unk);
+        text += &format!("strings:     {} of 0x{:X} bytes each\n", header.string_count, header.string_size);
+        for (i, string) in file.strings.iter().enumerate() {
+            text += &format!("    {:>4}: {:?}\n", i, string);
+        }
+        emit(options, text)?;
+    }
+    Ok(0)
+}
+
+fn disasm(options: &Options) -> CliResult {
+    let file = load(&options.args[0])?;
+    let symbols = symbols(options)?;
+    let catalog = catalog(options, symbols.as_ref())?;
+    let mut disassembler = Disassembler::new(&file).with_catalog(&catalog);
+    if let Some(ref symbols) = symbols {
+        disassembler = disassembler.with_symbols(symbols);
+    }
+    if options.json {
+        emit_json(options, json!({
+            "entrypoint": file.entrypoint,
+            "strings": file.strings,
+            "scripts": file.iter().enumerate().map(|(i, script)| json!({
+                "index": i,
+                "name": disassembler.script_label(i),
+                "start": script.bounds.0,
+                "end": script.bounds.1,
+                "commands": script.iter().map(|command| json!({
+                    "position": command.position,
+                    "text": disassembler.command(i, command),
+                })).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+        }))?;
+    } else {
+        emit(options, disassembler.to_string())?;
+    }
+    Ok(0)
+}
+
+fn asm(options: &Options) -> CliResult {
+    let path = &options.args[0];
+    let output = options.output.as_ref().ok_or("asm needs an output file (-o)")?;
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut file = MscsbFile::assemble(&text).map_err(|e| format!("{}: {}", path, e))?;
+    if let Some(platform) = options.platform {
+        file.set_platform(platform);
+    }
+    let mut bytes = vec![];
+    file.write(&mut bytes);
+    fs::write(output, &bytes).map_err(|e| format!("{}: {}", output, e))?;
+    if options.json {
+        // `-o` names the assembled file, so the summary goes to stdout
+        let stdout = Options::default();
+        emit_json(&stdout, json!({ "scripts": file.scripts.len(), "bytes": bytes.len() }))?;
+    }
+    Ok(0)
+}
+
+fn convert(options: &Options) -> CliResult {
+    let path = &options.args[0];
+    let output = options.output.as_ref().ok_or("convert needs an output file (-o)")?;
+    let platform = options.platform.ok_or("convert needs a target platform (--platform)")?;
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let converted = MscsbFile::convert(&bytes, platform).ok_or_else(|| format!("{}: not a valid mscsb file", path))?;
+    fs::write(output, &converted).map_err(|e| format!("{}: {}", output, e))?;
+    Ok(0)
+}
+
+fn validate(options: &Options) -> CliResult {
+    let file = load(&options.args[0])?;
+    let symbols = symbols(options)?;
+    let catalog = catalog(options, symbols.as_ref())?;
+    let diagnostics = Validator::new(&catalog).validate(&file);
+    if options.json {
+        emit_json(options, Value::Array(diagnostics.iter().map(|d| json!({
+            "severity": match d.severity {
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            },
+            "script": d.script,
+            "position": d.position,
+            "message": d.message,
+        })).collect()))?;
+    } else {
+        emit(options, diagnostics.iter().map(|d| format!("{}\n", d)).collect())?;
+    }
+    let failed = diagnostics.iter().any(|d| d.severity == Severity::Error);
+    Ok(if failed { 1 } else { 0 })
+}
+
+fn diff(options: &Options) -> CliResult {
+    let old = load(&options.args[0])?;
+    let new = load(&options.args[1])?;
+    let diff = old.diff(&new);
+    if options.json {
+        emit_json(options, Value::Array(diff.changes.iter().filter_map(|change| match *change {
+            ScriptChange::Unchanged { .. } => None,
+            ScriptChange::Added { new } => Some(json!({ "change": "added", "new": new })),
+            ScriptChange::Removed { old } => Some(json!({ "change": "removed", "old": old })),
+            ScriptChange::Modified { old, new, similarity, ref ops } => Some(json!({
+                "change": "modified",
+                "old": old,
+                "new": new,
+                "similarity": similarity,
+                "ops": ops.iter().map(|op| match *op {
+                    msc::DiffOp::Equal { old, new } => json!({ "op": "equal", "old": old, "new": new }),
+                    msc::DiffOp::Delete { old } => json!({ "op": "delete", "old": old }),
+                    msc::DiffOp::Insert { new } => json!({ "op": "insert", "new": new }),
+                }).collect::<Vec<_>>(),
+            })),
+        }).collect()))?;
+    } else {
+        emit(options, diff.to_unified(&old, &new, options.context.unwrap_or(3)))?;
+    }
+    Ok(if diff.is_empty() { 0 } else { 1 })
+}
+
+fn dump_strings(options: &Options) -> CliResult {
+    let file = load(&options.args[0])?;
+    if options.json {
+        emit_json(options, json!(file.strings))?;
+    } else {
+        emit(options, file.strings.iter().enumerate().map(|(i, s)| format!("{}: {:?}\n", i, s)).collect())?;
+    }
+    Ok(0)
+}
+
+fn search(options: &Options) -> CliResult {
+    let pattern = Pattern::parse(&options.args[0]).map_err(|e| e.to_string())?;
+    let path = Path::new(&options.args[1]);
+    let found: Vec<(PathBuf, SearchMatch)> = if path.is_dir() {
+        let search = pattern.find_in_dir(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (path, error) in search.errors.iter() {
+            eprintln!("msc: skipping {}: {}", path.display(), error);
+        }
+        search.matches
+    } else {
+        let file = load(&options.args[1])?;
+        pattern.find(&file).into_iter().map(|m| (path.to_path_buf(), m)).collect()
+    };
+    if options.json {
+        emit_json(options, Value::Array(found.iter().map(|(path, m)| json!({
+            "file": path.display().to_string(),
+            "script": m.script,
+            "command": m.command,
+            "position": m.position,
+            "len": m.len,
+            "captures": m.captures,
+        })).collect()))?;
+    } else {
+        let mut text = String::new();
+        for (path, m) in found.iter() {
+            text += &format!("{}: script_{} 0x{:X} ({} commands)", path.display(), m.script, m.position, m.len);
+            for (name, value) in m.captures.iter() {
+                text += &format!(" ${}=0x{:X}", name, value);
+            }
+            text.push('\n');
+        }
+        emit(options, text)?;
+    }
+    Ok(if found.is_empty() { 1 } else { 0 })
+}
@@ -0,0 +1,335 @@
+use super::{Cmd, Command, MscsbFile, Platform, Script};
+use super::error::{Error, Result};
+use std::collections::HashMap;
+
+// Scripts are laid out the same way `MscsbFile::write` does
+const SCRIPT_DATA_START: u32 = 0x10;
+
+// Operand that can't be filled in until the script is placed
+enum Fixup {
+    Label(String),
+    Script(String),
+}
+
+/// Operand for `ScriptBuilder::insn`
+#[derive(Debug, Copy, Clone)]
+pub enum Operand<'a> {
+    Value(u32),
+    /// A label for branches, or a script name for `PushInt`
+    Label(&'a str),
+}
+
+/// Builds a `Script` one command at a time, computing positions and resolving
+/// labels when finished.
+#[derive(Default)]
+pub struct ScriptBuilder {
+    commands: Vec<(Cmd, bool)>,
+    fixups: HashMap<usize, Fixup>,
+    labels: HashMap<String, usize>,
+    errors: Vec<String>,
+}
+
+impl ScriptBuilder {
+    pub fn new() -> ScriptBuilder {
+        ScriptBuilder::default()
+    }
+
+    /// Append a command, setting the push bit if it produces a value
+    pub fn cmd(&mut self, cmd: Cmd) -> &mut ScriptBuilder {
+        self.commands.push((cmd, cmd.produces_value()));
+        self
+    }
+
+    /// Append a command with an explicit push bit
+    pub fn cmd_with_push_bit(&mut self, cmd: Cmd, push_bit: bool) -> &mut ScriptBuilder {
+        self.commands.push((cmd, push_bit));
+        self
+    }
+
+    /// Append a command by mnemonic, the same way the assembler reads a line
+    pub fn insn(&mut self, name: &str, push_bit: bool, operands: &[Operand]) -> &mut ScriptBuilder {
+        let values: Vec<u32> = operands.iter()
+            .map(|operand| match *operand {
+                Operand::Value(value) => value,
+                Operand::Label(_) => 0,
+            })
+            .collect();
+        let cmd = match Cmd::from_parts(name, &values) {
+            Some(cmd) => cmd,
+            None => {
+                self.errors.push(format!("bad instruction {} with operands {:?}", name, operands));
+                return self;
+            }
+        };
+        for operand in operands.iter() {
+            if let Operand::Label(label) = *operand {
+                let fixup = match cmd {
+                    Cmd::PushInt { .. } => Fixup::Script(String::from(label)),
+                    _ if cmd.branch_target().is_some() => Fixup::Label(String::from(label)),
+                    _ => {
+                        self.errors.push(format!("{} can't take label '{}'", name, label));
+                        return self;
+                    }
+                };
+                self.fixups.insert(self.commands.len(), fixup);
+            }
+        }
+        self.cmd_with_push_bit(cmd, push_bit)
+    }
+
+    /// Clear the push bit of the last command so its result is discarded
+    pub fn discard(&mut self) -> &mut ScriptBuilder {
+        if let Some(last) = self.commands.last_mut() {
+            last.1 = false;
+        }
+        self
+    }
+
+    /// Mark the position of the next command
+    pub fn label(&mut self, name: &str) -> &mut ScriptBuilder {
+        if self.labels.insert(String::from(name), self.commands.len()).is_some() {
+            self.errors.push(format!("label '{}' defined twice", name));
+        }
+        self
+    }
+
+    pub fn begin(&mut self, arg_count: u16, var_count: u16) -> &mut ScriptBuilder {
+        self.cmd(Cmd::Begin { arg_count, var_count })
+    }
+
+    pub fn end(&mut self) -> &mut ScriptBuilder {
+        self.cmd(Cmd::End)
+    }
+
+    /// Return the value on top of the stack
+    pub fn ret(&mut self) -> &mut ScriptBuilder {
+        self.cmd(Cmd::Return6)
+    }
+
+    pub fn push_int(&mut self, val: u32) -> &mut ScriptBuilder {
+        self.cmd(Cmd::PushInt { val })
+    }
+
+    pub fn push_short(&mut self, val: u16) -> &mut ScriptBuilder {
+        self.cmd(Cmd::PushShort { val })
+    }
+
+    pub fn push_float(&mut self, val: f32) -> &mut ScriptBuilder {
+        self.push_int(val.to_bits())
+    }
+
+    /// Push the address of a script, resolved by `MscsbFileBuilder`
+    pub fn push_script(&mut self, name: &str) -> &mut ScriptBuilder {
+        self.fixups.insert(self.commands.len(), Fixup::Script(String::from(name)));
+        self.push_int(0)
+    }
+
+    pub fn push_local(&mut self, var_num: u16) -> &mut ScriptBuilder {
+        self.cmd(Cmd::PushVar { var_type: 0, var_num })
+    }
+
+    pub fn push_global(&mut self, var_num: u16) -> &mut ScriptBuilder {
+        self.cmd(Cmd::PushVar { var_type: 1, var_num })
+    }
+
+    pub fn set_local(&mut self, var_num: u16) -> &mut ScriptBuilder {
+        self.cmd(Cmd::SetVar { var_type: 0, var_num })
+    }
+
+    pub fn set_global(&mut self, var_num: u16) -> &mut ScriptBuilder {
+        self.cmd(Cmd::SetVar { var_type: 1, var_num })
+    }
+
+    pub fn inc_local(&mut self, var_num: u16) -> &mut ScriptBuilder {
+        self.cmd(Cmd::IncI { var_type: 0, var_num })
+    }
+
+    pub fn dec_local(&mut self, var_num: u16) -> &mut ScriptBuilder {
+        self.cmd(Cmd::DecI { var_type: 0, var_num })
+    }
+
+    pub fn jump(&mut self, label: &str) -> &mut ScriptBuilder {
+        self.branch(Cmd::Jump { loc: 0 }, label)
+    }
+
+    pub fn if_(&mut self, label: &str) -> &mut ScriptBuilder {
+        self.branch(Cmd::If { loc: 0 }, label)
+    }
+
+    pub fn if_not(&mut self, label: &str) -> &mut ScriptBuilder {
+        self.branch(Cmd::IfNot { loc: 0 }, label)
+    }
+
+    pub fn else_(&mut self, label: &str) -> &mut ScriptBuilder {
+        self.branch(Cmd::Else { loc: 0 }, label)
+    }
+
+    pub fn try_(&mut self, label: &str) -> &mut ScriptBuilder {
+        self.branch(Cmd::Try { loc: 0 }, label)
+    }
+
+    pub fn sys(&mut self, sys_num: u8, arg_count: u8) -> &mut ScriptBuilder {
+        self.cmd(Cmd::Sys { arg_count, sys_num })
+    }
+
+    /// Call the script address pushed below `arg_count` arguments
+    pub fn call(&mut self, arg_count: u8) -> &mut ScriptBuilder {
+        self.cmd(Cmd::CallFunc { arg_count })
+    }
+
+    pub fn printf(&mut self, arg_count: u8) -> &mut ScriptBuilder {
+        self.cmd(Cmd::PrintF { arg_count })
+    }
+
+    pub fn pop(&mut self) -> &mut ScriptBuilder {
+        self.cmd(Cmd::Pop)
+    }
+
+    fn branch(&mut self, cmd: Cmd, label: &str) -> &mut ScriptBuilder {
+        self.fixups.insert(self.commands.len(), Fixup::Label(String::from(label)));
+        self.cmd(cmd)
+    }
+
+    /// Size of the script in bytes
+    pub fn size(&self) -> u32 {
+        self.commands.iter().map(|&(cmd, _)| cmd.size()).sum()
+    }
+
+    /// Lay the script out starting at `start`. Fails on unknown labels or any
+    /// reference to another script, use `MscsbFileBuilder` for those.
+    pub fn finish(&self, start: u32) -> Result<Script> {
+        self.place(start, &HashMap::new())
+    }
+
+    fn place(&self, start: u32, scripts: &HashMap<String, u32>) -> Result<Script> {
+        if let Some(error) = self.errors.first() {
+            return Err(Error::Format(error.clone()));
+        }
+        let mut positions = Vec::with_capacity(self.commands.len() + 1);
+        let mut position = start;
+        for &(cmd, _) in self.commands.iter() {
+            positions.push(position);
+            position += cmd.size();
+        }
+        positions.push(position);
+
+        let mut commands = Vec::with_capacity(self.commands.len());
+        for (i, &(mut cmd, push_bit)) in self.commands.iter().enumerate() {
+            match self.fixups.get(&i) {
+                Some(Fixup::Label(label)) => {
+                    let target = self.labels.get(label)
+                        .ok_or_else(|| Error::Format(format!("unknown label '{}'", label)))?;
+                    cmd.set_branch_target(positions[*target]);
+                }
+                Some(Fixup::Script(name)) => {
+                    let target = scripts.get(name)
+                        .ok_or_else(|| Error::Format(format!("unknown script '{}'", name)))?;
+                    cmd = Cmd::PushInt { val: *target };
+                }
+                None => {}
+            }
+            commands.push(Command { cmd, push_bit, position: positions[i] });
+        }
+        Ok(Script { commands, bounds: (start, position) })
+    }
+}
+
+/// Assembles named scripts and a string table into an `MscsbFile`, laying
+/// scripts out in the order they were added
+#[derive(Default)]
+pub struct MscsbFileBuilder {
+    scripts: Vec<(String, ScriptBuilder)>,
+    strings: Vec<String>,
+    entrypoint: Option<String>,
+}
+
+impl MscsbFileBuilder {
+    pub fn new() -> MscsbFileBuilder {
+        MscsbFileBuilder::default()
+    }
+
+    pub fn script(&mut self, name: &str, script: ScriptBuilder) -> &mut MscsbFileBuilder {
+        self.scripts.push((String::from(name), script));
+        self
+    }
+
+    /// Add a string if it isn't already in the table, returning its index
+    pub fn string(&mut self, string: &str) -> u32 {
+        match self.strings.iter().position(|s| s == string) {
+            Some(index) => index as u32,
+            None => {
+                self.strings.push(String::from(string));
+                self.strings.len() as u32 - 1
+            }
+        }
+    }
+
+    /// Script to start execution at, defaults to the first one
+    pub fn entrypoint(&mut self, name: &str) -> &mut MscsbFileBuilder {
+        self.entrypoint = Some(String::from(name));
+        self
+    }
+
+    pub fn build(&self) -> Result<MscsbFile> {
+        let mut starts = HashMap::new();
+        let mut position = SCRIPT_DATA_START;
+        for (name, script) in self.scripts.iter() {
+            if starts.insert(name.clone(), position).is_some() {
+                return Err(Error::Format(format!("script '{}' defined twice", name)));
+            }
+            position += script.size();
+        }
+        let scripts = self.scripts.iter()
+            .map(|(name, script)| script.place(starts[name], &starts))
+            .collect::<Result<Vec<_>>>()?;
+        let entrypoint = match self.entrypoint {
+            Some(ref name) => *starts.get(name)
+                .ok_or_else(|| Error::Format(format!("unknown entrypoint '{}'", name)))?,
+            None => SCRIPT_DATA_START,
+        };
+        Ok(MscsbFile { scripts, strings: self.strings.clone(), entrypoint, platform: Platform::default() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let mut builder = MscsbFileBuilder::new();
+        let format = builder.string("x = %d\n");
+        let mut main = ScriptBuilder::new();
+        main.begin(0, 1)
+            .push_short(3).set_local(0)
+            .label("loop")
+            .push_local(0).if_not("done")
+            .push_int(format).push_local(0).printf(2)
+            .dec_local(0)
+            .jump("loop")
+            .label("done")
+            .push_script("helper").call(0).discard()
+            .end();
+        let mut helper = ScriptBuilder::new();
+        helper.begin(0, 0).push_short(1).ret();
+        let file = builder
+            .script("main", main)
+            .script("helper", helper)
+            .entrypoint("main")
+            .build()
+            .unwrap();
+
+        assert_eq!(file.entrypoint, 0x10);
+        assert_eq!(file.scripts[1].bounds.0, file.scripts[0].bounds.1);
+        assert_eq!(file.call_graph().edges(), vec![(0, 1)]);
+        let text = file.disassemble();
+        assert!(text.contains("IfNot loc_"));
+        assert!(text.contains("PushInt. script_1"));
+        assert!(text.contains("    CallFunc 0\n"));
+        assert_eq!(crate::assemble(&text).unwrap().disassemble(), text);
+
+        assert!(ScriptBuilder::new().jump("nowhere").finish(0x10).is_err());
+        assert!(ScriptBuilder::new().push_script("main").finish(0x10).is_err());
+        assert!(ScriptBuilder::new().label("a").label("a").finish(0x10).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+use super::{Cmd, MscsbFile, Script};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CallTarget {
+    /// Call to the script at this index
+    Script(usize),
+    /// Constant address that isn't the start of any script
+    Constant(u32),
+    /// Address computed at runtime
+    Indirect,
+}
+
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    /// Index of the calling script
+    pub caller: usize,
+    /// Index of the `CallFunc` command within the caller
+    pub command: usize,
+    pub position: u32,
+    pub target: CallTarget,
+}
+
+#[derive(Debug, Clone)]
+pub struct CallGraph {
+    pub script_count: usize,
+    pub entrypoint: Option<usize>,
+    pub sites: Vec<CallSite>,
+}
+
+/// The command pushing the constant address the call at `index` jumps to, and that
+/// address. `sources` is `script.operand_sources()`.
+pub(crate) fn address_push(script: &Script, sources: &[Vec<Option<usize>>], index: usize) -> Option<(usize, u32)> {
+    let source = sources[index].first().cloned().flatten()?;
+    match script.commands[source].cmd {
+        Cmd::PushInt { val } => Some((source, val)),
+        Cmd::PushShort { val } => Some((source, val as u32)),
+        _ => None,
+    }
+}
+
+/// Commands of `script` whose constant is used as a call address, by index, with the
+/// address they push. A constant equal to a script's start that never reaches a call is
+/// just a number and isn't included.
+pub(crate) fn address_pushes(script: &Script) -> BTreeMap<usize, u32> {
+    let sources = script.operand_sources();
+    script
+        .commands
+        .iter()
+        .enumerate()
+        .filter(|(_, command)| {
+            matches!(command.cmd, Cmd::CallFunc { .. } | Cmd::CallFunc2 { .. } | Cmd::CallFunc3 { .. })
+        })
+        .filter_map(|(i, _)| address_push(script, &sources, i))
+        .collect()
+}
+
+impl CallGraph {
+    pub fn new(file: &MscsbFile) -> CallGraph {
+        let mut sites = vec![];
+        for (caller, script) in file.scripts.iter().enumerate() {
+            let sources = script.operand_sources();
+            for (i, command) in script.commands.iter().enumerate() {
+                match command.cmd {
+                    Cmd::CallFunc { .. } | Cmd::CallFunc2 { .. } | Cmd::CallFunc3 { .. } => {}
+                    _ => continue,
+                }
+                let target = match address_push(script, &sources, i) {
+                    Some((_, address)) => resolve(file, address),
+                    None => CallTarget::Indirect,
+                };
+                sites.push(CallSite {
+                    caller,
+                    command: i,
+                    position: command.position,
+                    target,
+                });
+            }
+        }
+        CallGraph {
+            script_count: file.scripts.len(),
+            entrypoint: file.get_script_from_loc(file.entrypoint),
+            sites,
+        }
+    }
+
+    /// Resolved (caller, callee) pairs, deduplicated
+    pub fn edges(&self) -> Vec<(usize, usize)> {
+        self.sites
+            .iter()
+            .filter_map(|site| match site.target {
+                CallTarget::Script(callee) => Some((site.caller, callee)),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    pub fn callees(&self, script: usize) -> Vec<usize> {
+        self.edges()
+            .into_iter()
+            .filter(|&(caller, _)| caller == script)
+            .map(|(_, callee)| callee)
+            .collect()
+    }
+
+    pub fn callers(&self, script: usize) -> Vec<usize> {
+        self.edges()
+            .into_iter()
+            .filter(|&(_, callee)| callee == script)
+            .map(|(caller, _)| caller)
+            .collect()
+    }
+
+    /// Call sites whose target couldn't be resolved to a script
+    pub fn unresolved(&self) -> impl Iterator<Item = &CallSite> {
+        self.sites
+            .iter()
+            .filter(|site| !matches!(site.target, CallTarget::Script(_)))
+    }
+
+    /// Scripts reachable from the entrypoint by resolved calls
+    pub fn reachable(&self) -> BTreeSet<usize> {
+        self.reachable_from(self.entrypoint.iter().cloned())
+    }
+
+    pub fn reachable_from<I: IntoIterator<Item = usize>>(&self, roots: I) -> BTreeSet<usize> {
+        let adjacency = self.adjacency();
+        let mut seen = BTreeSet::new();
+        let mut todo: Vec<usize> = roots.into_iter().collect();
+        while let Some(script) = todo.pop() {
+            if script < self.script_count && seen.insert(script) {
+                todo.extend(adjacency[script].iter().cloned());
+            }
+        }
+        seen
+    }
+
+    /// Scripts that can't be reached from the entrypoint. Scripts only called
+    /// through unresolved call sites end up here too.
+    pub fn unreachable(&self) -> Vec<usize> {
+        let reachable = self.reachable();
+        (0..self.script_count)
+            .filter(|i| !reachable.contains(i))
+            .collect()
+    }
+
+    /// Groups of mutually recursive scripts (including scripts that call themselves)
+    pub fn recursion(&self) -> Vec<Vec<usize>> {
+        let adjacency = self.adjacency();
+        strongly_connected(&adjacency)
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1 || adjacency[component[0]].contains(&component[0])
+            })
+            .collect()
+    }
+
+    /// Render the graph in Graphviz DOT format
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph calls {\n");
+        for i in 0..self.script_count {
+            if Some(i) == self.entrypoint {
+                writeln!(dot, "    script_{} [shape=box];", i).unwrap();
+            } else {
+                writeln!(dot, "    script_{};", i).unwrap();
+            }
+        }
+        for (caller, callee) in self.edges() {
+            writeln!(dot, "    script_{} -> script_{};", caller, callee).unwrap();
+        }
+        for site in self.unresolved() {
+            let label = match site.target {
+                CallTarget::Constant(loc) => format!("0x{:x}", loc),
+                _ => String::from("?"),
+            };
+            writeln!(
+                dot,
+                "    unresolved_{:x} [label=\"{}\", shape=plaintext];\n    script_{} -> unresolved_{:x} [style=dashed];",
+                site.position, label, site.caller, site.position
+            ).unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn adjacency(&self) -> Vec<Vec<usize>> {
+        let mut adjacency = vec![vec![]; self.script_count];
+        for (caller, callee) in self.edges() {
+            adjacency[caller].push(callee);
+        }
+        adjacency
+    }
+}
+
+impl MscsbFile {
+    pub fn call_graph(&self) -> CallGraph {
+        CallGraph::new(self)
+    }
+}
+
+fn resolve(file: &MscsbFile, loc: u32) -> CallTarget {
+    match file.get_script_from_loc(loc) {
+        Some(script) => CallTarget::Script(script),
+        None => CallTarget::Constant(loc),
+    }
+}
+
+// Tarjan's algorithm, iterative so deep call chains can't overflow the stack
+fn strongly_connected(adjacency: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let count = adjacency.len();
+    let mut index = vec![usize::MAX; count];
+    let mut low = vec![0; count];
+    let mut on_stack = vec![false; count];
+    let mut stack = vec![];
+    let mut components = vec![];
+    let mut next_index = 0;
+
+    for root in 0..count {
+        if index[root] != usize::MAX {
+            continue;
+        }
+        let mut work = vec![];
+        index[root] = next_index;
+        low[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        work.push((root, 0));
+        while let Some(&(node, edge)) = work.last() {
+            if let Some(&child) = adjacency[node].get(edge) {
+                work.last_mut().unwrap().1 += 1;
+                if index[child] == usize::MAX {
+                    index[child] = next_index;
+                    low[child] = next_index;
+                    next_index += 1;
+                    stack.push(child);
+                    on_stack[child] = true;
+                    work.push((child, 0));
+                } else if on_stack[child] {
+                    low[node] = low[node].min(index[child]);
+                }
+                continue;
+            }
+            work.pop();
+            if let Some(&(parent, _)) = work.last() {
+                low[parent] = low[parent].min(low[node]);
+            }
+            if low[node] == index[node] {
+                let mut component = vec![];
+                loop {
+                    let member = stack.pop().unwrap();
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                component.sort();
+                components.push(component);
+            }
+        }
+    }
+    components
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::test::script;
+
+    #[test]
+    fn test_call_graph() {
+        let file = MscsbFile::new(
+            vec![
+                script(0x10, &[
+                    Cmd::PushInt { val: 0x20 }, Cmd::PushShort { val: 1 }, Cmd::CallFunc { arg_count: 1 },
+                    Cmd::PushVar { var_type: 0, var_num: 0 }, Cmd::CallFunc { arg_count: 0 },
+                    Cmd::End,
+                ]),
+                script(0x20, &[Cmd::PushInt { val: 0x30 }, Cmd::CallFunc { arg_count: 0 }, Cmd::End]),
+                script(0x30, &[Cmd::PushInt { val: 0x20 }, Cmd::CallFunc2 { arg_count: 0 }, Cmd::End]),
+                script(0x40, &[Cmd::PushInt { val: 0x40 }, Cmd::CallFunc3 { arg_count: 0 }, Cmd::End]),
+            ],
+            vec![],
+            0x10,
+        );
+        let graph = file.call_graph();
+        assert_eq!(graph.edges(), vec![(0, 1), (1, 2), (2, 1), (3, 3)]);
+        assert_eq!(graph.unresolved().count(), 1);
+        assert_eq!(graph.unreachable(), vec![3]);
+        assert_eq!(graph.recursion(), vec![vec![1, 2], vec![3]]);
+        assert!(graph.to_dot().contains("script_0 -> script_1;"));
+    }
+}
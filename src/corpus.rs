@@ -0,0 +1,263 @@
+use super::MscsbFile;
+use super::error::{Error, Result};
+use super::hash::{command_token, Fnv};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// A parsed file in a `MscCorpus`
+#[derive(Debug, Clone)]
+pub struct CorpusEntry {
+    pub path: PathBuf,
+    pub file: MscsbFile,
+    // `fingerprint` of the file when loaded or last saved
+    baseline: u64,
+}
+
+impl CorpusEntry {
+    /// Whether the file would write differently than when it was loaded or last saved
+    pub fn is_modified(&self) -> bool {
+        fingerprint(&self.file) != self.baseline
+    }
+}
+
+// Hash of everything `MscsbFile::write` puts in the file. Command positions are left out
+// since the writer lays scripts out again anyway.
+fn fingerprint(file: &MscsbFile) -> u64 {
+    let mut hash = Fnv::new();
+    hash.write(file.platform().to_string().as_bytes());
+    hash.write_u32(file.entrypoint);
+    hash.write_u32(file.scripts.len() as u32);
+    for script in file.iter() {
+        hash.write_u32(script.commands.len() as u32);
+        for command in script.iter() {
+            hash.write(&command_token(&command.cmd, command.push_bit, false).to_le_bytes());
+        }
+    }
+    hash.write_u32(file.strings.len() as u32);
+    for string in file.strings.iter() {
+        hash.write_u32(string.len() as u32);
+        hash.write(string.as_bytes());
+    }
+    hash.0
+}
+
+/// Every `.mscsb` file under a directory
+#[derive(Debug)]
+pub struct MscCorpus {
+    pub root: PathBuf,
+    /// Sorted by path
+    pub files: Vec<CorpusEntry>,
+    /// Files that couldn't be read or parsed
+    pub errors: Vec<(PathBuf, Error)>,
+}
+
+impl MscCorpus {
+    /// Load using one thread per available core
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<MscCorpus> {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        MscCorpus::load_with_threads(root, threads)
+    }
+
+    /// Walk `root` and parse every `.mscsb` file found. Only failing to walk the
+    /// directory tree is an error, problems with single files end up in `errors`
+    /// and the rest still load.
+    pub fn load_with_threads<P: AsRef<Path>>(root: P, threads: usize) -> Result<MscCorpus> {
+        let root = root.as_ref().to_path_buf();
+        let mut paths = vec![];
+        walk(&root, &mut paths)?;
+        paths.sort();
+
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(paths.len()));
+        thread::scope(|scope| {
+            for _ in 0..threads.max(1).min(paths.len().max(1)) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let path = match paths.get(i) {
+                        Some(path) => path,
+                        None => break,
+                    };
+                    let result = load_entry(path);
+                    results.lock().unwrap().push((i, result));
+                });
+            }
+        });
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|&(i, _)| i);
+        let mut files = vec![];
+        let mut errors = vec![];
+        for (i, result) in results {
+            match result {
+                Ok(entry) => files.push(entry),
+                Err(e) => errors.push((paths[i].clone(), e)),
+            }
+        }
+        Ok(MscCorpus { root, files, errors })
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, CorpusEntry> {
+        self.files.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, CorpusEntry> {
+        self.files.iter_mut()
+    }
+
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&MscsbFile> {
+        self.files.iter().find(|entry| entry.path == path.as_ref()).map(|entry| &entry.file)
+    }
+
+    /// Number of times each mnemonic is used across every file
+    pub fn opcode_histogram(&self) -> BTreeMap<&'static str, usize> {
+        let mut histogram = BTreeMap::new();
+        for entry in self.files.iter() {
+            for command in entry.file.iter().flat_map(|script| script.iter()) {
+                *histogram.entry(command.cmd.name()).or_insert(0) += 1;
+            }
+        }
+        histogram
+    }
+
+    /// For each sys call number, how many times each file calls it
+    pub fn sys_usage(&self) -> BTreeMap<u8, BTreeMap<PathBuf, usize>> {
+        let mut usage: BTreeMap<u8, BTreeMap<PathBuf, usize>> = BTreeMap::new();
+        for entry in self.files.iter() {
+            for command in entry.file.iter().flat_map(|script| script.iter()) {
+                if let crate::Cmd::Sys { sys_num, .. } = command.cmd {
+                    *usage.entry(sys_num).or_default().entry(entry.path.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+        usage
+    }
+
+    /// The `count` largest scripts as (path, script index, size in bytes), largest first
+    pub fn largest_scripts(&self, count: usize) -> Vec<(&Path, usize, u32)> {
+        let mut scripts: Vec<(&Path, usize, u32)> =
+            self.files.iter()
+                .flat_map(|entry| {
+                    entry.file.iter()
+                        .enumerate()
+                        .map(move |(i, s)| (entry.path.as_path(), i, s.bounds.1 - s.bounds.0))
+                })
+                .collect();
+        scripts.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(b.0)).then(a.1.cmp(&b.1)));
+        scripts.truncate(count);
+        scripts
+    }
+
+    /// Write back every file that changed since it was loaded, returning their paths.
+    /// Each file is written to a temporary file next to it and then renamed over it.
+    pub fn save(&mut self) -> Result<Vec<PathBuf>> {
+        let mut written = vec![];
+        for entry in self.files.iter_mut() {
+            let baseline = fingerprint(&entry.file);
+            if baseline == entry.baseline {
+                continue;
+            }
+            let mut bytes = vec![];
+            entry.file.write(&mut bytes);
+            let mut temp = entry.path.clone().into_os_string();
+            temp.push(".tmp");
+            let temp = PathBuf::from(temp);
+            fs::write(&temp, &bytes)?;
+            if let Err(e) = fs::rename(&temp, &entry.path) {
+                let _ = fs::remove_file(&temp);
+                return Err(e.into());
+            }
+            entry.baseline = baseline;
+            written.push(entry.path.clone());
+        }
+        Ok(written)
+    }
+}
+
+fn walk(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // Symlinked directories aren't followed so loops can't happen
+        if entry.file_type()?.is_dir() {
+            walk(&path, paths)?;
+        } else if path.extension().is_some_and(|ext| ext == "mscsb") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn load_entry(path: &Path) -> Result<CorpusEntry> {
+    let bytes = fs::read(path)?;
+    let file = MscsbFile::from_bytes(&bytes)
+        .ok_or_else(|| Error::Format(String::from("not a valid mscsb file")))?;
+    let baseline = fingerprint(&file);
+    Ok(CorpusEntry { path: path.to_path_buf(), file, baseline })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assemble, Cmd};
+
+    #[test]
+    fn test_corpus() {
+        let root = std::env::temp_dir().join(format!("msc-corpus-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("fighter/mario")).unwrap();
+        let a = assemble("main:\n    Begin 0, 0\n    PushShort. 0x1\n    Sys 1, 0x10\n    End\n").unwrap();
+        let b = assemble("main:\n    Begin 0, 0\n    Sys 0, 0x10\n    Sys 0, 0x20\n    End\nother:\n    End\n").unwrap();
+        a.write_to_file(root.join("a.mscsb")).unwrap();
+        b.write_to_file(root.join("fighter/mario/b.mscsb")).unwrap();
+        fs::write(root.join("fighter/broken.mscsb"), b"not msc").unwrap();
+        // Cut off in the middle of the script, so only the header parses
+        let mut bytes = vec![];
+        a.write(&mut bytes);
+        fs::write(root.join("fighter/truncated.mscsb"), &bytes[..0x38]).unwrap();
+        // Claims billions of strings, which mustn't take the whole load down
+        let mut huge = bytes[..0x40].to_vec();
+        huge[0x10..0x14].copy_from_slice(&0x10u32.to_le_bytes());
+        huge[0x18..0x1C].copy_from_slice(&0u32.to_le_bytes());
+        huge[0x20..0x28].copy_from_slice(&[0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+        fs::write(root.join("fighter/huge.mscsb"), &huge).unwrap();
+        fs::write(root.join("notes.txt"), b"ignored").unwrap();
+
+        let mut corpus = MscCorpus::load_with_threads(&root, 3).unwrap();
+        assert_eq!(corpus.files.len(), 2);
+        let errors: Vec<&Path> = corpus.errors.iter().map(|(path, _)| path.as_path()).collect();
+        assert_eq!(errors, vec![
+            root.join("fighter/broken.mscsb"),
+            root.join("fighter/huge.mscsb"),
+            root.join("fighter/truncated.mscsb"),
+        ]);
+
+        let histogram = corpus.opcode_histogram();
+        assert_eq!(histogram["Sys"], 3);
+        assert_eq!(histogram["End"], 3);
+        let usage = corpus.sys_usage();
+        assert_eq!(usage[&0x10].len(), 2);
+        assert_eq!(usage[&0x20].values().cloned().collect::<Vec<_>>(), vec![1]);
+        let largest = corpus.largest_scripts(1);
+        assert_eq!((largest[0].1, largest[0].2), (0, 12));
+
+        assert!(corpus.save().unwrap().is_empty());
+        assert!(!corpus.files[0].is_modified());
+        corpus.files[0].file.scripts[0].commands[1].cmd = Cmd::PushShort { val: 2 };
+        assert!(corpus.files[0].is_modified());
+        let written = corpus.save().unwrap();
+        assert_eq!(written, vec![root.join("a.mscsb")]);
+        assert!(!corpus.files[0].is_modified());
+        assert!(corpus.save().unwrap().is_empty());
+
+        let reloaded = MscCorpus::load(&root).unwrap();
+        match reloaded.get(root.join("a.mscsb")).unwrap().scripts[0].commands[1].cmd {
+            Cmd::PushShort { val } => assert_eq!(val, 2),
+            ref other => panic!("unexpected command {:?}", other),
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
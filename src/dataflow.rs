@@ -0,0 +1,306 @@
+use super::{Cmd, Script};
+use std::collections::{BTreeSet, HashMap};
+
+/// A variable a command can address
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Variable {
+    Local(u16),
+    Global(u16),
+}
+
+impl Variable {
+    /// The variable `cmd` addresses, if any
+    pub fn of(cmd: &Cmd) -> Option<Variable> {
+        cmd.variable().map(|(var_type, var_num)| match var_type {
+            0 => Variable::Local(var_num),
+            _ => Variable::Global(var_num),
+        })
+    }
+}
+
+/// A write that can reach a command
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Definition {
+    pub variable: Variable,
+    /// Index of the writing command, `None` for the value the variable had when the
+    /// script started. For a global this can be a call, which may have written it.
+    pub command: Option<usize>,
+}
+
+/// Live variables, reaching definitions and def-use chains for one script, computed
+/// per command over its control flow graph. Calls and leaving the script count as
+/// reading every global the script mentions, since other scripts can see them, and
+/// calls as possibly writing each of them.
+#[derive(Debug, Clone)]
+pub struct Dataflow {
+    arg_count: u16,
+    // Variable each command reads and writes
+    reads: Vec<Option<Variable>>,
+    writes: Vec<Option<Variable>>,
+    // Globals the script mentions, which calls and exits read and calls may write
+    globals: Vec<Variable>,
+    leaves: Vec<bool>,
+    live_in: Vec<BTreeSet<Variable>>,
+    live_out: Vec<BTreeSet<Variable>>,
+    reaching: Vec<BTreeSet<Definition>>,
+    use_defs: Vec<Vec<Option<usize>>>,
+    def_uses: HashMap<usize, Vec<usize>>,
+}
+
+impl Script {
+    /// Indices of the commands that can run right after command `index`. Branches out
+    /// of the script have no successor.
+    pub fn successors(&self, index: usize) -> Vec<usize> {
+        self.successors_in(&self.command_indices(), index)
+    }
+
+    fn command_indices(&self) -> HashMap<u32, usize> {
+        self.iter().enumerate().map(|(i, c)| (c.position, i)).collect()
+    }
+
+    // `successors` with `command_indices` built once by the caller
+    fn successors_in(&self, positions: &HashMap<u32, usize>, index: usize) -> Vec<usize> {
+        let command = &self.commands[index];
+        let mut successors = vec![];
+        if let Some(&target) = command.cmd.branch_target().and_then(|loc| positions.get(&loc)) {
+            successors.push(target);
+        }
+        if !command.cmd.ends_flow() && index + 1 < self.commands.len() {
+            successors.push(index + 1);
+        }
+        successors
+    }
+
+    pub fn dataflow(&self) -> Dataflow {
+        Dataflow::new(self)
+    }
+}
+
+impl Dataflow {
+    pub fn new(script: &Script) -> Dataflow {
+        let n = script.commands.len();
+        let positions = script.command_indices();
+        let successors: Vec<Vec<usize>> = (0..n).map(|i| script.successors_in(&positions, i)).collect();
+        let arg_count = match script.commands.first().map(|c| c.cmd) {
+            Some(Cmd::Begin { arg_count, .. }) => arg_count,
+            _ => 0,
+        };
+        let variables: BTreeSet<Variable> = script.iter().filter_map(|c| Variable::of(&c.cmd)).collect();
+        let globals: Vec<Variable> = variables.iter().cloned().filter(|v| matches!(v, Variable::Global(_))).collect();
+
+        let mut reads = Vec::with_capacity(n);
+        let mut writes = Vec::with_capacity(n);
+        let mut calls = Vec::with_capacity(n);
+        let mut leaves = Vec::with_capacity(n);
+        // Reads for liveness, which add the globals calls and exits can see
+        let mut gen: Vec<Vec<Variable>> = Vec::with_capacity(n);
+        for (i, command) in script.iter().enumerate() {
+            let variable = Variable::of(&command.cmd);
+            let access = command.cmd.variable_access();
+            reads.push(variable.filter(|_| access.is_some_and(|a| a.is_read())));
+            writes.push(variable.filter(|_| access.is_some_and(|a| a.is_write())));
+            calls.push(matches!(
+                command.cmd,
+                Cmd::Sys { .. } | Cmd::CallFunc { .. } | Cmd::CallFunc2 { .. } | Cmd::CallFunc3 { .. }
+            ));
+            leaves.push(calls[i] || match command.cmd {
+                Cmd::Return6 | Cmd::Return7 | Cmd::Return8 | Cmd::Return9 | Cmd::End | Cmd::Exit => true,
+                _ => command.cmd.branch_target().is_some_and(|loc| !positions.contains_key(&loc)) ||
+                    (i + 1 == n && !command.cmd.ends_flow()),
+            });
+            let mut used: Vec<Variable> = reads[i].into_iter().collect();
+            if leaves[i] {
+                used.extend(globals.iter().cloned());
+            }
+            gen.push(used);
+        }
+
+        let mut live_in = vec![BTreeSet::new(); n];
+        let mut live_out = vec![BTreeSet::new(); n];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in (0..n).rev() {
+                let out: BTreeSet<Variable> = successors[i].iter()
+                    .flat_map(|&s| live_in[s].iter().cloned())
+                    .collect();
+                let mut live: BTreeSet<Variable> = out.iter().cloned().filter(|v| Some(*v) != writes[i]).collect();
+                live.extend(gen[i].iter().cloned());
+                if live != live_in[i] {
+                    live_in[i] = live;
+                    changed = true;
+                }
+                live_out[i] = out;
+            }
+        }
+
+        let mut reaching = vec![BTreeSet::new(); n];
+        if n > 0 {
+            reaching[0] = variables.iter().map(|&variable| Definition { variable, command: None }).collect();
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..n {
+                let mut out: BTreeSet<Definition> = reaching[i].iter().cloned()
+                    .filter(|d| Some(d.variable) != writes[i])
+                    .collect();
+                if let Some(variable) = writes[i] {
+                    out.insert(Definition { variable, command: Some(i) });
+                }
+                // A call may or may not write each global, so earlier writes still reach
+                if calls[i] {
+                    out.extend(globals.iter().map(|&variable| Definition { variable, command: Some(i) }));
+                }
+                for &s in successors[i].iter() {
+                    if !out.is_subset(&reaching[s]) {
+                        reaching[s].extend(out.iter().cloned());
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        let mut use_defs = vec![vec![]; n];
+        let mut def_uses: HashMap<usize, Vec<usize>> = HashMap::new();
+        for i in 0..n {
+            for definition in reaching[i].iter().filter(|d| gen[i].contains(&d.variable)) {
+                if Some(definition.variable) == reads[i] {
+                    use_defs[i].push(definition.command);
+                }
+                if let Some(def) = definition.command {
+                    let uses = def_uses.entry(def).or_default();
+                    if uses.last() != Some(&i) {
+                        uses.push(i);
+                    }
+                }
+            }
+        }
+
+        Dataflow { arg_count, reads, writes, globals, leaves, live_in, live_out, reaching, use_defs, def_uses }
+    }
+
+    /// Variables whose value may be read later, before command `index` runs
+    pub fn live_in(&self, index: usize) -> &BTreeSet<Variable> {
+        &self.live_in[index]
+    }
+
+    /// Variables whose value may be read later, after command `index` runs
+    pub fn live_out(&self, index: usize) -> &BTreeSet<Variable> {
+        &self.live_out[index]
+    }
+
+    /// Definitions that can reach command `index` before it runs
+    pub fn reaching(&self, index: usize) -> &BTreeSet<Definition> {
+        &self.reaching[index]
+    }
+
+    /// Commands that can read the value command `index` writes, including calls and exits
+    /// reading globals, see `implicit_uses`. For a call, the reads of globals it may write.
+    pub fn uses_of(&self, index: usize) -> &[usize] {
+        self.def_uses.get(&index).map_or(&[], |uses| &uses[..])
+    }
+
+    /// Globals command `index` reads without addressing them: every global the script
+    /// mentions, at calls and at commands leaving the script
+    pub fn implicit_uses(&self, index: usize) -> &[Variable] {
+        if self.leaves[index] {
+            &self.globals
+        } else {
+            &[]
+        }
+    }
+
+    /// Writes whose value command `index` can read, `None` being the value from the
+    /// start of the script. Empty if the command doesn't read a variable.
+    pub fn definitions_of(&self, index: usize) -> &[Option<usize>] {
+        &self.use_defs[index]
+    }
+
+    /// Commands that can read a local which isn't an argument before anything writes it
+    pub fn uninitialized_reads(&self) -> Vec<usize> {
+        (0..self.reads.len())
+            .filter(|&i| match self.reads[i] {
+                Some(Variable::Local(var_num)) => var_num >= self.arg_count && self.use_defs[i].contains(&None),
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// Commands writing a value nothing can read
+    pub fn dead_stores(&self) -> Vec<usize> {
+        (0..self.writes.len())
+            .filter(|&i| match self.writes[i] {
+                Some(variable) => !self.live_out[i].contains(&variable),
+                None => false,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn test_dataflow() {
+        let file = assemble("
+main:
+    Begin 1, 3
+    PushShort. 0x1
+    SetVar 0, 0x1
+    PushVar. 0, 0x0
+    IfNot loc_a
+    PushShort. 0x2
+    SetVar 0, 0x1
+    PushShort. 0x5
+    SetVar 0, 0x2
+loc_a:
+    PushVar. 0, 0x1
+    PushVar. 0, 0x2
+    AddI.
+    SetVar 1, 0x4
+    PushShort. 0x0
+    SetVar 0, 0x1
+    End
+").unwrap();
+        let script = &file.scripts[0];
+        assert_eq!(script.successors(4), vec![9, 5]);
+        let flow = script.dataflow();
+        assert_eq!(flow.uninitialized_reads(), vec![10]);
+        assert_eq!(flow.dead_stores(), vec![14]);
+        assert_eq!(flow.uses_of(2), &[9]);
+        assert_eq!(flow.definitions_of(9), &[Some(2), Some(6)]);
+        assert_eq!(flow.definitions_of(10), &[None, Some(8)]);
+        assert!(flow.live_out(2).contains(&Variable::Local(1)));
+        assert!(flow.live_in(7).contains(&Variable::Local(1)));
+        assert!(!flow.live_in(7).contains(&Variable::Local(2)));
+        assert!(flow.live_out(12).contains(&Variable::Global(4)));
+        assert!(flow.reaching(15).contains(&Definition { variable: Variable::Global(4), command: Some(12) }));
+        assert_eq!(flow.uses_of(12), &[15]);
+        assert_eq!(flow.implicit_uses(15), &[Variable::Global(4)]);
+        assert!(flow.implicit_uses(12).is_empty());
+    }
+
+    #[test]
+    fn test_dataflow_calls() {
+        let file = assemble("
+main:
+    Begin 0, 0
+    PushShort. 0x1
+    SetVar 1, 0x3
+    Sys 0, 0x10
+    PushVar. 1, 0x3
+    Pop
+    End
+").unwrap();
+        let flow = file.scripts[0].dataflow();
+        // The call may have written the global, or left the value from before it
+        assert_eq!(flow.definitions_of(4), &[Some(2), Some(3)]);
+        assert_eq!(flow.uses_of(2), &[3, 4, 6]);
+        assert_eq!(flow.uses_of(3), &[4, 6]);
+        assert_eq!(flow.implicit_uses(3), &[Variable::Global(3)]);
+        assert!(flow.dead_stores().is_empty());
+    }
+}
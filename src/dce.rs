@@ -0,0 +1,170 @@
+use super::MscsbFile;
+use super::call_graph::address_pushes;
+use super::error::Result;
+use super::layout::relayout;
+use std::collections::{HashMap, HashSet};
+
+/// What `MscsbFile::eliminate_dead_code` removed. Indices and positions refer to
+/// the file before the pass.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DeadCodeReport {
+    /// Scripts nothing reachable calls or refers to
+    pub removed_scripts: Vec<usize>,
+    /// (script, position) of unreachable commands in scripts that were kept
+    pub removed_commands: Vec<(usize, u32)>,
+    pub bytes_saved: u32,
+}
+
+impl DeadCodeReport {
+    pub fn is_empty(&self) -> bool {
+        self.removed_scripts.is_empty() && self.removed_commands.is_empty()
+    }
+}
+
+impl MscsbFile {
+    /// Remove commands that can't be reached from the entrypoint, and scripts with no
+    /// reachable commands left. A `PushInt` or `PushShort` of a script's address that a
+    /// reachable call jumps to makes that script reachable. If the entrypoint isn't a command every
+    /// script is kept, but unreachable commands inside them are still removed. Fails, leaving
+    /// the file alone, if a `PushShort` address can't follow its script.
+    pub fn eliminate_dead_code(&mut self) -> Result<DeadCodeReport> {
+        let mut index = HashMap::new();
+        for (s, script) in self.scripts.iter().enumerate() {
+            for (c, command) in script.iter().enumerate() {
+                index.insert(command.position, (s, c));
+            }
+        }
+        let starts: HashSet<u32> = self.scripts.iter().map(|s| s.bounds.0).collect();
+        let addresses: Vec<_> = self.scripts.iter().map(address_pushes).collect();
+
+        let mut reached: Vec<Vec<bool>> = self.scripts.iter().map(|s| vec![false; s.commands.len()]).collect();
+        let mut work = if index.contains_key(&self.entrypoint) {
+            vec![self.entrypoint]
+        } else {
+            self.scripts.iter().map(|s| s.bounds.0).collect()
+        };
+        while let Some(position) = work.pop() {
+            let (s, c) = match index.get(&position) {
+                Some(&found) => found,
+                None => continue,
+            };
+            if reached[s][c] {
+                continue;
+            }
+            reached[s][c] = true;
+            let script = &self.scripts[s];
+            let command = &script.commands[c];
+            // Entering a script partway still needs its start
+            work.push(script.bounds.0);
+            if let Some(loc) = command.cmd.branch_target() {
+                work.push(loc);
+            }
+            if let Some(&val) = addresses[s].get(&c).filter(|val| starts.contains(val)) {
+                work.push(val);
+            }
+            if !command.cmd.ends_flow() {
+                if let Some(next) = script.commands.get(c + 1) {
+                    work.push(next.position);
+                }
+            }
+        }
+
+        let before: u32 = self.iter().map(|s| s.bounds.1 - s.bounds.0).sum();
+        let mut report = DeadCodeReport::default();
+        let mut scripts = Vec::with_capacity(self.scripts.len());
+        for (s, script) in self.scripts.iter().enumerate() {
+            if !reached[s].iter().any(|&r| r) && !script.commands.is_empty() {
+                report.removed_scripts.push(s);
+                scripts.push(None);
+                continue;
+            }
+            let mut kept = Vec::with_capacity(script.commands.len());
+            for (command, &live) in script.iter().zip(reached[s].iter()) {
+                if live {
+                    kept.push(command.clone());
+                } else {
+                    report.removed_commands.push((s, command.position));
+                }
+            }
+            scripts.push(Some(kept));
+        }
+        if !report.is_empty() {
+            relayout(self, scripts)?;
+        }
+        report.bytes_saved = before - self.iter().map(|s| s.bounds.1 - s.bounds.0).sum::<u32>();
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::assemble;
+
+    #[test]
+    fn test_eliminate_dead_code() {
+        let mut file = assemble("
+.entrypoint main
+main:
+    Begin 0, 0
+    PushShort. 0x32
+    SetVar 1, 0x0
+    PushInt. helper
+    CallFunc. 0
+    PushShort. 0x49
+    CallFunc. 0
+    Jump loc_end
+    PushShort. 0x5
+    Pop
+loc_end:
+    End
+
+unused:
+    Begin 0, 0
+    PushInt. unused
+    CallFunc 0
+    End
+
+helper:
+    Begin 0, 0
+    PushShort. 0x1
+    Return6
+    End
+
+callback:
+    Begin 0, 0
+    End
+").unwrap();
+        // `PushShort. 0x49` is the address of callback. `PushShort. 0x32` equals the
+        // start of unused, but is only stored, so it doesn't keep unused alive or move.
+        let report = file.eliminate_dead_code().unwrap();
+        assert_eq!(report.removed_scripts, vec![1]);
+        assert_eq!(report.removed_commands.len(), 3);
+        assert_eq!(report.bytes_saved, 13 + 5);
+
+        let expected = assemble("
+.entrypoint main
+main:
+    Begin 0, 0
+    PushShort. 0x32
+    SetVar 1, 0x0
+    PushInt. helper
+    CallFunc. 0
+    PushShort. 0x37
+    CallFunc. 0
+    Jump loc_end
+loc_end:
+    End
+
+helper:
+    Begin 0, 0
+    PushShort. 0x1
+    Return6
+
+callback:
+    Begin 0, 0
+    End
+").unwrap();
+        assert_eq!(file.disassemble(), expected.disassemble());
+        assert!(file.eliminate_dead_code().unwrap().is_empty());
+    }
+}
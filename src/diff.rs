@@ -0,0 +1,438 @@
+use super::{Disassembler, MscsbFile};
+use super::hash::{self, Key, Reloc};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiffOp {
+    /// Same instruction in both scripts (command indices)
+    Equal { old: usize, new: usize },
+    Delete { old: usize },
+    Insert { new: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptChange {
+    Added { new: usize },
+    Removed { old: usize },
+    /// Same instructions, possibly at a different index or offset
+    Unchanged { old: usize, new: usize },
+    Modified {
+        old: usize,
+        new: usize,
+        /// Fraction of instructions kept, from 0 to 1
+        similarity: f32,
+        ops: Vec<DiffOp>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileDiff {
+    pub changes: Vec<ScriptChange>,
+}
+
+struct Normalized {
+    keys: Vec<Key>,
+    relocs: Vec<Reloc>,
+}
+
+fn normalize(file: &MscsbFile, index: usize) -> Normalized {
+    let (keys, relocs) = hash::normalize(&file.scripts[index], |val| file.get_script_from_loc(val))
+        .into_iter()
+        .unzip();
+    Normalized { keys, relocs }
+}
+
+impl FileDiff {
+    pub fn new(old: &MscsbFile, new: &MscsbFile) -> FileDiff {
+        let old_norm: Vec<Normalized> = (0..old.scripts.len()).map(|i| normalize(old, i)).collect();
+        let new_norm: Vec<Normalized> = (0..new.scripts.len()).map(|i| normalize(new, i)).collect();
+        let pairs = match_scripts(&old_norm, &new_norm);
+        let script_map: HashMap<usize, usize> = pairs.iter().cloned().collect();
+
+        let mut changes = vec![];
+        let mut matched_new = vec![false; new.scripts.len()];
+        for (old_index, old_script) in old_norm.iter().enumerate() {
+            let new_index = match script_map.get(&old_index) {
+                Some(&new_index) => new_index,
+                None => {
+                    changes.push(ScriptChange::Removed { old: old_index });
+                    continue;
+                }
+            };
+            matched_new[new_index] = true;
+            let ops = diff_commands(old_script, &new_norm[new_index], &script_map);
+            let equal = ops.iter().filter(|op| matches!(op, DiffOp::Equal { .. })).count();
+            if equal == ops.len() {
+                changes.push(ScriptChange::Unchanged { old: old_index, new: new_index });
+            } else {
+                let total = old_script.keys.len() + new_norm[new_index].keys.len();
+                changes.push(ScriptChange::Modified {
+                    old: old_index,
+                    new: new_index,
+                    similarity: (2 * equal) as f32 / total.max(1) as f32,
+                    ops,
+                });
+            }
+        }
+        for (new_index, matched) in matched_new.into_iter().enumerate() {
+            if !matched {
+                changes.push(ScriptChange::Added { new: new_index });
+            }
+        }
+        FileDiff { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.iter().all(|change| matches!(change, ScriptChange::Unchanged { .. }))
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = usize> + '_ {
+        self.changes.iter().filter_map(|change| match *change {
+            ScriptChange::Added { new } => Some(new),
+            _ => None,
+        })
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = usize> + '_ {
+        self.changes.iter().filter_map(|change| match *change {
+            ScriptChange::Removed { old } => Some(old),
+            _ => None,
+        })
+    }
+
+    pub fn modified(&self) -> impl Iterator<Item = &ScriptChange> {
+        self.changes.iter().filter(|change| matches!(change, ScriptChange::Modified { .. }))
+    }
+
+    /// Render as a unified diff of the disassembly, with `context` lines around changes
+    pub fn to_unified(&self, old: &MscsbFile, new: &MscsbFile, context: usize) -> String {
+        let old_dis = Disassembler::new(old);
+        let new_dis = Disassembler::new(new);
+        let mut text = String::new();
+        for change in self.changes.iter() {
+            match *change {
+                ScriptChange::Unchanged { .. } => {}
+                ScriptChange::Added { new: index } => {
+                    writeln!(text, "--- /dev/null\n+++ b/script_{}", index).unwrap();
+                    let script = &new.scripts[index];
+                    writeln!(text, "@@ -0,0 +1,{} @@", script.commands.len()).unwrap();
+                    for command in script.iter() {
+                        writeln!(text, "+{}", new_dis.command(index, command)).unwrap();
+                    }
+                }
+                ScriptChange::Removed { old: index } => {
+                    writeln!(text, "--- a/script_{}\n+++ /dev/null", index).unwrap();
+                    let script = &old.scripts[index];
+                    writeln!(text, "@@ -1,{} +0,0 @@", script.commands.len()).unwrap();
+                    for command in script.iter() {
+                        writeln!(text, "-{}", old_dis.command(index, command)).unwrap();
+                    }
+                }
+                ScriptChange::Modified { old: old_index, new: new_index, ref ops, .. } => {
+                    writeln!(text, "--- a/script_{}\n+++ b/script_{}", old_index, new_index).unwrap();
+                    let line = |op: &DiffOp| match *op {
+                        DiffOp::Equal { old: i, .. } =>
+                            format!(" {}", old_dis.command(old_index, &old.scripts[old_index].commands[i])),
+                        DiffOp::Delete { old: i } =>
+                            format!("-{}", old_dis.command(old_index, &old.scripts[old_index].commands[i])),
+                        DiffOp::Insert { new: i } =>
+                            format!("+{}", new_dis.command(new_index, &new.scripts[new_index].commands[i])),
+                    };
+                    for (start, end) in hunks(ops, context) {
+                        let (mut old_start, mut new_start) = (None, None);
+                        let (mut old_len, mut new_len) = (0, 0);
+                        for op in &ops[start..end] {
+                            match *op {
+                                DiffOp::Equal { old, new } => {
+                                    old_start.get_or_insert(old);
+                                    new_start.get_or_insert(new);
+                                    old_len += 1;
+                                    new_len += 1;
+                                }
+                                DiffOp::Delete { old } => {
+                                    old_start.get_or_insert(old);
+                                    old_len += 1;
+                                }
+                                DiffOp::Insert { new } => {
+                                    new_start.get_or_insert(new);
+                                    new_len += 1;
+                                }
+                            }
+                        }
+                        writeln!(text, "@@ -{},{} +{},{} @@",
+                                 old_start.map_or(0, |s| s + 1), old_len,
+                                 new_start.map_or(0, |s| s + 1), new_len).unwrap();
+                        for op in &ops[start..end] {
+                            writeln!(text, "{}", line(op)).unwrap();
+                        }
+                    }
+                }
+            }
+        }
+        text
+    }
+}
+
+impl MscsbFile {
+    /// Compare against a newer version of the same file
+    pub fn diff(&self, new: &MscsbFile) -> FileDiff {
+        FileDiff::new(self, new)
+    }
+}
+
+// Ranges of `ops` to print, changes plus `context` surrounding equal ops
+fn hunks(ops: &[DiffOp], context: usize) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for (i, op) in ops.iter().enumerate() {
+        if let DiffOp::Equal { .. } = op {
+            continue;
+        }
+        let start = i.saturating_sub(context);
+        let end = (i + 1 + context).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if last.1 >= start => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    hunks
+}
+
+// Pair up old and new scripts: identical scripts first, then the most similar
+// remaining ones, preferring scripts that stayed near the same index
+fn match_scripts(old: &[Normalized], new: &[Normalized]) -> Vec<(usize, usize)> {
+    let mut pairs = vec![];
+    let mut old_used = vec![false; old.len()];
+    let mut new_used = vec![false; new.len()];
+
+    let mut by_keys: HashMap<&[Key], Vec<usize>> = HashMap::new();
+    for (i, script) in new.iter().enumerate() {
+        by_keys.entry(&script.keys[..]).or_default().push(i);
+    }
+    for (i, script) in old.iter().enumerate() {
+        if let Some(candidates) = by_keys.get_mut(&script.keys[..]) {
+            let best = candidates
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, &j)| (i as isize - j as isize).abs())
+                .map(|(n, _)| n);
+            if let Some(n) = best {
+                let j = candidates.remove(n);
+                old_used[i] = true;
+                new_used[j] = true;
+                pairs.push((i, j));
+            }
+        }
+    }
+
+    let old_bags: Vec<_> = old.iter().map(bag).collect();
+    let new_bags: Vec<_> = new.iter().map(bag).collect();
+    let mut candidates = vec![];
+    for i in (0..old.len()).filter(|&i| !old_used[i]) {
+        for j in (0..new.len()).filter(|&j| !new_used[j]) {
+            let shared: usize = old_bags[i]
+                .iter()
+                .map(|(key, count)| (*count).min(*new_bags[j].get(key).unwrap_or(&0)))
+                .sum();
+            let total = old[i].keys.len() + new[j].keys.len();
+            let similarity = (2 * shared) as f32 / total.max(1) as f32;
+            if similarity >= 0.5 {
+                candidates.push((similarity, (i as isize - j as isize).abs(), i, j));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap().then(a.1.cmp(&b.1)));
+    for (_, _, i, j) in candidates {
+        if !old_used[i] && !new_used[j] {
+            old_used[i] = true;
+            new_used[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    pairs.sort();
+    pairs
+}
+
+fn bag(script: &Normalized) -> HashMap<&Key, usize> {
+    let mut bag = HashMap::new();
+    for key in script.keys.iter() {
+        *bag.entry(key).or_insert(0) += 1;
+    }
+    bag
+}
+
+fn diff_commands(old: &Normalized, new: &Normalized, script_map: &HashMap<usize, usize>) -> Vec<DiffOp> {
+    let ops = lcs(&old.keys, &new.keys);
+    let command_map: HashMap<usize, usize> =
+        ops.iter()
+            .filter_map(|op| match *op {
+                DiffOp::Equal { old, new } => Some((old, new)),
+                _ => None,
+            })
+            .collect();
+    // Instructions that only matched because their targets were blanked out still
+    // differ if they don't point at corresponding places
+    let mut fixed = Vec::with_capacity(ops.len());
+    for op in ops {
+        if let DiffOp::Equal { old: i, new: j } = op {
+            let same = match (old.relocs[i], new.relocs[j]) {
+                (Reloc::None, Reloc::None) => true,
+                (Reloc::Branch { index: Some(a), .. }, Reloc::Branch { index: Some(b), .. }) =>
+                    command_map.get(&a) == Some(&b) || (a == old.keys.len() && b == new.keys.len()),
+                (Reloc::Branch { index: None, offset: a }, Reloc::Branch { index: None, offset: b }) => a == b,
+                (Reloc::Script(a), Reloc::Script(b)) => script_map.get(&a) == Some(&b),
+                _ => false,
+            };
+            if !same {
+                fixed.push(DiffOp::Delete { old: i });
+                fixed.push(DiffOp::Insert { new: j });
+                continue;
+            }
+        }
+        fixed.push(op);
+    }
+    fixed
+}
+
+fn lcs<T: PartialEq>(a: &[T], b: &[T]) -> Vec<DiffOp> {
+    let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut ops: Vec<DiffOp> = (0..prefix).map(|i| DiffOp::Equal { old: i, new: i }).collect();
+    hirschberg(a_mid, b_mid, prefix, prefix, &mut ops);
+    for k in 0..suffix {
+        ops.push(DiffOp::Equal { old: a.len() - suffix + k, new: b.len() - suffix + k });
+    }
+    ops
+}
+
+// Hirschberg's algorithm, so large scripts don't need the whole LCS table. Finds where
+// an LCS crosses the middle of `a` from the lengths of both halves, then recurses.
+fn hirschberg<T: PartialEq>(a: &[T], b: &[T], a_start: usize, b_start: usize, ops: &mut Vec<DiffOp>) {
+    if a.is_empty() || b.is_empty() {
+        ops.extend((0..a.len()).map(|i| DiffOp::Delete { old: a_start + i }));
+        ops.extend((0..b.len()).map(|j| DiffOp::Insert { new: b_start + j }));
+        return;
+    }
+    if a.len() == 1 {
+        match b.iter().position(|x| *x == a[0]) {
+            Some(j) => {
+                ops.extend((0..j).map(|k| DiffOp::Insert { new: b_start + k }));
+                ops.push(DiffOp::Equal { old: a_start, new: b_start + j });
+                ops.extend((j + 1..b.len()).map(|k| DiffOp::Insert { new: b_start + k }));
+            }
+            None => {
+                ops.push(DiffOp::Delete { old: a_start });
+                ops.extend((0..b.len()).map(|k| DiffOp::Insert { new: b_start + k }));
+            }
+        }
+        return;
+    }
+    let mid = a.len() / 2;
+    let forward = lcs_lengths(a[..mid].iter(), b.iter());
+    let backward = lcs_lengths(a[mid..].iter().rev(), b.iter().rev());
+    let split = (0..=b.len())
+        .max_by_key(|&j| (forward[j] + backward[b.len() - j], std::cmp::Reverse(j)))
+        .unwrap();
+    hirschberg(&a[..mid], &b[..split], a_start, b_start, ops);
+    hirschberg(&a[mid..], &b[split..], a_start + mid, b_start + split, ops);
+}
+
+// Last row of the LCS table: entry `j` is the LCS length of `a` and the first `j` of `b`
+fn lcs_lengths<'a, T: PartialEq + 'a, A, B>(a: A, b: B) -> Vec<u32>
+    where A: Iterator<Item = &'a T>, B: Iterator<Item = &'a T> + Clone
+{
+    let mut row = vec![0u32; b.clone().count() + 1];
+    for x in a {
+        let mut diagonal = 0;
+        for (j, y) in b.clone().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if x == y { diagonal + 1 } else { above.max(row[j]) };
+            diagonal = above;
+        }
+    }
+    row
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::Cmd;
+    use super::super::test::script;
+
+    #[test]
+    fn test_diff_ignores_relocation() {
+        let old = MscsbFile::new(
+            vec![
+                script(0x10, &[Cmd::PushInt { val: 0x20 }, Cmd::CallFunc { arg_count: 0 }, Cmd::End]),
+                script(0x20, &[
+                    Cmd::PushShort { val: 1 }, Cmd::IfNot { loc: 0x24 },
+                    Cmd::PushShort { val: 2 }, Cmd::Pop, Cmd::End,
+                ]),
+                script(0x30, &[Cmd::Nop, Cmd::End]),
+            ],
+            vec![],
+            0x10,
+        );
+        // Script 1 grew an instruction, which pushes everything after it along
+        let new = MscsbFile::new(
+            vec![
+                script(0x10, &[Cmd::PushInt { val: 0x20 }, Cmd::CallFunc { arg_count: 0 }, Cmd::End]),
+                script(0x20, &[
+                    Cmd::PushShort { val: 1 }, Cmd::IfNot { loc: 0x25 }, Cmd::Nop,
+                    Cmd::PushShort { val: 3 }, Cmd::Pop, Cmd::End,
+                ]),
+                script(0x30, &[Cmd::Exit]),
+            ],
+            vec![],
+            0x10,
+        );
+        let diff = old.diff(&new);
+        assert_eq!(diff.changes[0], ScriptChange::Unchanged { old: 0, new: 0 });
+        match diff.changes[1] {
+            ScriptChange::Modified { ref ops, .. } => assert_eq!(ops, &vec![
+                DiffOp::Equal { old: 0, new: 0 },
+                DiffOp::Equal { old: 1, new: 1 },
+                DiffOp::Delete { old: 2 },
+                DiffOp::Insert { new: 2 },
+                DiffOp::Insert { new: 3 },
+                DiffOp::Equal { old: 3, new: 4 },
+                DiffOp::Equal { old: 4, new: 5 },
+            ]),
+            ref change => panic!("expected a modified script, got {:?}", change),
+        }
+        assert_eq!(diff.removed().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(diff.added().collect::<Vec<_>>(), vec![2]);
+        let text = diff.to_unified(&old, &new, 1);
+        assert!(text.contains("@@ -2,3 +2,4 @@\n IfNot loc_24\n-PushShort. 0x2\n+Nop\n+PushShort. 0x3\n Pop\n"));
+    }
+
+    #[test]
+    fn test_lcs() {
+        let (a, b) = (b"xxABCBDAByy", b"xxBDCABAyy");
+        let ops = lcs(&a[..], &b[..]);
+        let equal: Vec<(usize, usize)> = ops.iter()
+            .filter_map(|op| match *op {
+                DiffOp::Equal { old, new } => Some((old, new)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(equal.len(), 4 + 4);
+        assert!(equal.iter().all(|&(i, j)| a[i] == b[j]));
+        assert!(equal.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        // Every element is accounted for exactly once, in order
+        let old: Vec<usize> = ops.iter().filter_map(|op| match *op {
+            DiffOp::Equal { old, .. } | DiffOp::Delete { old } => Some(old),
+            _ => None,
+        }).collect();
+        let new: Vec<usize> = ops.iter().filter_map(|op| match *op {
+            DiffOp::Equal { new, .. } | DiffOp::Insert { new } => Some(new),
+            _ => None,
+        }).collect();
+        assert_eq!((old, new), ((0..a.len()).collect(), (0..b.len()).collect()));
+    }
+}
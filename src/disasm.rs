@@ -0,0 +1,183 @@
+use super::{printf, Cmd, Command, MscsbFile, Platform, SymbolMap, SysCatalog};
+use std::collections::HashSet;
+use std::fmt::{self, Write};
+
+/// Renders an `MscsbFile` as assembly text
+pub struct Disassembler<'a> {
+    file: &'a MscsbFile,
+    catalog: &'a SysCatalog,
+    symbols: Option<&'a SymbolMap>,
+    labels: Vec<String>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(file: &'a MscsbFile) -> Disassembler<'a> {
+        Disassembler {
+            file,
+            catalog: SysCatalog::builtin_shared(),
+            symbols: None,
+            labels: (0..file.scripts.len()).map(|i| format!("script_{}", i)).collect(),
+        }
+    }
+
+    pub fn with_catalog(mut self, catalog: &'a SysCatalog) -> Disassembler<'a> {
+        self.catalog = catalog;
+        self
+    }
+
+    /// Name scripts, variables and sys calls after a symbol map
+    pub fn with_symbols(mut self, symbols: &'a SymbolMap) -> Disassembler<'a> {
+        self.symbols = Some(symbols);
+        self.labels = symbols.script_labels(self.file);
+        self
+    }
+
+    /// Label of script `index`, see `SymbolMap::script_labels`
+    pub fn script_label(&self, index: usize) -> String {
+        self.labels[index].clone()
+    }
+
+    /// Listing of a single script, including its label
+    pub fn script(&self, index: usize) -> String {
+        let script = &self.file.scripts[index];
+        let positions: HashSet<u32> = script.iter().map(|c| c.position).collect();
+        let targets: HashSet<u32> =
+            script.iter()
+                .filter_map(|c| c.cmd.branch_target())
+                .collect();
+        let mut text = String::new();
+        let label = format!("{}:", self.script_label(index));
+        let comment = self.symbols
+            .and_then(|symbols| symbols.script(self.file, index))
+            .and_then(|script| script.symbol.comment.as_ref());
+        write!(text, "{:<28}; 0x{:X} - 0x{:X}", label, script.bounds.0, script.bounds.1).unwrap();
+        match comment {
+            Some(comment) => writeln!(text, " {}", comment).unwrap(),
+            None => writeln!(text).unwrap(),
+        }
+        let sources = match script.iter().any(|c| matches!(c.cmd, Cmd::PrintF { .. })) {
+            true => script.operand_sources(),
+            false => vec![],
+        };
+        for (i, command) in script.iter().enumerate() {
+            if targets.contains(&command.position) {
+                writeln!(text, "loc_{:X}:", command.position).unwrap();
+            }
+            let line = self.format_command(&positions, command);
+            let comment = match command.cmd {
+                Cmd::PrintF { .. } => self.printf_comment(&sources, index, i),
+                _ => self.comment(index, command),
+            };
+            match comment {
+                Some(comment) => writeln!(text, "    {:<24}; {}", line, comment).unwrap(),
+                None => writeln!(text, "    {}", line).unwrap(),
+            }
+        }
+        text
+    }
+
+    /// A single command without label or comment, such as `PushInt. 0x1A`
+    pub fn command(&self, script: usize, command: &Command) -> String {
+        let positions: HashSet<u32> = self.file.scripts[script].iter().map(|c| c.position).collect();
+        self.format_command(&positions, command)
+    }
+
+    // `positions` holds every command position in the script, branches to them get a label
+    fn format_command(&self, positions: &HashSet<u32>, command: &Command) -> String {
+        let mut line = String::from(command.cmd.name());
+        if command.push_bit {
+            line.push('.');
+        }
+        let operands: Vec<String> = match command.cmd {
+            Cmd::Jump { loc } | Cmd::Jump5 { loc } | Cmd::Try { loc } |
+            Cmd::If { loc } | Cmd::IfNot { loc } | Cmd::Else { loc } => {
+                if positions.contains(&loc) {
+                    vec![format!("loc_{:X}", loc)]
+                } else {
+                    vec![format!("0x{:X}", loc)]
+                }
+            }
+            Cmd::PushInt { val } => match self.file.get_script_from_loc(val) {
+                Some(target) => vec![self.script_label(target)],
+                None => vec![format!("0x{:X}", val)],
+            },
+            Cmd::PushShort { val } => vec![format!("0x{:X}", val)],
+            Cmd::Sys { arg_count, sys_num } => vec![arg_count.to_string(), format!("0x{:X}", sys_num)],
+            _ => match command.cmd.variable() {
+                Some((var_type, var_num)) => vec![var_type.to_string(), format!("0x{:X}", var_num)],
+                None => command.cmd.operands().iter().map(|o| o.to_string()).collect(),
+            },
+        };
+        if !operands.is_empty() {
+            line.push(' ');
+            line.push_str(&operands.join(", "));
+        }
+        line
+    }
+
+    // The format string a `PrintF` uses and anything wrong with its arguments
+    fn printf_comment(&self, sources: &[Vec<Option<usize>>], script: usize, index: usize) -> Option<String> {
+        let script = &self.file.scripts[script];
+        let (_, string) = printf::format_source(script, sources, index)?;
+        let mut comment = match self.file.strings.get(string as usize) {
+            Some(format) => format!("{:?}", format),
+            None => String::new(),
+        };
+        for problem in printf::check(self.file, script, sources, index) {
+            if !comment.is_empty() {
+                comment.push_str(" - ");
+            }
+            comment.push_str(&problem);
+        }
+        Some(comment)
+    }
+
+    fn comment(&self, script: usize, command: &Command) -> Option<String> {
+        if let Cmd::Sys { sys_num, .. } = command.cmd {
+            let symbol = self.symbols.and_then(|symbols| symbols.sys(sys_num));
+            let mut comment = match symbol {
+                Some(symbol) => Some(symbol.name.clone()),
+                None => self.catalog.get(sys_num).map(|call| call.name.clone()),
+            };
+            if let Some(problem) = self.catalog.check(command) {
+                comment = Some(match comment {
+                    Some(name) => format!("{} - {}", name, problem),
+                    None => problem,
+                });
+            }
+            return comment;
+        }
+        let (var_type, var_num) = command.cmd.variable()?;
+        let symbol = self.symbols?.variable(self.file, script, var_type, var_num)?;
+        Some(match symbol.comment {
+            Some(ref comment) => format!("{} - {}", symbol.name, comment),
+            None => symbol.name.clone(),
+        })
+    }
+}
+
+impl<'a> fmt::Display for Disassembler<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.file.platform != Platform::default() {
+            writeln!(f, ".platform {}", self.file.platform)?;
+        }
+        match self.file.get_script_from_loc(self.file.entrypoint) {
+            Some(entry) => writeln!(f, ".entrypoint {}", self.script_label(entry))?,
+            None => writeln!(f, ".entrypoint 0x{:X}", self.file.entrypoint)?,
+        }
+        for string in self.file.strings.iter() {
+            writeln!(f, ".string {:?}", string)?;
+        }
+        for i in 0..self.file.scripts.len() {
+            writeln!(f)?;
+            write!(f, "{}", self.script(i))?;
+        }
+        Ok(())
+    }
+}
+
+impl MscsbFile {
+    pub fn disassemble(&self) -> String {
+        Disassembler::new(self).to_string()
+    }
+}
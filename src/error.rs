@@ -0,0 +1,68 @@
+use std::fmt;
+
+/// Variants can be added, including ones that only exist with some features enabled
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Io(std::io::Error),
+    #[cfg(feature = "serde")]
+    Json(serde_json::Error),
+    #[cfg(feature = "serde")]
+    Toml(toml::de::Error),
+    #[cfg(feature = "serde")]
+    TomlSer(toml::ser::Error),
+    /// Data that parsed but doesn't make sense, such as a bad key
+    Format(String),
+    /// Error in assembly text, with a 1-based line number
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            #[cfg(feature = "serde")]
+            Error::Json(e) => write!(f, "json error: {}", e),
+            #[cfg(feature = "serde")]
+            Error::Toml(e) => write!(f, "toml error: {}", e),
+            #[cfg(feature = "serde")]
+            Error::TomlSer(e) => write!(f, "toml error: {}", e),
+            Error::Format(msg) => write!(f, "{}", msg),
+            Error::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Error {
+        Error::Toml(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<toml::ser::Error> for Error {
+    fn from(e: toml::ser::Error) -> Error {
+        Error::TomlSer(e)
+    }
+}
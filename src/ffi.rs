@@ -0,0 +1,366 @@
+//! C API over `MscsbFile`. `include/msc.h` is generated from this file by `build.rs`.
+
+use super::{Cmd, Command, MscsbFile};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// Returned by every fallible function, `MSC_ERROR_SUCCESS` on success
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MscError {
+    Success = 0,
+    /// A required pointer was null
+    NullPointer = 1,
+    /// The data isn't a valid mscsb file
+    InvalidFile = 2,
+    /// A script, command or string index past the end
+    OutOfRange = 3,
+    /// Unknown mnemonic, wrong number of operands or an operand that doesn't fit
+    BadCommand = 4,
+    /// A string that isn't valid UTF-8
+    BadString = 5,
+    /// The output buffer is too small, the needed size has been written out
+    BufferTooSmall = 6,
+    /// A bug in the library, the handle should not be used again
+    Panic = 7,
+}
+
+/// A decoded command. When passed in, `opcode` is ignored and the command is built
+/// from `name` and the first `operand_count` operands.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct MscCommand {
+    /// NUL terminated mnemonic, as printed by the disassembler
+    pub name: [c_char; 16],
+    pub opcode: u8,
+    pub push_bit: bool,
+    pub position: u32,
+    pub operand_count: u32,
+    pub operands: [u32; 2],
+}
+
+impl MscCommand {
+    fn from_command(command: &Command) -> MscCommand {
+        let mut name = [0; 16];
+        for (slot, &byte) in name.iter_mut().zip(command.cmd.name().as_bytes()) {
+            *slot = byte as c_char;
+        }
+        let operands = command.cmd.operands();
+        let mut padded = [0; 2];
+        padded[..operands.len()].copy_from_slice(&operands);
+        MscCommand {
+            name,
+            opcode: command.cmd.value(),
+            push_bit: command.push_bit,
+            position: command.position,
+            operand_count: operands.len() as u32,
+            operands: padded,
+        }
+    }
+
+    fn to_command(&self) -> Result<Command, MscError> {
+        let name: Vec<u8> = self.name.iter().map(|&c| c as u8).collect();
+        let name = CStr::from_bytes_until_nul(&name)
+            .ok()
+            .and_then(|name| name.to_str().ok())
+            .ok_or(MscError::BadCommand)?;
+        let operands = self.operands.get(..self.operand_count as usize).ok_or(MscError::BadCommand)?;
+        let cmd = Cmd::from_parts(name, operands).ok_or(MscError::BadCommand)?;
+        Ok(Command { cmd, push_bit: self.push_bit, position: self.position })
+    }
+}
+
+// Turn panics into MscError::Panic instead of unwinding into C
+fn guard<F: FnOnce() -> Result<(), MscError>>(f: F) -> MscError {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => MscError::Success,
+        Ok(Err(e)) => e,
+        Err(_) => MscError::Panic,
+    }
+}
+
+unsafe fn file_ref<'a>(file: *const MscsbFile) -> Result<&'a MscsbFile, MscError> {
+    file.as_ref().ok_or(MscError::NullPointer)
+}
+
+unsafe fn file_mut<'a>(file: *mut MscsbFile) -> Result<&'a mut MscsbFile, MscError> {
+    file.as_mut().ok_or(MscError::NullPointer)
+}
+
+unsafe fn out<'a, T>(ptr: *mut T) -> Result<&'a mut T, MscError> {
+    ptr.as_mut().ok_or(MscError::NullPointer)
+}
+
+unsafe fn c_str<'a>(s: *const c_char) -> Result<&'a str, MscError> {
+    if s.is_null() {
+        return Err(MscError::NullPointer);
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| MscError::BadString)
+}
+
+// Copy into a caller's buffer, writing the needed size to `len` either way
+unsafe fn copy_out(bytes: &[u8], buf: *mut u8, cap: usize, len: *mut usize) -> Result<(), MscError> {
+    *out(len)? = bytes.len();
+    if buf.is_null() || cap < bytes.len() {
+        return Err(MscError::BufferTooSmall);
+    }
+    ptr::copy_nonoverlapping(bytes.as_ptr(), buf, bytes.len());
+    Ok(())
+}
+
+/// Static description of an error code
+#[no_mangle]
+pub extern "C" fn msc_error_message(error: MscError) -> *const c_char {
+    let message: &'static [u8] = match error {
+        MscError::Success => b"success\0",
+        MscError::NullPointer => b"null pointer\0",
+        MscError::InvalidFile => b"not a valid mscsb file\0",
+        MscError::OutOfRange => b"index out of range\0",
+        MscError::BadCommand => b"bad command\0",
+        MscError::BadString => b"string is not valid utf-8\0",
+        MscError::BufferTooSmall => b"buffer too small\0",
+        MscError::Panic => b"internal error\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+/// Parse `len` bytes into a new handle, free it with `msc_file_free`
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `file` must be writable
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_load(data: *const u8, len: usize, file: *mut *mut MscsbFile) -> MscError {
+    guard(|| {
+        let file = out(file)?;
+        *file = ptr::null_mut();
+        if data.is_null() {
+            return Err(MscError::NullPointer);
+        }
+        let parsed = MscsbFile::from_bytes(slice::from_raw_parts(data, len)).ok_or(MscError::InvalidFile)?;
+        *file = Box::into_raw(Box::new(parsed));
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `file` must be null or come from `msc_file_load`, and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_free(file: *mut MscsbFile) {
+    if !file.is_null() {
+        drop(Box::from_raw(file));
+    }
+}
+
+/// Serialize into `buf`. `len` is set to the size of the file even if `buf` is too
+/// small, so passing a null `buf` first gives the size to allocate.
+///
+/// # Safety
+/// `file` must be a valid handle, `buf` null or `cap` writable bytes and `len` writable
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_write(file: *const MscsbFile, buf: *mut u8, cap: usize, len: *mut usize) -> MscError {
+    guard(|| {
+        let mut bytes = vec![];
+        file_ref(file)?.write(&mut bytes);
+        copy_out(&bytes, buf, cap, len)
+    })
+}
+
+/// # Safety
+/// `file` must be a valid handle or null
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_script_count(file: *const MscsbFile) -> usize {
+    file.as_ref().map_or(0, |file| file.scripts.len())
+}
+
+/// # Safety
+/// `file` must be a valid handle and `start` and `end` writable
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_script_bounds(file: *const MscsbFile, script: usize, start: *mut u32, end: *mut u32) -> MscError {
+    guard(|| {
+        let script = file_ref(file)?.scripts.get(script).ok_or(MscError::OutOfRange)?;
+        *out(start)? = script.bounds.0;
+        *out(end)? = script.bounds.1;
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `file` must be a valid handle and `count` writable
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_command_count(file: *const MscsbFile, script: usize, count: *mut usize) -> MscError {
+    guard(|| {
+        let script = file_ref(file)?.scripts.get(script).ok_or(MscError::OutOfRange)?;
+        *out(count)? = script.commands.len();
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `file` must be a valid handle and `command` writable
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_get_command(file: *const MscsbFile, script: usize, index: usize, command: *mut MscCommand) -> MscError {
+    guard(|| {
+        let script = file_ref(file)?.scripts.get(script).ok_or(MscError::OutOfRange)?;
+        let found = script.commands.get(index).ok_or(MscError::OutOfRange)?;
+        *out(command)? = MscCommand::from_command(found);
+        Ok(())
+    })
+}
+
+/// Replace a command. Positions and branch targets are left for the caller to keep
+/// consistent, as with editing `Script::commands` directly.
+///
+/// # Safety
+/// `file` must be a valid handle and `command` readable
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_set_command(file: *mut MscsbFile, script: usize, index: usize, command: *const MscCommand) -> MscError {
+    guard(|| {
+        let command = command.as_ref().ok_or(MscError::NullPointer)?.to_command()?;
+        let script = file_mut(file)?.scripts.get_mut(script).ok_or(MscError::OutOfRange)?;
+        *script.commands.get_mut(index).ok_or(MscError::OutOfRange)? = command;
+        Ok(())
+    })
+}
+
+/// Insert a command before `index`, which may be the command count to append
+///
+/// # Safety
+/// `file` must be a valid handle and `command` readable
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_insert_command(file: *mut MscsbFile, script: usize, index: usize, command: *const MscCommand) -> MscError {
+    guard(|| {
+        let command = command.as_ref().ok_or(MscError::NullPointer)?.to_command()?;
+        let script = file_mut(file)?.scripts.get_mut(script).ok_or(MscError::OutOfRange)?;
+        if index > script.commands.len() {
+            return Err(MscError::OutOfRange);
+        }
+        script.commands.insert(index, command);
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `file` must be a valid handle
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_remove_command(file: *mut MscsbFile, script: usize, index: usize) -> MscError {
+    guard(|| {
+        let script = file_mut(file)?.scripts.get_mut(script).ok_or(MscError::OutOfRange)?;
+        if index >= script.commands.len() {
+            return Err(MscError::OutOfRange);
+        }
+        script.commands.remove(index);
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `file` must be a valid handle or null
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_string_count(file: *const MscsbFile) -> usize {
+    file.as_ref().map_or(0, |file| file.strings.len())
+}
+
+/// Copy a string into `buf` with a NUL terminator. `len` is set to the size needed
+/// including the terminator, see `msc_file_write`.
+///
+/// # Safety
+/// `file` must be a valid handle, `buf` null or `cap` writable bytes and `len` writable
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_get_string(file: *const MscsbFile, index: usize, buf: *mut c_char, cap: usize, len: *mut usize) -> MscError {
+    guard(|| {
+        let string = file_ref(file)?.strings.get(index).ok_or(MscError::OutOfRange)?;
+        let mut bytes = string.clone().into_bytes();
+        bytes.push(0);
+        copy_out(&bytes, buf as *mut u8, cap, len)
+    })
+}
+
+/// # Safety
+/// `file` must be a valid handle and `string` NUL terminated
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_set_string(file: *mut MscsbFile, index: usize, string: *const c_char) -> MscError {
+    guard(|| {
+        let string = c_str(string)?;
+        *file_mut(file)?.strings.get_mut(index).ok_or(MscError::OutOfRange)? = String::from(string);
+        Ok(())
+    })
+}
+
+/// Append a string, writing its index to `index` if it isn't null
+///
+/// # Safety
+/// `file` must be a valid handle, `string` NUL terminated and `index` null or writable
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_add_string(file: *mut MscsbFile, string: *const c_char, index: *mut usize) -> MscError {
+    guard(|| {
+        let string = c_str(string)?;
+        let file = file_mut(file)?;
+        file.strings.push(String::from(string));
+        if let Some(index) = index.as_mut() {
+            *index = file.strings.len() - 1;
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `file` must be a valid handle or null
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_entrypoint(file: *const MscsbFile) -> u32 {
+    file.as_ref().map_or(0, |file| file.entrypoint)
+}
+
+/// # Safety
+/// `file` must be a valid handle
+#[no_mangle]
+pub unsafe extern "C" fn msc_file_set_entrypoint(file: *mut MscsbFile, entrypoint: u32) -> MscError {
+    guard(|| {
+        file_mut(file)?.entrypoint = entrypoint;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn test_c_api() {
+        let mut bytes = vec![];
+        assemble("main:\n    Begin 0, 0\n    PushShort. 0x1\n    Sys 1, 0x10\n    End\n").unwrap().write(&mut bytes);
+        unsafe {
+            let mut file = ptr::null_mut();
+            assert_eq!(msc_file_load(b"nope".as_ptr(), 4, &mut file), MscError::InvalidFile);
+            assert!(file.is_null());
+            assert_eq!(msc_file_load(bytes.as_ptr(), bytes.len(), &mut file), MscError::Success);
+            assert_eq!(msc_file_script_count(file), 1);
+
+            let mut command = std::mem::zeroed::<MscCommand>();
+            assert_eq!(msc_file_get_command(file, 0, 4, &mut command), MscError::OutOfRange);
+            assert_eq!(msc_file_get_command(file, 0, 2, &mut command), MscError::Success);
+            assert_eq!(CStr::from_ptr(command.name.as_ptr()).to_str().unwrap(), "Sys");
+            assert_eq!((command.operand_count, command.operands), (2, [1, 0x10]));
+
+            command.operands[1] = 0x20;
+            assert_eq!(msc_file_set_command(file, 0, 2, &command), MscError::Success);
+            command.operand_count = 1;
+            assert_eq!(msc_file_set_command(file, 0, 2, &command), MscError::BadCommand);
+            let mut index = 0;
+            assert_eq!(msc_file_add_string(file, b"hi\0".as_ptr() as *const c_char, &mut index), MscError::Success);
+
+            let mut len = 0;
+            assert_eq!(msc_file_write(file, ptr::null_mut(), 0, &mut len), MscError::BufferTooSmall);
+            let mut written = vec![0u8; len];
+            assert_eq!(msc_file_write(file, written.as_mut_ptr(), len, &mut len), MscError::Success);
+            msc_file_free(file);
+
+            let reloaded = MscsbFile::from_bytes(&written).unwrap();
+            assert_eq!(reloaded.strings, vec!["hi"]);
+            assert_eq!(reloaded.scripts[0].commands[2].cmd.operands(), vec![1, 0x20]);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use super::{Cmd, MscsbFile, Script};
+use super::call_graph::address_pushes;
+use std::collections::HashMap;
+
+// FNV-1a, so hashes stay the same across Rust versions and can be saved to disk
+pub(crate) struct Fnv(pub u64);
+
+impl Fnv {
+    pub fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u32(&mut self, n: u32) {
+        self.write(&n.to_le_bytes());
+    }
+}
+
+/// The operand of a command that changes when code moves, in terms that don't
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Reloc {
+    None,
+    /// Branch to `offset` bytes past the start of the script. `index` is the command
+    /// there, or `commands.len()` for the end of the script, if it lands on one.
+    Branch { offset: u32, index: Option<usize> },
+    /// Pushes the address of this script for a call
+    Script(usize),
+}
+
+/// A command with its relocated operand left out, see `Reloc`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Key {
+    pub name: &'static str,
+    pub push_bit: bool,
+    pub operands: Vec<u32>,
+}
+
+impl Key {
+    pub fn new(cmd: &Cmd, push_bit: bool, relocated: bool) -> Key {
+        let operands = if relocated { vec![] } else { cmd.operands() };
+        Key { name: cmd.name(), push_bit, operands }
+    }
+
+    pub fn token(&self) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write(self.name.as_bytes());
+        hash.write(&[self.push_bit as u8]);
+        for &operand in self.operands.iter() {
+            hash.write_u32(operand);
+        }
+        hash.0
+    }
+}
+
+/// Hash of a single command. Operands are left out when `relocated`, for branches
+/// and script addresses that change when code moves.
+pub(crate) fn command_token(cmd: &Cmd, push_bit: bool, relocated: bool) -> u64 {
+    Key::new(cmd, push_bit, relocated).token()
+}
+
+/// Split every command of `script` into the part that stays the same when code moves
+/// and the part that doesn't. `script_ref` resolves the `PushInt` and `PushShort`
+/// addresses that calls jump to, other constants are kept as they are. The
+/// script hashes, signatures, diffs and patches all compare scripts through this.
+pub(crate) fn normalize<F: Fn(u32) -> Option<usize>>(script: &Script, script_ref: F) -> Vec<(Key, Reloc)> {
+    let positions: HashMap<u32, usize> =
+        script.iter()
+            .enumerate()
+            .map(|(i, c)| (c.position, i))
+            .chain(std::iter::once((script.bounds.1, script.commands.len())))
+            .collect();
+    let addresses = address_pushes(script);
+    script.iter()
+        .enumerate()
+        .map(|(i, command)| {
+            let reloc = match command.cmd.branch_target() {
+                Some(loc) => Reloc::Branch {
+                    offset: loc.wrapping_sub(script.bounds.0),
+                    index: positions.get(&loc).cloned(),
+                },
+                None => addresses.get(&i).and_then(|&val| script_ref(val)).map_or(Reloc::None, Reloc::Script),
+            };
+            (Key::new(&command.cmd, command.push_bit, reloc != Reloc::None), reloc)
+        })
+        .collect()
+}
+
+impl Script {
+    /// Hash of the script's instructions that doesn't change when the script is moved.
+    /// Branch targets are hashed relative to the start of the script.
+    pub fn content_hash(&self) -> u64 {
+        self.hash_with(|_| None)
+    }
+
+    fn hash_with<F: Fn(u32) -> Option<usize>>(&self, script_ref: F) -> u64 {
+        let mut hash = Fnv::new();
+        for (key, reloc) in normalize(self, script_ref) {
+            hash.write(key.name.as_bytes());
+            hash.write(&[key.push_bit as u8]);
+            match reloc {
+                Reloc::Branch { offset, .. } => hash.write_u32(offset),
+                // Script addresses move around between versions, so only note that
+                // one is pushed
+                Reloc::Script(_) => hash.write(b"script"),
+                Reloc::None => {
+                    for operand in key.operands {
+                        hash.write_u32(operand);
+                    }
+                }
+            }
+        }
+        hash.0
+    }
+}
+
+impl MscsbFile {
+    /// Like `Script::content_hash` but also ignores where the addresses calls jump to point
+    pub fn script_hash(&self, index: usize) -> u64 {
+        self.scripts[index].hash_with(|val| self.get_script_from_loc(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::test::script;
+
+    #[test]
+    fn test_script_hash_follows_call_addresses() {
+        let caller = |address: u16, stored: u32| script(0x10, &[
+            Cmd::PushShort { val: address }, Cmd::CallFunc { arg_count: 0 },
+            Cmd::PushInt { val: stored }, Cmd::Pop, Cmd::End,
+        ]);
+        let callee = |start: u32| script(start, &[Cmd::Nop, Cmd::End]);
+        let old = MscsbFile::new(vec![caller(0x20, 0x20), callee(0x20)], vec![], 0x10);
+        // The callee moved, and its PushShort address with it
+        let moved = MscsbFile::new(vec![caller(0x30, 0x20), callee(0x20), callee(0x30)], vec![], 0x10);
+        assert_eq!(old.script_hash(0), moved.script_hash(0));
+        // A stored number that happens to equal the new start is still a different number
+        let changed = MscsbFile::new(vec![caller(0x30, 0x30), callee(0x20), callee(0x30)], vec![], 0x10);
+        assert_ne!(old.script_hash(0), changed.script_hash(0));
+    }
+}
@@ -0,0 +1,124 @@
+use super::{Cmd, Command, MscsbFile, Script};
+use super::call_graph::address_pushes;
+use super::error::{Error, Result};
+use std::collections::HashMap;
+
+/// Replace the scripts of `file`, laying them out back to back from where the first
+/// script started. `scripts[i]` holds the surviving commands of script `i` with their
+/// old positions, or `None` to drop the script. Branch targets, the `PushInt` or
+/// `PushShort` addresses of calls and the entrypoint are moved to match, with targets
+/// whose command was removed moving to the next surviving command. Other constants are
+/// left alone even if they equal a script's start. Fails without touching `file` if a
+/// `PushShort` address no longer fits in 16 bits.
+pub(crate) fn relayout(file: &mut MscsbFile, scripts: Vec<Option<Vec<Command>>>) -> Result<()> {
+    let mut moved: HashMap<u32, u32> = HashMap::new();
+    let mut starts: HashMap<u32, u32> = HashMap::new();
+    let mut position = file.scripts.first().map(|s| s.bounds.0).unwrap_or(0x10);
+    let mut laid_out = Vec::with_capacity(scripts.len());
+    let mut addresses = Vec::with_capacity(scripts.len());
+    for (old, new) in file.scripts.iter().zip(scripts) {
+        let mut script = match new {
+            Some(commands) => Script { commands, bounds: old.bounds },
+            None => continue,
+        };
+        // Found before anything moves, while positions and branch targets still agree
+        addresses.push(address_pushes(&script));
+        let commands = &mut script.commands;
+        let start = position;
+        let mut new_positions = Vec::with_capacity(commands.len());
+        for command in commands.iter() {
+            new_positions.push(position);
+            position += command.cmd.size();
+        }
+        // Every old position in the script maps to the first survivor at or after it
+        let mut next = 0;
+        for command in old.iter() {
+            while next < commands.len() && commands[next].position < command.position {
+                next += 1;
+            }
+            moved.insert(command.position, new_positions.get(next).cloned().unwrap_or(position));
+        }
+        moved.insert(old.bounds.1, position);
+        moved.insert(old.bounds.0, start);
+        starts.insert(old.bounds.0, start);
+        for (command, new_position) in commands.iter_mut().zip(new_positions) {
+            command.position = new_position;
+        }
+        script.bounds = (start, position);
+        laid_out.push(script);
+    }
+
+    for (script, addresses) in laid_out.iter_mut().zip(addresses) {
+        for (i, command) in script.commands.iter_mut().enumerate() {
+            if let Some(loc) = command.cmd.branch_target() {
+                if let Some(&new) = moved.get(&loc) {
+                    command.cmd.set_branch_target(new);
+                }
+            }
+            if !addresses.contains_key(&i) {
+                continue;
+            }
+            match command.cmd {
+                Cmd::PushInt { ref mut val } => {
+                    if let Some(&new) = starts.get(val) {
+                        *val = new;
+                    }
+                }
+                Cmd::PushShort { ref mut val } => {
+                    if let Some(&new) = starts.get(&(*val as u32)) {
+                        if new > 0xFFFF {
+                            return Err(Error::Format(format!(
+                                "PushShort at 0x{:X} refers to a script moved to 0x{:X}, which needs a PushInt",
+                                command.position, new
+                            )));
+                        }
+                        *val = new as u16;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some(&entrypoint) = moved.get(&file.entrypoint) {
+        file.entrypoint = entrypoint;
+    }
+    file.scripts = laid_out;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn test_relayout_short_addresses() {
+        let mut file = assemble("
+main:
+    Begin 0, 0
+    PushShort. 0x1B
+    CallFunc 0
+    End
+other:
+    End
+").unwrap();
+        assert_eq!(file.scripts[1].bounds.0, 0x1B);
+
+        // Dropping the Begin moves `other` down, and the PushShort address of the call with it
+        let mut main = file.scripts[0].commands.clone();
+        main.remove(0);
+        let scripts = vec![Some(main), Some(file.scripts[1].commands.clone())];
+        relayout(&mut file, scripts).unwrap();
+        assert_eq!(file.scripts[1].bounds.0, 0x16);
+        assert!(matches!(file.scripts[0].commands[0].cmd, Cmd::PushShort { val: 0x16 }));
+
+        // Growing main pushes `other` past what a PushShort can hold
+        let mut main = file.scripts[0].commands.clone();
+        let filler = Command { cmd: Cmd::PushInt { val: 0 }, push_bit: false, position: 0x10 };
+        main.splice(0..0, std::iter::repeat_n(filler, 0x3334));
+        let scripts = vec![Some(main), Some(file.scripts[1].commands.clone())];
+        let before = file.disassemble();
+        assert!(relayout(&mut file, scripts).is_err());
+        assert_eq!(file.disassemble(), before);
+    }
+}
@@ -7,11 +7,67 @@
 
 #[macro_use] extern crate nom;
 extern crate byteorder;
+extern crate msc_macros;
+#[cfg(feature = "serde")] extern crate serde;
+#[cfg(feature = "serde")] extern crate serde_json;
+#[cfg(feature = "serde")] extern crate toml;
 
+mod macros;
+mod error;
 mod mscb_file;
-pub use mscb_file::MscsbFile;
+mod stack;
+mod mnemonic;
+mod call_graph;
+mod xref;
+mod hash;
+mod symbols;
+mod sys_catalog;
+mod disasm;
+mod validate;
+mod printf;
+mod strings;
+mod locals;
+mod dataflow;
+mod diff;
+mod asm;
+mod builder;
+mod layout;
+mod dce;
+mod signature;
+mod search;
+mod corpus;
+mod patch;
+mod optimize;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "capi")]
+mod ffi;
+pub use error::{Error, Result};
+pub use mscb_file::{Endian, InstructionIter, MscsbFile, MscsbHeader, MscsbView, Platform, HEADER_SIZE, MAGIC};
+pub use call_graph::{CallGraph, CallSite, CallTarget};
+pub use xref::{XrefIndex, XrefKind, XrefSite, XrefTarget};
+pub use sys_catalog::{ArgType, Returns, SysCall, SysCatalog};
+pub use symbols::{ScriptKey, ScriptSymbol, Symbol, SymbolMap};
+pub use disasm::Disassembler;
+pub use validate::{Diagnostic, Severity, Validator};
+pub use printf::{FormatSpec, FormatString, ValueType};
+pub use dataflow::{Dataflow, Definition, Variable};
+pub use diff::{DiffOp, FileDiff, ScriptChange};
+pub use asm::{assemble, assemble_script};
+pub use builder::{MscsbFileBuilder, Operand, ScriptBuilder};
+pub use optimize::{OptimizeStats, Optimizer};
+pub use dce::DeadCodeReport;
+pub use signature::{ScriptMatch, Signature, SignatureMatcher};
+pub use search::{DirSearch, Element, InsnPattern, OperandPattern, Pattern, SearchMatch};
+pub use corpus::{CorpusEntry, MscCorpus};
+#[doc(hidden)]
+pub use msc_macros::script as __msc_script;
+pub use patch::{Anchor, Conflict, Patch, PatchCommand, PatchEdit, Reference, ScriptId, ScriptRef};
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Cmd {
     Nop, // 0
     Unk1, // 1
@@ -119,10 +175,10 @@ pub enum Cmd {
     },  // 0x2F
     CallFunc2 {
         arg_count: u8,
-    }, // 0x2F
+    }, // 0x30
     CallFunc3 {
         arg_count: u8,
-    }, // 0x2F
+    }, // 0x31
     Push,    // 0x32
     Pop,     // 0x33
     If {
@@ -185,6 +241,7 @@ pub enum Cmd {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Command {
     pub cmd: Cmd,
     pub push_bit: bool,
@@ -192,13 +249,15 @@ pub struct Command {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "serde_impl::RawScript"))]
 pub struct Script {
     pub commands: Vec<Command>,
     pub bounds: (u32, u32),
 }
 
 impl Script {
-    pub fn iter(&self) -> std::slice::Iter<Command> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Command> {
         self.commands.iter()
     }
 }
@@ -207,6 +266,17 @@ impl Script {
 mod test {
     use super::*;
 
+    // Lays commands out one byte apart, enough for analyses that only look at positions
+    pub fn script(start: u32, cmds: &[Cmd]) -> Script {
+        let mut position = start;
+        let commands = cmds.iter().map(|&cmd| {
+            let command = Command { cmd, push_bit: cmd.produces_value(), position };
+            position += 1;
+            command
+        }).collect();
+        Script { commands, bounds: (start, position) }
+    }
+
     #[test]
     fn test_parser() {
         let pikachu = MscsbFile::open("/home/jam/dev/msc/pikachu.mscsb").unwrap();
@@ -216,5 +286,95 @@ mod test {
             println!("{:?}", c);
         }
     }
+
+    #[test]
+    fn test_roundtrip() {
+        let command = |cmd, push_bit, position| Command { cmd, push_bit, position };
+        let file = MscsbFile::new(
+            vec![
+                Script {
+                    commands: vec![
+                        command(Cmd::Begin { arg_count: 0, var_count: 0 }, false, 0x10),
+                        command(Cmd::PushInt { val: 0x25 }, true, 0x15),
+                        command(Cmd::CallFunc2 { arg_count: 0 }, false, 0x1A),
+                        command(Cmd::Jump { loc: 0x24 }, false, 0x1C),
+                        command(Cmd::Nop, false, 0x21),
+                        command(Cmd::Nop, false, 0x22),
+                        command(Cmd::Nop, false, 0x23),
+                        command(Cmd::End, false, 0x24),
+                    ],
+                    bounds: (0x10, 0x25),
+                },
+                Script {
+                    commands: vec![command(Cmd::End, false, 0x25)],
+                    bounds: (0x25, 0x26),
+                },
+            ],
+            vec![String::from("hello"), String::from("sixteen chars!!!")],
+            0x10,
+        );
+        let path = std::env::temp_dir().join("msc_test_roundtrip.mscsb");
+        file.write_to_file(&path).unwrap();
+        let parsed = MscsbFile::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(parsed.entrypoint, 0x10);
+        assert_eq!(parsed.strings, file.strings);
+        assert_eq!(parsed.scripts.len(), 2);
+        for (parsed, script) in parsed.scripts.iter().zip(file.scripts.iter()) {
+            assert_eq!(parsed.bounds, script.bounds);
+            let positions: Vec<(u32, bool)> = parsed.iter().map(|c| (c.position, c.push_bit)).collect();
+            let expected: Vec<(u32, bool)> = script.iter().map(|c| (c.position, c.push_bit)).collect();
+            assert_eq!(positions, expected);
+        }
+        assert!(matches!(parsed.scripts[0].commands[2].cmd, Cmd::CallFunc2 { arg_count: 0 }));
+        assert_eq!(parsed.get_script_from_loc(0x25), Some(1));
+    }
+
+    // The analyses are mostly tested on fixtures with made up positions, so check they
+    // see the same thing in a file that went through the writer and parser
+    #[test]
+    fn test_analyses_on_parsed_file() {
+        let file = assemble(r#"
+.string "%d\n"
+main:
+    Begin 1, 1
+    PushShort. 0x0
+    PushVar. 0, 0x0
+    PrintF 2
+    PushInt. helper
+    CallFunc. 0
+    SetVar 1, 0x3
+    PushVar. 1, 0x3
+    IfNot loc_end
+    PushInt. helper
+    CallFunc 0
+loc_end:
+    End
+helper:
+    Begin 0, 0
+    PushShort. 0x1
+    Return6
+"#).unwrap();
+        let mut bytes = vec![];
+        file.write(&mut bytes);
+        let parsed = MscsbFile::from_bytes(&bytes).unwrap();
+
+        let positions = |file: &MscsbFile| -> Vec<Vec<u32>> {
+            file.iter().map(|s| s.iter().map(|c| c.position).collect()).collect()
+        };
+        assert_eq!(positions(&parsed), positions(&file));
+        assert_eq!(parsed.call_graph().edges(), vec![(0, 1)]);
+        assert_eq!(parsed.call_graph().edges(), file.call_graph().edges());
+        assert_eq!(parsed.call_graph().unresolved().count(), 0);
+        let xrefs = parsed.xrefs();
+        assert_eq!(xrefs.targets(), file.xrefs().targets());
+        assert_eq!(xrefs.refs_to(xref::XrefTarget::String(0)).len(), 1);
+        assert_eq!(xrefs.refs_to(xref::XrefTarget::Script(1)).len(), 4);
+        assert_eq!(parsed.disassemble(), file.disassemble());
+        assert!(parsed.disassemble().contains("IfNot loc_"));
+        assert!(parsed.diff(&file).is_empty());
+        assert!(parsed.validate().is_empty());
+    }
 }
 
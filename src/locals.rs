@@ -0,0 +1,105 @@
+use super::{Cmd, MscsbFile, Script};
+use std::collections::HashMap;
+
+impl Script {
+    /// Local `var_num`s the script's commands address, sorted
+    pub fn used_locals(&self) -> Vec<u16> {
+        let mut used: Vec<u16> = self.iter()
+            .filter_map(|c| match c.cmd.variable() {
+                Some((0, var_num)) => Some(var_num),
+                _ => None,
+            })
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+        used
+    }
+
+    /// Renumber locals densely in order of first use and shrink `Begin`'s `var_count` to
+    /// match. Argument slots keep their numbers whether they're used or not. Returns the
+    /// `(old, new)` numbers of locals that moved. Scripts that don't start with `Begin`
+    /// are left alone.
+    pub fn compact_locals(&mut self) -> Vec<(u16, u16)> {
+        let arg_count = match self.commands.first().map(|c| c.cmd) {
+            Some(Cmd::Begin { arg_count, .. }) => arg_count,
+            _ => return vec![],
+        };
+        let mut renumbered: HashMap<u16, u16> = HashMap::new();
+        let mut moved = vec![];
+        let mut next = arg_count;
+        for command in self.commands.iter_mut() {
+            let var_num = match command.cmd.variable_mut() {
+                Some((&mut 0, var_num)) if *var_num >= arg_count => var_num,
+                _ => continue,
+            };
+            let new = *renumbered.entry(*var_num).or_insert_with(|| {
+                next += 1;
+                next - 1
+            });
+            if new != *var_num {
+                moved.push((*var_num, new));
+                *var_num = new;
+            }
+        }
+        moved.sort_unstable();
+        moved.dedup();
+        if let Cmd::Begin { ref mut var_count, .. } = self.commands[0].cmd {
+            *var_count = next;
+        }
+        moved
+    }
+}
+
+impl MscsbFile {
+    /// `Script::compact_locals` on every script, returning how many local slots were freed
+    pub fn compact_locals(&mut self) -> usize {
+        let mut freed = 0;
+        for script in self.scripts.iter_mut() {
+            let before = match script.commands.first().map(|c| c.cmd) {
+                Some(Cmd::Begin { var_count, .. }) => var_count,
+                _ => continue,
+            };
+            script.compact_locals();
+            if let Cmd::Begin { var_count, .. } = script.commands[0].cmd {
+                freed += before.saturating_sub(var_count) as usize;
+            }
+        }
+        freed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{assemble, Cmd};
+
+    #[test]
+    fn test_compact_locals() {
+        let mut file = assemble("
+main:
+    Begin 1, 9
+    PushVar. 0, 0x0
+    SetVar 0, 0x7
+    PushVar. 1, 0x7
+    SetVar 0, 0x4
+    PushVar. 0, 0x4
+    AddVarByF 0, 0x7
+    IncI 0, 0x0
+    End
+other:
+    Begin 2, 2
+    PushVar. 0, 0x1
+    Return8
+").unwrap();
+        assert_eq!(file.scripts[0].used_locals(), vec![0, 4, 7]);
+        assert_eq!(file.compact_locals(), 6);
+        let main = &file.scripts[0];
+        assert!(matches!(main.commands[0].cmd, Cmd::Begin { arg_count: 1, var_count: 3 }));
+        let vars: Vec<Option<(u8, u16)>> = main.iter().skip(1).map(|c| c.cmd.variable()).collect();
+        assert_eq!(vars, vec![
+            Some((0, 0)), Some((0, 1)), Some((1, 7)), Some((0, 2)), Some((0, 2)), Some((0, 1)), Some((0, 0)), None,
+        ]);
+        assert!(matches!(file.scripts[1].commands[0].cmd, Cmd::Begin { arg_count: 2, var_count: 2 }));
+        assert!(file.scripts[0].clone().compact_locals().is_empty());
+        assert!(file.validate().is_empty());
+    }
+}
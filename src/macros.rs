@@ -0,0 +1,89 @@
+/// Build a `Script` from assembly written inline in Rust, using the same mnemonics
+/// and operand syntax as `Disassembler` output. `;` comments out the rest of its
+/// line, so script listings can be pasted in as they are. The script is laid out at 0x10.
+///
+/// Operands are integer literals, labels for branches, or parenthesized `u32`
+/// expressions. Expressions for operands narrower than 32 bits must be constant.
+/// Unknown mnemonics, wrong operand counts, operands too wide for their field and
+/// unknown or duplicate labels are all compile errors:
+///
+/// ```compile_fail
+/// msc::msc! {
+///     Jump loc_nowhere
+/// };
+/// ```
+///
+/// ```compile_fail
+/// msc::msc! {
+///     PushShort. 0x10000
+/// };
+/// ```
+///
+/// ```compile_fail
+/// msc::msc! {
+///     PushVar. 0, (0x8000 * 2)
+/// };
+/// ```
+#[macro_export]
+macro_rules! msc {
+    ($($body:tt)*) => {
+        $crate::__msc_script!($crate; $($body)*)
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{assemble_script, Disassembler, MscsbFile};
+
+    #[test]
+    fn test_msc_macro() {
+        let script = crate::msc! {
+            Begin 0, 1
+            PushShort. 0x0
+            SetVar 0, 0x0
+        loc_top:
+            PushVar. 0, 0x0
+            PushShort. 10
+            LessThan.
+            IfNot loc_end
+            PushInt. (0x1000 + 0x20)
+            Sys. 1, 0x2F
+            IncI 0, 0x0
+            Jump loc_top
+        loc_end:
+            End
+        };
+        assert_eq!(script.bounds.0, 0x10);
+
+        let file = MscsbFile::new(vec![script], vec![], 0x10);
+        let text = Disassembler::new(&file).script(0);
+        let body: String = text.lines().skip(1).map(|line| format!("{}\n", line)).collect();
+        let reassembled = assemble_script(&body, 0x10).unwrap();
+        assert_eq!(format!("{:?}", reassembled.commands), format!("{:?}", file.scripts[0].commands));
+        assert!(text.contains("PushInt. 0x1020"));
+    }
+
+    #[test]
+    fn test_msc_macro_pasted_listing() {
+        let script = crate::msc! {
+script_0:                   ; 0x10 - 0x33 init - sets things up
+    Begin 0, 1
+    PushShort. 0x0          ; comments can hold "strings" and (parens)
+    SetVar 0, 0x0
+loc_1C:
+    PushVar. 0, 0x0
+    PushShort. (0x4 * 2)
+    LessThan.
+    IfNot loc_32
+    IncI 0, 0x0
+    Jump loc_1C
+loc_32:
+    End
+        };
+        let file = MscsbFile::new(vec![script], vec![], 0x10);
+        let text = Disassembler::new(&file).script(0);
+        assert!(text.starts_with("script_0:"));
+        assert!(text.contains("loc_1C:\n") && text.contains("IfNot loc_32\n"), "{}", text);
+        assert!(text.contains("PushShort. 0x8\n"));
+    }
+}
@@ -0,0 +1,225 @@
+use super::Cmd;
+
+impl Cmd {
+    /// Mnemonic used by the text disassembler
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cmd::Nop => "Nop",
+            Cmd::Unk1 => "Unk1",
+            Cmd::Begin { .. } => "Begin",
+            Cmd::End => "End",
+            Cmd::Jump { .. } => "Jump",
+            Cmd::Jump5 { .. } => "Jump5",
+            Cmd::Return6 => "Return6",
+            Cmd::Return7 => "Return7",
+            Cmd::Return8 => "Return8",
+            Cmd::Return9 => "Return9",
+            Cmd::PushInt { .. } => "PushInt",
+            Cmd::PushVar { .. } => "PushVar",
+            Cmd::ErrorC => "ErrorC",
+            Cmd::PushShort { .. } => "PushShort",
+            Cmd::AddI => "AddI",
+            Cmd::SubI => "SubI",
+            Cmd::MultI => "MultI",
+            Cmd::DivI => "DivI",
+            Cmd::ModI => "ModI",
+            Cmd::NegI => "NegI",
+            Cmd::IncI { .. } => "IncI",
+            Cmd::DecI { .. } => "DecI",
+            Cmd::AndI => "AndI",
+            Cmd::OrI => "OrI",
+            Cmd::NotI => "NotI",
+            Cmd::XorI => "XorI",
+            Cmd::ShiftL => "ShiftL",
+            Cmd::ShiftR => "ShiftR",
+            Cmd::SetVar { .. } => "SetVar",
+            Cmd::AddVarBy { .. } => "AddVarBy",
+            Cmd::SubVarBy { .. } => "SubVarBy",
+            Cmd::MultVarBy { .. } => "MultVarBy",
+            Cmd::DivVarBy { .. } => "DivVarBy",
+            Cmd::ModVarBy { .. } => "ModVarBy",
+            Cmd::AndVarBy { .. } => "AndVarBy",
+            Cmd::OrVarBy { .. } => "OrVarBy",
+            Cmd::XorVarBy { .. } => "XorVarBy",
+            Cmd::Equals => "Equals",
+            Cmd::NotEquals => "NotEquals",
+            Cmd::LessThan => "LessThan",
+            Cmd::LessOrEqual => "LessOrEqual",
+            Cmd::Greater => "Greater",
+            Cmd::GreaterOrEqual => "GreaterOrEqual",
+            Cmd::Not => "Not",
+            Cmd::PrintF { .. } => "PrintF",
+            Cmd::Sys { .. } => "Sys",
+            Cmd::Try { .. } => "Try",
+            Cmd::CallFunc { .. } => "CallFunc",
+            Cmd::CallFunc2 { .. } => "CallFunc2",
+            Cmd::CallFunc3 { .. } => "CallFunc3",
+            Cmd::Push => "Push",
+            Cmd::Pop => "Pop",
+            Cmd::If { .. } => "If",
+            Cmd::IfNot { .. } => "IfNot",
+            Cmd::Else { .. } => "Else",
+            Cmd::Error37 => "Error37",
+            Cmd::IntToFloat { .. } => "IntToFloat",
+            Cmd::FloatToInt { .. } => "FloatToInt",
+            Cmd::AddF => "AddF",
+            Cmd::SubF => "SubF",
+            Cmd::MultF => "MultF",
+            Cmd::DivF => "DivF",
+            Cmd::NegF => "NegF",
+            Cmd::IncF { .. } => "IncF",
+            Cmd::DecF { .. } => "DecF",
+            Cmd::VarSetF { .. } => "VarSetF",
+            Cmd::AddVarByF { .. } => "AddVarByF",
+            Cmd::SubVarByF { .. } => "SubVarByF",
+            Cmd::MultVarByF { .. } => "MultVarByF",
+            Cmd::DivVarByF { .. } => "DivVarByF",
+            Cmd::EqualsF => "EqualsF",
+            Cmd::NotEqualsF => "NotEqualsF",
+            Cmd::LessThanF => "LessThanF",
+            Cmd::LessOrEqualF => "LessOrEqualF",
+            Cmd::GreaterF => "GreaterF",
+            Cmd::GreaterOrEqualF => "GreaterOrEqualF",
+            Cmd::Error4C => "Error4C",
+            Cmd::Exit => "Exit",
+        }
+    }
+
+    /// Operand values in field order
+    pub fn operands(&self) -> Vec<u32> {
+        match *self {
+            Cmd::Begin { arg_count, var_count } => vec![arg_count as u32, var_count as u32],
+            Cmd::Jump { loc } => vec![loc],
+            Cmd::Jump5 { loc } => vec![loc],
+            Cmd::PushInt { val } => vec![val],
+            Cmd::PushVar { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::PushShort { val } => vec![val as u32],
+            Cmd::IncI { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::DecI { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::SetVar { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::AddVarBy { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::SubVarBy { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::MultVarBy { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::DivVarBy { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::ModVarBy { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::AndVarBy { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::OrVarBy { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::XorVarBy { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::PrintF { arg_count } => vec![arg_count as u32],
+            Cmd::Sys { arg_count, sys_num } => vec![arg_count as u32, sys_num as u32],
+            Cmd::Try { loc } => vec![loc],
+            Cmd::CallFunc { arg_count } => vec![arg_count as u32],
+            Cmd::CallFunc2 { arg_count } => vec![arg_count as u32],
+            Cmd::CallFunc3 { arg_count } => vec![arg_count as u32],
+            Cmd::If { loc } => vec![loc],
+            Cmd::IfNot { loc } => vec![loc],
+            Cmd::Else { loc } => vec![loc],
+            Cmd::IntToFloat { stack_pos } => vec![stack_pos as u32],
+            Cmd::FloatToInt { stack_pos } => vec![stack_pos as u32],
+            Cmd::IncF { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::DecF { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::VarSetF { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::AddVarByF { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::SubVarByF { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::MultVarByF { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            Cmd::DivVarByF { var_type, var_num } => vec![var_type as u32, var_num as u32],
+            _ => vec![],
+        }
+    }
+
+    /// Build a command from its mnemonic and operands in field order. Returns `None`
+    /// for unknown mnemonics, the wrong number of operands or operands that don't fit.
+    pub fn from_parts(name: &str, operands: &[u32]) -> Option<Cmd> {
+        let mut padded = [0u32; 2];
+        for (slot, &operand) in padded.iter_mut().zip(operands.iter()) {
+            *slot = operand;
+        }
+        let op = |i: usize| padded[i];
+        let cmd = match name {
+            "Nop" => Cmd::Nop,
+            "Unk1" => Cmd::Unk1,
+            "Begin" => Cmd::Begin { arg_count: op(0) as u16, var_count: op(1) as u16 },
+            "End" => Cmd::End,
+            "Jump" => Cmd::Jump { loc: op(0) },
+            "Jump5" => Cmd::Jump5 { loc: op(0) },
+            "Return6" => Cmd::Return6,
+            "Return7" => Cmd::Return7,
+            "Return8" => Cmd::Return8,
+            "Return9" => Cmd::Return9,
+            "PushInt" => Cmd::PushInt { val: op(0) },
+            "PushVar" => Cmd::PushVar { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "ErrorC" => Cmd::ErrorC,
+            "PushShort" => Cmd::PushShort { val: op(0) as u16 },
+            "AddI" => Cmd::AddI,
+            "SubI" => Cmd::SubI,
+            "MultI" => Cmd::MultI,
+            "DivI" => Cmd::DivI,
+            "ModI" => Cmd::ModI,
+            "NegI" => Cmd::NegI,
+            "IncI" => Cmd::IncI { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "DecI" => Cmd::DecI { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "AndI" => Cmd::AndI,
+            "OrI" => Cmd::OrI,
+            "NotI" => Cmd::NotI,
+            "XorI" => Cmd::XorI,
+            "ShiftL" => Cmd::ShiftL,
+            "ShiftR" => Cmd::ShiftR,
+            "SetVar" => Cmd::SetVar { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "AddVarBy" => Cmd::AddVarBy { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "SubVarBy" => Cmd::SubVarBy { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "MultVarBy" => Cmd::MultVarBy { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "DivVarBy" => Cmd::DivVarBy { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "ModVarBy" => Cmd::ModVarBy { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "AndVarBy" => Cmd::AndVarBy { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "OrVarBy" => Cmd::OrVarBy { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "XorVarBy" => Cmd::XorVarBy { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "Equals" => Cmd::Equals,
+            "NotEquals" => Cmd::NotEquals,
+            "LessThan" => Cmd::LessThan,
+            "LessOrEqual" => Cmd::LessOrEqual,
+            "Greater" => Cmd::Greater,
+            "GreaterOrEqual" => Cmd::GreaterOrEqual,
+            "Not" => Cmd::Not,
+            "PrintF" => Cmd::PrintF { arg_count: op(0) as u8 },
+            "Sys" => Cmd::Sys { arg_count: op(0) as u8, sys_num: op(1) as u8 },
+            "Try" => Cmd::Try { loc: op(0) },
+            "CallFunc" => Cmd::CallFunc { arg_count: op(0) as u8 },
+            "CallFunc2" => Cmd::CallFunc2 { arg_count: op(0) as u8 },
+            "CallFunc3" => Cmd::CallFunc3 { arg_count: op(0) as u8 },
+            "Push" => Cmd::Push,
+            "Pop" => Cmd::Pop,
+            "If" => Cmd::If { loc: op(0) },
+            "IfNot" => Cmd::IfNot { loc: op(0) },
+            "Else" => Cmd::Else { loc: op(0) },
+            "Error37" => Cmd::Error37,
+            "IntToFloat" => Cmd::IntToFloat { stack_pos: op(0) as u8 },
+            "FloatToInt" => Cmd::FloatToInt { stack_pos: op(0) as u8 },
+            "AddF" => Cmd::AddF,
+            "SubF" => Cmd::SubF,
+            "MultF" => Cmd::MultF,
+            "DivF" => Cmd::DivF,
+            "NegF" => Cmd::NegF,
+            "IncF" => Cmd::IncF { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "DecF" => Cmd::DecF { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "VarSetF" => Cmd::VarSetF { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "AddVarByF" => Cmd::AddVarByF { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "SubVarByF" => Cmd::SubVarByF { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "MultVarByF" => Cmd::MultVarByF { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "DivVarByF" => Cmd::DivVarByF { var_type: op(0) as u8, var_num: op(1) as u16 },
+            "EqualsF" => Cmd::EqualsF,
+            "NotEqualsF" => Cmd::NotEqualsF,
+            "LessThanF" => Cmd::LessThanF,
+            "LessOrEqualF" => Cmd::LessOrEqualF,
+            "GreaterF" => Cmd::GreaterF,
+            "GreaterOrEqualF" => Cmd::GreaterOrEqualF,
+            "Error4C" => Cmd::Error4C,
+            "Exit" => Cmd::Exit,
+            _ => return None,
+        };
+        if cmd.operands() == operands {
+            Some(cmd)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use super::{Endian, MscsbFile};
+use super::parser::take_header;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Size of the header, the script data starts right after it
+pub const HEADER_SIZE: usize = 0x30;
+
+/// The first 0x10 bytes of every file, the magic followed by version bytes. No platform
+/// is known to use different ones.
+pub const MAGIC: &[u8; 0x10] = b"\xB2\xAC\xBC\xBA\xE6\x90\x32\x01\xFD\x02\x00\x00\x00\x00\x00\x00";
+
+// The header is little endian on every platform, only the bytecode differs
+pub(crate) const HEADER_ENDIAN: Endian = Endian::Little;
+
+/// The fixed size header at the start of every file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MscsbHeader {
+    /// Magic and version bytes, see `MAGIC`
+    pub magic: [u8; 0x10],
+    /// Size of the script data, including the 0x10 bytes of padding before the first script
+    pub script_data_size: u32,
+    pub entrypoint: u32,
+    pub script_count: u32,
+    /// Unknown, always 0x16 in files written by this crate
+    pub unk: u32,
+    /// Space given to each string in the string table, including its null
+    pub string_size: u32,
+    pub string_count: u32,
+}
+
+impl MscsbHeader {
+    /// Read just the header from the start of a file, without touching the bytecode
+    pub fn peek(bytes: &[u8]) -> Option<MscsbHeader> {
+        take_header(bytes, HEADER_ENDIAN.nom()).ok().map(|(_, header)| header)
+    }
+
+    /// Whether the script offset table and string table could fit in a file of `len`
+    /// bytes. Checked before either is read, so a bad header can't make us allocate
+    /// for billions of entries.
+    pub(crate) fn fits(&self, len: usize) -> bool {
+        let len = len as u64;
+        (self.string_size > 0 || self.string_count == 0) &&
+            self.string_size as u64 * self.string_count as u64 <= len &&
+            self.script_count as u64 * 4 <= len
+    }
+
+    /// Read only the first `HEADER_SIZE` bytes of a file
+    pub fn open<P: AsRef<Path>>(path: P) -> Option<MscsbHeader> {
+        let mut buffer = [0; HEADER_SIZE];
+        File::open(path).ok()?.read_exact(&mut buffer).ok()?;
+        MscsbHeader::peek(&buffer)
+    }
+}
+
+impl MscsbFile {
+    /// The header `write` produces for this file
+    pub fn header(&self) -> MscsbHeader {
+        let commands: u32 = self.iter().flat_map(|s| s.iter()).map(|c| c.cmd.size()).sum();
+        MscsbHeader {
+            magic: *MAGIC,
+            script_data_size: 0x10 + commands,
+            entrypoint: self.entrypoint,
+            script_count: self.scripts.len() as u32,
+            unk: 0x16, // oof ouch magic number
+            string_size: self.strings
+                .iter()
+                .map(|s| (s.len() as u32 + 0x10) & !0xF) // Room for the null, rounded to 0x10
+                .max()
+                .unwrap_or(0),
+            string_count: self.strings.len() as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn test_header() {
+        let file = assemble(r#"
+.string "hello"
+.string "a longer string here"
+main:
+    Begin 0, 0
+    End
+other:
+    Return6
+"#).unwrap();
+        let header = file.header();
+        assert_eq!(&header.magic, MAGIC);
+        assert_eq!((header.script_data_size, header.entrypoint, header.script_count), (0x17, 0x10, 2));
+        assert_eq!((header.unk, header.string_size, header.string_count), (0x16, 0x20, 2));
+
+        let mut bytes = vec![];
+        file.write(&mut bytes);
+        assert_eq!(MscsbHeader::peek(&bytes[..HEADER_SIZE]), Some(header));
+        assert_eq!(MscsbHeader::peek(&bytes[..HEADER_SIZE - 1]), None);
+        assert_eq!(MscsbHeader::peek(b"\0not an mscsb file, but long enough to hold a header"), None);
+    }
+}
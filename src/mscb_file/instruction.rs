@@ -0,0 +1,141 @@
+use super::{MscsbView, Platform};
+use super::parser::take_cmd;
+use super::writer::WriteImpl;
+use super::super::Command;
+use crate::error::{Error, Result};
+use std::io::Write;
+
+impl Command {
+    /// Decode the Wii U instruction at the start of `bytes`, which sits at `position`
+    /// in the script data. Returns the command and the number of bytes it took.
+    pub fn decode(bytes: &[u8], position: u32) -> Result<(Command, usize)> {
+        Command::decode_for(bytes, position, Platform::WiiU)
+    }
+
+    pub fn decode_for(bytes: &[u8], position: u32, platform: Platform) -> Result<(Command, usize)> {
+        match take_cmd(bytes, position as usize, platform.bytecode_endian().nom()) {
+            Ok((rest, command)) => Ok((command, bytes.len() - rest.len())),
+            Err(nom::Err::Incomplete(_)) => {
+                Err(Error::Format(format!("instruction at 0x{:X} is cut off", position)))
+            }
+            Err(_) => Err(Error::Format(format!(
+                "unknown opcode 0x{:X} at 0x{:X}", bytes[0] & 0x7F, position
+            ))),
+        }
+    }
+
+    /// Encode for the Wii U, `Cmd::size` bytes are written
+    pub fn encode<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.encode_for(writer, Platform::WiiU)
+    }
+
+    pub fn encode_for<W: Write>(&self, writer: &mut W, platform: Platform) -> Result<()> {
+        let mut bytes = Vec::with_capacity(self.cmd.size() as usize);
+        WriteImpl::write(self, &mut bytes, platform.bytecode_endian().is_big());
+        writer.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+/// Decodes instructions one at a time from raw bytecode with no file around it.
+/// Stops after the first error.
+#[derive(Debug, Clone)]
+pub struct InstructionIter<'a> {
+    bytes: &'a [u8],
+    position: u32,
+    platform: Platform,
+    failed: bool,
+}
+
+impl<'a> InstructionIter<'a> {
+    /// Wii U bytecode whose first byte is at `position`
+    pub fn new(bytes: &'a [u8], position: u32) -> InstructionIter<'a> {
+        InstructionIter::new_for(bytes, position, Platform::WiiU)
+    }
+
+    pub fn new_for(bytes: &'a [u8], position: u32, platform: Platform) -> InstructionIter<'a> {
+        InstructionIter { bytes, position, platform, failed: false }
+    }
+
+    /// Position of the next instruction
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Bytes not decoded yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<'a> Iterator for InstructionIter<'a> {
+    type Item = Result<Command>;
+
+    fn next(&mut self) -> Option<Result<Command>> {
+        if self.bytes.is_empty() || self.failed {
+            return None;
+        }
+        match Command::decode_for(self.bytes, self.position, self.platform) {
+            Ok((command, size)) => {
+                self.bytes = &self.bytes[size..];
+                self.position += size as u32;
+                Some(Ok(command))
+            }
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<'a> std::iter::FusedIterator for InstructionIter<'a> {}
+
+impl<'a> MscsbView<'a> {
+    /// Decode a script's instructions as they're needed
+    pub fn instructions(&self, index: usize) -> Option<InstructionIter<'a>> {
+        let (start, _) = self.script_range(index)?;
+        Some(InstructionIter::new_for(self.script_bytes(index)?, start, self.platform()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Cmd;
+
+    #[test]
+    fn test_encode_decode() {
+        let commands = [
+            Command { cmd: Cmd::Begin { arg_count: 1, var_count: 2 }, push_bit: false, position: 0x10 },
+            Command { cmd: Cmd::PushVar { var_type: 0, var_num: 0x102 }, push_bit: true, position: 0x15 },
+            Command { cmd: Cmd::IfNot { loc: 0x1F }, push_bit: false, position: 0x19 },
+            Command { cmd: Cmd::End, push_bit: false, position: 0x1E },
+        ];
+        let mut bytes = vec![];
+        for command in commands.iter() {
+            command.encode(&mut bytes).unwrap();
+        }
+        assert_eq!(&bytes[5..9], &[0x8B, 0x00, 0x01, 0x02]);
+
+        let (decoded, size) = Command::decode(&bytes[5..], 0x15).unwrap();
+        assert_eq!(size, 4);
+        assert_eq!((decoded.cmd.operands(), decoded.push_bit), (vec![0, 0x102], true));
+
+        let decoded: Vec<Command> = InstructionIter::new(&bytes, 0x10).collect::<Result<_>>().unwrap();
+        assert_eq!(decoded.iter().map(|c| c.position).collect::<Vec<_>>(), vec![0x10, 0x15, 0x19, 0x1E]);
+        assert_eq!(decoded[2].cmd.branch_target(), Some(0x1F));
+
+        let mut three_ds = vec![];
+        commands[1].encode_for(&mut three_ds, Platform::ThreeDs).unwrap();
+        assert_eq!(three_ds, vec![0x8B, 0x00, 0x02, 0x01]);
+        assert_eq!(Command::decode_for(&three_ds, 0, Platform::ThreeDs).unwrap().0.cmd.operands(), vec![0, 0x102]);
+
+        assert!(Command::decode(&bytes[..3], 0x10).is_err());
+        assert!(Command::decode(&[0x4E], 0x10).is_err());
+        let mut iter = InstructionIter::new(&[0x03, 0x7F, 0x03], 0x10);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+}
@@ -1,23 +1,63 @@
+mod header;
+mod instruction;
 mod parser;
+mod platform;
+mod view;
 mod writer;
 
+pub use header::{MscsbHeader, HEADER_SIZE, MAGIC};
+pub use instruction::InstructionIter;
+pub use platform::{Endian, Platform};
+pub use view::MscsbView;
 use super::Script;
 use parser::take_file;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "crate::serde_impl::RawFile"))]
 pub struct MscsbFile {
     pub scripts: Vec<Script>,
     pub strings: Vec<String>,
     pub entrypoint: u32,
+    // Detected when parsing, decides the byte order `write` uses
+    pub(crate) platform: Platform,
 }
 
 impl MscsbFile {
+    /// A file for the default platform, see `with_platform`
+    pub fn new(scripts: Vec<Script>, strings: Vec<String>, entrypoint: u32) -> MscsbFile {
+        MscsbFile { scripts, strings, entrypoint, platform: Platform::default() }
+    }
+
+    pub fn with_platform(mut self, platform: Platform) -> MscsbFile {
+        self.platform = platform;
+        self
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+    }
+
     pub fn open<P: AsRef<Path>>(path: P) -> Option<MscsbFile> {
         let mut buffer = vec![];
         File::open(path).ok()?.read_to_end(&mut buffer).ok()?;
-        Some(take_file(&buffer[..]).unwrap().1)
+        MscsbFile::from_bytes(&buffer)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<MscsbFile> {
+        MscsbFile::from_bytes_for(bytes, Platform::detect(bytes)?)
+    }
+
+    /// Parse without detecting the platform
+    pub fn from_bytes_for(bytes: &[u8], platform: Platform) -> Option<MscsbFile> {
+        take_file(bytes, platform)
     }
 
     pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
@@ -28,17 +68,12 @@ impl MscsbFile {
         Ok(())
     }
 
-    pub fn iter(&self) -> std::slice::Iter<Script> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Script> {
         self.scripts.iter()
     }
 
     pub fn get_script_from_loc(&self, loc: u32) -> Option<usize> {
-        for i in 0..self.scripts.len() {
-            if self.scripts[i].bounds.0 == loc {
-                return Some(i);
-            }
-        }
-        None
+        self.scripts.iter().position(|script| script.bounds.0 == loc)
     }
 }
 
@@ -1,5 +1,7 @@
-use nom::{be_u8, be_u16, be_u32, le_u32, IResult};
-use super::MscsbFile;
+use nom::{be_u8, Endianness, IResult};
+use super::{MscsbFile, MscsbHeader, Platform, MAGIC};
+use super::header::HEADER_ENDIAN;
+use super::platform::SIGNATURE;
 use super::super::{Cmd, Command, Script};
 
 fn get_nom_position(input: &[u8], input_size: usize) -> IResult<&[u8], usize> {
@@ -7,16 +9,17 @@ fn get_nom_position(input: &[u8], input_size: usize) -> IResult<&[u8], usize> {
     do_parse!(input, (input_size - remaining))
 }
 
-fn take_script(input: &[u8], position: usize) -> IResult<&[u8], Script> {
+pub fn take_script(input: &[u8], position: usize, endian: Endianness) -> IResult<&[u8], Script> {
     do_parse!(
         input,
         commands: many0!(complete!(
                 do_parse!(
                     pos: apply!(get_nom_position, input.len()) >>
-                    cmd: apply!(take_cmd, pos) >>
+                    cmd: apply!(take_cmd, position + pos, endian) >>
                     (cmd)
                 ))) >>
-        size: apply!(get_nom_position, input.len()) >>
+        // Decoding stops at the first bad opcode, which would leave the script short
+        size: verify!(apply!(get_nom_position, input.len()), |size: usize| size == input.len()) >>
         (Script {
             bounds: (position as u32, (position + size) as u32),
             commands
@@ -31,53 +34,88 @@ pub fn str_from_u8_nul_utf8(utf8_src: &[u8]) -> Result<&str, std::str::Utf8Error
     ::std::str::from_utf8(&utf8_src[0..nul_range_end])
 }
 
-pub fn take_file(input: &[u8]) -> IResult<&[u8], MscsbFile> {
+pub fn take_header(input: &[u8], endian: Endianness) -> IResult<&[u8], MscsbHeader> {
     do_parse!(
         input,
-        tag!(b"\xB2\xAC\xBC\xBA\xE6\x90\x32\x01\xFD\x02\x00\x00\x00\x00\x00\x00") >>
-        script_data_size: le_u32 >>
-        entrypoint: le_u32 >>
-        script_count: le_u32 >>
-        _unk: le_u32 >>
-        string_size: le_u32 >>
-        string_count: le_u32 >>
+        magic: verify!(take!(0x10), |magic: &[u8]| magic.starts_with(SIGNATURE)) >>
+        script_data_size: u32!(endian) >>
+        entrypoint: u32!(endian) >>
+        script_count: u32!(endian) >>
+        unk: u32!(endian) >>
+        string_size: u32!(endian) >>
+        string_count: u32!(endian) >>
         _padding: take!(8) >>
-        script_data: take!(script_data_size) >>
-        _padding: take!((0x10 - (script_data_size & 0xF)) & 0xF) >> // pad to 0x10
-        script_offsets: count!(le_u32, script_count as usize) >>
-        _padding: take!((0x10 - ((script_count * 4) & 0xF)) & 0xF) >> // pad to 0x10
-        strings: count!(take!(string_size), string_count as usize) >>
-        ({
-            let mut script_offsets = script_offsets.clone();
-            script_offsets.sort();
-            script_offsets.push(script_data_size);
-            let scripts =
-                (0..script_offsets.len() - 1)
-                .filter_map(|i| {
-                    Some(do_parse!(
-                        &script_data[script_offsets[i] as usize..script_offsets[i+1] as usize],
-                        script: complete!(apply!(take_script, script_offsets[i] as usize)) >>
-                        (script)
-                    ).unwrap().1)
-                })
-                .collect();
-            let strings =
-                strings
-                .iter()
-                .map(|s|
-                    String::from(
-                        str_from_u8_nul_utf8(s).unwrap_or("[UTF-8 Error]")
-                )).collect();
-            MscsbFile {
-                scripts,
-                strings,
-                entrypoint
-            }
+        (MscsbHeader {
+            magic: {
+                let mut bytes = [0; 0x10];
+                bytes.copy_from_slice(magic);
+                bytes
+            },
+            script_data_size,
+            entrypoint,
+            script_count,
+            unk,
+            string_size,
+            string_count
         })
     )
 }
 
-fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
+// The header, script data, script offsets and strings of a file, split up but not
+// decoded
+type Layout<'a> = (MscsbHeader, &'a [u8], Vec<u32>, Vec<&'a [u8]>);
+
+fn take_layout(input: &[u8]) -> IResult<&[u8], Layout<'_>> {
+    let header_endian = HEADER_ENDIAN.nom();
+    do_parse!(
+        input,
+        header: verify!(
+            apply!(take_header, header_endian),
+            |header: MscsbHeader| header.magic == *MAGIC && header.fits(input.len())
+        ) >>
+        script_data: take!(header.script_data_size) >>
+        _padding: take!((0x10 - (header.script_data_size & 0xF)) & 0xF) >> // pad to 0x10
+        script_offsets: count!(u32!(header_endian), header.script_count as usize) >>
+        _padding: take!((0x10 - ((header.script_count * 4) & 0xF)) & 0xF) >> // pad to 0x10
+        strings: count!(take!(header.string_size), header.string_count as usize) >>
+        ((header, script_data, script_offsets, strings))
+    )
+}
+
+/// Parse a whole file, `None` if it's truncated, a script offset is outside the script
+/// data or a script has bytes that don't decode
+pub fn take_file(input: &[u8], platform: Platform) -> Option<MscsbFile> {
+    let (_, (header, script_data, mut script_offsets, strings)) = take_layout(input).ok()?;
+    if script_offsets.iter().any(|&offset| offset > header.script_data_size) {
+        return None;
+    }
+    script_offsets.sort();
+    script_offsets.push(header.script_data_size);
+    let bytecode = platform.bytecode_endian().nom();
+    let scripts =
+        script_offsets
+        .windows(2)
+        .map(|range| {
+            let bytes = &script_data[range[0] as usize..range[1] as usize];
+            take_script(bytes, range[0] as usize, bytecode).ok().map(|(_, script)| script)
+        })
+        .collect::<Option<Vec<Script>>>()?;
+    let strings =
+        strings
+        .iter()
+        .map(|s|
+            String::from(
+                str_from_u8_nul_utf8(s).unwrap_or("[UTF-8 Error]")
+        )).collect();
+    Some(MscsbFile {
+        scripts,
+        strings,
+        entrypoint: header.entrypoint,
+        platform
+    })
+}
+
+pub fn take_cmd(input: &[u8], position: usize, endian: Endianness) -> IResult<&[u8], Command> {
     do_parse!(
         input,
         cmd_num: be_u8 >>
@@ -85,8 +123,8 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             0 => value!(Cmd::Nop, take!(0)) |
             1 => value!(Cmd::Unk1, take!(0)) |
             2 => do_parse!(
-                arg_count: be_u16 >>
-                var_count: be_u16 >>
+                arg_count: u16!(endian) >>
+                var_count: u16!(endian) >>
                 (Cmd::Begin {
                     arg_count,
                     var_count
@@ -94,13 +132,13 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             3 => value!(Cmd::End, take!(0)) |
             4 => do_parse!(
-                loc: be_u32 >>
+                loc: u32!(endian) >>
                 (Cmd::Jump {
                     loc
                 })
             ) |
             5 => do_parse!(
-                loc: be_u32 >>
+                loc: u32!(endian) >>
                 (Cmd::Jump5 {
                     loc
                 })
@@ -110,14 +148,14 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             8 => value!(Cmd::Return8, take!(0)) |
             9 => value!(Cmd::Return9, take!(0)) |
             0xA => do_parse!(
-                val: be_u32 >>
+                val: u32!(endian) >>
                 (Cmd::PushInt {
                     val
                 })
             ) |
             0xB => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::PushVar {
                     var_type,
                     var_num
@@ -125,7 +163,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0xC => value!(Cmd::ErrorC, take!(0)) |
             0xD => do_parse!(
-                val: be_u16 >>
+                val: u16!(endian) >>
                 (Cmd::PushShort {
                     val
                 })
@@ -138,7 +176,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             0x13 => value!(Cmd::NegI, take!(0)) |
             0x14 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::IncI {
                     var_type,
                     var_num
@@ -146,7 +184,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x15 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::DecI {
                     var_type,
                     var_num
@@ -160,7 +198,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             0x1B => value!(Cmd::ShiftR, take!(0)) |
             0x1C => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::SetVar {
                     var_type,
                     var_num
@@ -168,7 +206,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x1D => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::AddVarBy {
                     var_type,
                     var_num
@@ -176,7 +214,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x1E => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::SubVarBy {
                     var_type,
                     var_num
@@ -184,7 +222,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x1F => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::MultVarBy {
                     var_type,
                     var_num
@@ -192,7 +230,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x20 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::DivVarBy {
                     var_type,
                     var_num
@@ -200,7 +238,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x21 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::ModVarBy {
                     var_type,
                     var_num
@@ -208,7 +246,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x22 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::AndVarBy {
                     var_type,
                     var_num
@@ -216,7 +254,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x23 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::OrVarBy {
                     var_type,
                     var_num
@@ -224,7 +262,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x24 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::XorVarBy {
                     var_type,
                     var_num
@@ -252,7 +290,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
                 })
             ) |
             0x2E => do_parse!(
-                loc: be_u32 >>
+                loc: u32!(endian) >>
                 (Cmd::Try {
                     loc
                 })
@@ -278,19 +316,19 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             0x32 => value!(Cmd::Push, take!(0)) |
             0x33 => value!(Cmd::Pop, take!(0)) |
             0x34 => do_parse!(
-                loc: be_u32 >>
+                loc: u32!(endian) >>
                 (Cmd::If {
                     loc
                 })
             ) |
             0x35 => do_parse!(
-                loc: be_u32 >>
+                loc: u32!(endian) >>
                 (Cmd::IfNot {
                     loc
                 })
             ) |
             0x36 => do_parse!(
-                loc: be_u32 >>
+                loc: u32!(endian) >>
                 (Cmd::Else {
                     loc
                 })
@@ -315,7 +353,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             0x3E => value!(Cmd::NegF, take!(0)) |
             0x3F => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::IncF {
                     var_type,
                     var_num
@@ -323,7 +361,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x40 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::DecF {
                     var_type,
                     var_num
@@ -331,7 +369,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x41 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::VarSetF {
                     var_type,
                     var_num
@@ -339,7 +377,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x42 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::AddVarByF {
                     var_type,
                     var_num
@@ -347,7 +385,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x43 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::SubVarByF {
                     var_type,
                     var_num
@@ -355,7 +393,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x44 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::MultVarByF {
                     var_type,
                     var_num
@@ -363,7 +401,7 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
             ) |
             0x45 => do_parse!(
                 var_type: be_u8 >>
-                var_num: be_u16 >>
+                var_num: u16!(endian) >>
                 (Cmd::DivVarByF {
                     var_type,
                     var_num
@@ -387,3 +425,38 @@ fn take_cmd(input: &[u8], position: usize) -> IResult<&[u8], Command> {
         })
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::super::{MscsbFile, Platform};
+    use crate::assemble;
+    use byteorder::{ByteOrder, LittleEndian};
+
+    #[test]
+    fn test_malformed() {
+        let file = assemble("main:\n    Begin 0, 0\n    PushShort. 0x1\n    Sys 1, 0x10\n    End\n").unwrap();
+        let mut bytes = vec![];
+        file.write(&mut bytes);
+        assert!(MscsbFile::from_bytes_for(&bytes, Platform::WiiU).is_some());
+
+        // The PushShort at 0x15, with the script data starting 0x30 bytes in
+        let mut bad_opcode = bytes.clone();
+        bad_opcode[0x30 + 0x15] = 0x7F;
+        assert!(MscsbFile::from_bytes_for(&bad_opcode, Platform::WiiU).is_none());
+        assert!(MscsbFile::from_bytes(&bad_opcode).is_none());
+
+        // Counts far larger than the file, which must fail before anything is allocated
+        let mut header = bytes[..0x40].to_vec();
+        LittleEndian::write_u32(&mut header[0x10..], 0x10);
+        LittleEndian::write_u32(&mut header[0x18..], 0);
+        LittleEndian::write_u32(&mut header[0x20..], 0);
+        LittleEndian::write_u32(&mut header[0x24..], 0xFFFF_FFFF);
+        assert!(MscsbFile::from_bytes_for(&header, Platform::WiiU).is_none());
+        assert!(MscsbFile::from_bytes(&header).is_none());
+        LittleEndian::write_u32(&mut header[0x20..], 0x10);
+        assert!(MscsbFile::from_bytes_for(&header, Platform::WiiU).is_none());
+        LittleEndian::write_u32(&mut header[0x18..], 0x4000_0000);
+        LittleEndian::write_u32(&mut header[0x24..], 0);
+        assert!(MscsbFile::from_bytes_for(&header, Platform::WiiU).is_none());
+    }
+}
@@ -0,0 +1,177 @@
+use super::{MscsbFile, MscsbView, MAGIC};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use std::fmt;
+use std::str::FromStr;
+
+// Start of `MAGIC`, without the version bytes
+pub(crate) const SIGNATURE: &[u8; 6] = b"\xB2\xAC\xBC\xBA\xE6\x90";
+
+// Scripts decoded per platform when detecting, enough to tell byte orders apart
+const DETECT_SCRIPTS: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    pub(crate) fn is_big(self) -> bool {
+        self == Endian::Big
+    }
+
+    pub(crate) fn read_u32(self, bytes: &[u8]) -> u32 {
+        match self {
+            Endian::Little => LittleEndian::read_u32(bytes),
+            Endian::Big => BigEndian::read_u32(bytes),
+        }
+    }
+
+    pub(crate) fn nom(self) -> nom::Endianness {
+        match self {
+            Endian::Little => nom::Endianness::Little,
+            Endian::Big => nom::Endianness::Big,
+        }
+    }
+}
+
+/// Which game build a file is for. The builds share the header, its byte order and the
+/// magic and version bytes, only the byte order of the bytecode is known to differ. So
+/// nothing in the header tells them apart and `detect` has to decode scripts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Platform {
+    /// Little endian header, big endian bytecode
+    #[default]
+    WiiU,
+    /// Little endian throughout. Uses the same version bytes as the Wii U.
+    ThreeDs,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 2] = [Platform::WiiU, Platform::ThreeDs];
+
+    pub fn bytecode_endian(self) -> Endian {
+        match self {
+            Platform::WiiU => Endian::Big,
+            Platform::ThreeDs => Endian::Little,
+        }
+    }
+
+    /// Work out which platform a file was written for. Platforms that share a header
+    /// are told apart by decoding the first few scripts both ways and keeping the one
+    /// that decodes with the fewest implausible operands.
+    pub fn detect(bytes: &[u8]) -> Option<Platform> {
+        if !bytes.starts_with(MAGIC) {
+            return None;
+        }
+        Platform::ALL.iter()
+            .filter_map(|&platform| Some((platform.implausibility(bytes)?, platform)))
+            .min_by_key(|&(score, platform)| (score, Platform::ALL.iter().position(|&p| p == platform)))
+            .map(|(_, platform)| platform)
+    }
+
+    // Count of things that look wrong when the bytecode is read this way, `None` if
+    // the file doesn't parse at all
+    fn implausibility(self, bytes: &[u8]) -> Option<usize> {
+        let view = MscsbView::new(bytes, self)?;
+        let data_size = view.header().script_data_size;
+        let mut score = 0;
+        for i in 0..view.script_count().min(DETECT_SCRIPTS) {
+            let script = view.script(i)?;
+            for command in script.iter() {
+                use crate::Cmd::*;
+                score += match command.cmd {
+                    Begin { arg_count, var_count } => (arg_count > 0xFF) as usize + (var_count > 0xFF) as usize,
+                    PushShort { val } => (val > 0xFF && val.swap_bytes() <= 0xFF) as usize,
+                    _ => command.cmd.branch_target()
+                        .map_or(0, |loc| (loc < 0x10 || loc >= data_size) as usize),
+                };
+            }
+        }
+        Some(score)
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Platform::WiiU => "wiiu",
+            Platform::ThreeDs => "3ds",
+        })
+    }
+}
+
+impl FromStr for Platform {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Platform> {
+        match &s.to_ascii_lowercase()[..] {
+            "wiiu" | "wii-u" | "wii_u" => Ok(Platform::WiiU),
+            "3ds" => Ok(Platform::ThreeDs),
+            _ => Err(crate::Error::Format(format!("unknown platform '{}', expected wiiu or 3ds", s))),
+        }
+    }
+}
+
+impl MscsbFile {
+    /// Parse `bytes` as a file for another platform to produce one for `platform`
+    pub fn convert(bytes: &[u8], platform: Platform) -> Option<Vec<u8>> {
+        let mut file = MscsbFile::from_bytes(bytes)?;
+        file.platform = platform;
+        let mut converted = vec![];
+        file.write(&mut converted);
+        Some(converted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn test_platforms() {
+        let file = assemble("
+main:
+    Begin 0, 2
+    PushShort. 0x1
+    IfNot loc_end
+    PushInt. other
+    CallFunc 0
+loc_end:
+    End
+other:
+    Begin 0, 0
+    Return6
+").unwrap();
+        let mut wii_u = vec![];
+        file.write(&mut wii_u);
+        assert_eq!(Platform::detect(&wii_u), Some(Platform::WiiU));
+        assert_eq!(&wii_u[0x46..0x48], &[0x00, 0x01]);
+
+        let three_ds = MscsbFile::convert(&wii_u, Platform::ThreeDs).unwrap();
+        assert_eq!(three_ds.len(), wii_u.len());
+        assert_eq!(&three_ds[0x46..0x48], &[0x01, 0x00]);
+        assert_eq!(Platform::detect(&three_ds), Some(Platform::ThreeDs));
+        let parsed = MscsbFile::from_bytes(&three_ds).unwrap();
+        assert_eq!(parsed.platform(), Platform::ThreeDs);
+        assert_eq!(parsed.disassemble(), format!(".platform 3ds\n{}", file.disassemble()));
+        assert_eq!(assemble(&parsed.disassemble()).unwrap().platform(), Platform::ThreeDs);
+
+        assert_eq!(MscsbFile::convert(&three_ds, Platform::WiiU).unwrap(), wii_u);
+        assert_eq!(Platform::detect(b"not msc"), None);
+
+        // A script offset past the end of the script data
+        let data_size = LittleEndian::read_u32(&wii_u[0x10..]) as usize;
+        let table = 0x30 + ((data_size + 0xF) & !0xF);
+        let mut bad = wii_u.clone();
+        LittleEndian::write_u32(&mut bad[table..], 0x100);
+        assert!(MscsbFile::from_bytes_for(&bad, Platform::WiiU).is_none());
+        assert!(MscsbFile::from_bytes(&bad).is_none());
+        assert!(MscsbFile::from_bytes_for(&wii_u[..wii_u.len() - 1], Platform::WiiU).is_none());
+        assert_eq!("3DS".parse::<Platform>().unwrap(), Platform::ThreeDs);
+        assert!("gamecube".parse::<Platform>().is_err());
+    }
+}
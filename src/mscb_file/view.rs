@@ -0,0 +1,178 @@
+use super::{MscsbFile, MscsbHeader, Platform, HEADER_SIZE, MAGIC};
+use super::header::HEADER_ENDIAN;
+use super::parser::{str_from_u8_nul_utf8, take_script};
+use super::super::Script;
+
+/// A file borrowed from its bytes. Only the header and script offset table are read
+/// up front, scripts are decoded when asked for and strings are borrowed. The platform
+/// has to be given since telling platforms apart means decoding scripts, see
+/// `Platform::detect`.
+#[derive(Debug, Clone)]
+pub struct MscsbView<'a> {
+    header: MscsbHeader,
+    platform: Platform,
+    script_data: &'a [u8],
+    // Script offsets in the same order as `MscsbFile::scripts`
+    offsets: Vec<u32>,
+    strings: &'a [u8],
+}
+
+fn pad(size: usize) -> usize {
+    (0x10 - (size & 0xF)) & 0xF
+}
+
+impl<'a> MscsbView<'a> {
+    pub fn new(bytes: &'a [u8], platform: Platform) -> Option<MscsbView<'a>> {
+        let header = MscsbHeader::peek(bytes)?;
+        if header.magic != *MAGIC || !header.fits(bytes.len()) {
+            return None;
+        }
+        let data_size = header.script_data_size as usize;
+        let table_size = header.script_count as usize * 4;
+        let table_start = HEADER_SIZE + data_size + pad(data_size);
+        let strings_start = table_start + table_size + pad(table_size);
+        let strings_size = (header.string_size as usize).checked_mul(header.string_count as usize)?;
+        let script_data = bytes.get(HEADER_SIZE..HEADER_SIZE + data_size)?;
+        let table = bytes.get(table_start..table_start + table_size)?;
+        let strings = bytes.get(strings_start..strings_start.checked_add(strings_size)?)?;
+
+        let endian = HEADER_ENDIAN;
+        let mut offsets: Vec<u32> = table.chunks(4).map(|offset| endian.read_u32(offset)).collect();
+        if offsets.iter().any(|&offset| offset as usize > data_size) {
+            return None;
+        }
+        offsets.sort_unstable();
+        Some(MscsbView { header, platform, script_data, offsets, strings })
+    }
+
+    pub fn header(&self) -> &MscsbHeader {
+        &self.header
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn script_count(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Start and end of the script's bytes, which `Script::bounds` matches
+    pub fn script_range(&self, index: usize) -> Option<(u32, u32)> {
+        let start = *self.offsets.get(index)?;
+        let end = self.offsets.get(index + 1).cloned().unwrap_or(self.header.script_data_size);
+        Some((start, end))
+    }
+
+    /// Encoded bytecode of a script
+    pub fn script_bytes(&self, index: usize) -> Option<&'a [u8]> {
+        let (start, end) = self.script_range(index)?;
+        Some(&self.script_data[start as usize..end as usize])
+    }
+
+    /// Decode a single script, `None` if any of its bytes don't decode
+    pub fn script(&self, index: usize) -> Option<Script> {
+        let (start, _) = self.script_range(index)?;
+        let bytes = self.script_bytes(index)?;
+        take_script(bytes, start as usize, self.platform.bytecode_endian().nom()).ok().map(|(_, script)| script)
+    }
+
+    /// Every script in order, `None` for any that don't decode
+    pub fn scripts(&self) -> impl Iterator<Item = Option<Script>> + '_ {
+        (0..self.script_count()).map(move |i| self.script(i))
+    }
+
+    pub fn string_count(&self) -> usize {
+        self.header.string_count as usize
+    }
+
+    /// A string without its null terminator, `None` if it's out of range or not UTF-8
+    pub fn string(&self, index: usize) -> Option<&'a str> {
+        if index >= self.string_count() {
+            return None;
+        }
+        let size = self.header.string_size as usize;
+        str_from_u8_nul_utf8(&self.strings[index * size..(index + 1) * size]).ok()
+    }
+
+    /// Decode everything into an owned file, as `MscsbFile::from_bytes_for` would. `None`
+    /// if any script doesn't decode.
+    pub fn to_file(&self) -> Option<MscsbFile> {
+        Some(MscsbFile {
+            scripts: self.scripts().collect::<Option<Vec<Script>>>()?,
+            strings: (0..self.string_count())
+                .map(|i| String::from(self.string(i).unwrap_or("[UTF-8 Error]")))
+                .collect(),
+            entrypoint: self.header.entrypoint,
+            platform: self.platform,
+        })
+    }
+}
+
+impl MscsbFile {
+    /// Borrow `bytes` without decoding them, see `MscsbView`
+    pub fn view(bytes: &[u8], platform: Platform) -> Option<MscsbView<'_>> {
+        MscsbView::new(bytes, platform)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+    use byteorder::{ByteOrder, LittleEndian};
+
+    #[test]
+    fn test_view() {
+        let file = assemble(r#"
+.string "hello"
+.string "world"
+main:
+    Begin 0, 0
+    PushInt. other
+    CallFunc 0
+    End
+other:
+    Begin 0, 0
+    PushShort. 0x2
+    Sys 1, 0x10
+    Return6
+"#).unwrap();
+        let mut bytes = vec![];
+        file.write(&mut bytes);
+
+        let view = MscsbFile::view(&bytes, Platform::WiiU).unwrap();
+        assert_eq!(view.platform(), Platform::WiiU);
+        assert_eq!(view.script_count(), 2);
+        assert_eq!(view.script_range(1), Some(file.scripts[1].bounds));
+        assert_eq!(view.script_bytes(0).unwrap().len(), 13);
+        let other = view.script(1).unwrap();
+        assert_eq!(other.commands.len(), 4);
+        assert_eq!(other.commands[2].position, file.scripts[1].commands[2].position);
+        assert!(view.script(2).is_none());
+        assert_eq!(view.string(1), Some("world"));
+        assert_eq!(view.string(2), None);
+
+        let owned = view.to_file().unwrap();
+        assert_eq!(owned.disassemble(), file.disassemble());
+        assert_eq!(owned.disassemble(), MscsbFile::from_bytes(&bytes).unwrap().disassemble());
+
+        assert!(MscsbView::new(&bytes[..bytes.len() - 1], Platform::WiiU).is_none());
+
+        // A bad opcode in the second command of `main`
+        let mut bad_opcode = bytes.clone();
+        bad_opcode[HEADER_SIZE + 0x15] = 0x7F;
+        let view = MscsbView::new(&bad_opcode, Platform::WiiU).unwrap();
+        assert!(view.script(0).is_none());
+        assert!(view.script(1).is_some());
+        assert!(view.to_file().is_none());
+
+        // More strings than the file could hold
+        let mut huge = bytes[..0x40].to_vec();
+        LittleEndian::write_u32(&mut huge[0x10..], 0x10);
+        LittleEndian::write_u32(&mut huge[0x18..], 0);
+        LittleEndian::write_u32(&mut huge[0x20..], 0);
+        LittleEndian::write_u32(&mut huge[0x24..], 0xFFFF_FFFF);
+        assert!(MscsbView::new(&huge, Platform::WiiU).is_none());
+    }
+}
@@ -1,27 +1,28 @@
 use super::MscsbFile;
+use super::header::HEADER_ENDIAN;
 use super::super::{Cmd, Command};
 use byteorder::{LittleEndian, BigEndian, WriteBytesExt};
 
 impl MscsbFile {
     pub fn write(&self, f: &mut Vec<u8>) {
-        // Little Endian
+        let endian = HEADER_ENDIAN.is_big();
         macro_rules! write {
             ($e:expr) => {
-                WriteImpl::write($e, f, false);
+                WriteImpl::write($e, f, endian);
             }
         }
-        let max_str_len = self.get_max_string_size();
+        let header = self.header();
+        let max_str_len = header.string_size;
         let mut script_data: Vec<u8> = vec![];
         let script_offsets = self.generate_script_data(&mut script_data);
-        // Write magic
-        write!(&b"\xB2\xAC\xBC\xBA\xE6\x90\x32\x01\xFD\x02\x00\x00\x00\x00\x00\x00"[..]);
-        write!(0u32);
-        write!(script_data.len() as u32);
-        write!(self.entrypoint);
-        write!(self.scripts.len() as u32);
-        write!(0x16u32); // oof ouch magic number
+        debug_assert_eq!(script_data.len() as u32, header.script_data_size);
+        write!(&header.magic[..]);
+        write!(header.script_data_size);
+        write!(header.entrypoint);
+        write!(header.script_count);
+        write!(header.unk);
         write!(max_str_len);
-        write!(self.strings.len() as u32);
+        write!(header.string_count);
         write!(vec![0u8; 8]);
         write!(&script_data[..]);
         write!(vec![0u8; (0x10 - (f.len() % 0x10)) & 0xF]); // Pad to 0x10
@@ -31,15 +32,15 @@ impl MscsbFile {
             write!(string.as_bytes());
             write!(0u8); // null terminate
             // Pad strings to max length
-            write!(vec![0u8; max_str_len as usize - (1 + string.as_bytes().len())]);
+            write!(vec![0u8; max_str_len as usize - (1 + string.len())]);
         }
     }
 
     fn generate_script_data(&self, f: &mut Vec<u8>) -> Vec<u32> {
-        // Big Endian
+        let endian = self.platform.bytecode_endian().is_big();
         macro_rules! write {
             ($e:expr) => {
-                WriteImpl::write($e, f, true);
+                WriteImpl::write($e, f, endian);
             }
         }
         let mut script_offsets = vec![];
@@ -54,25 +55,16 @@ impl MscsbFile {
 
         script_offsets
     }
-
-    fn get_max_string_size(&self) -> u32 {
-        self.strings
-            .iter()
-            .map(|s| (s.len() + 0x10) & 0xF)
-            .max()
-            .unwrap() as u32
-    }
 }
 
 impl WriteImpl for &Command {
     fn write(self, f: &mut Vec<u8>, endian: bool) {
-        // Big Endian
         macro_rules! write {
             ($e:expr) => {
                 WriteImpl::write($e, f, endian);
             }
         }
-        write!(self.cmd.value() & (if self.push_bit {0x80u8} else {0x0u8}));
+        write!(self.cmd.value() | (if self.push_bit {0x80u8} else {0x0u8}));
         match self.cmd {
             Cmd::Begin { arg_count, var_count } => {
                 write!(arg_count);
@@ -257,8 +249,8 @@ impl Cmd {
             Cmd::Sys { arg_count: _, sys_num: _ } => 0x2D,
             Cmd::Try { loc: _ } => 0x2E,
             Cmd::CallFunc { arg_count: _ } => 0x2F,
-            Cmd::CallFunc2 { arg_count: _ } => 0x2F,
-            Cmd::CallFunc3 { arg_count: _ } => 0x2F,
+            Cmd::CallFunc2 { arg_count: _ } => 0x30,
+            Cmd::CallFunc3 { arg_count: _ } => 0x31,
             Cmd::Push => 0x32,
             Cmd::Pop => 0x33,
             Cmd::If { loc: _ } => 0x34,
@@ -290,10 +282,30 @@ impl Cmd {
             _ => panic!("Cannot cast Cmd {:?} to u8", self),
         }
     }
+
+    /// Size of the encoded command in bytes
+    pub fn size(&self) -> u32 {
+        match self {
+            Cmd::PrintF { .. } | Cmd::CallFunc { .. } | Cmd::CallFunc2 { .. } |
+            Cmd::CallFunc3 { .. } | Cmd::IntToFloat { .. } | Cmd::FloatToInt { .. } => 2,
+            Cmd::PushShort { .. } | Cmd::Sys { .. } => 3,
+            Cmd::PushVar { .. } | Cmd::IncI { .. } | Cmd::DecI { .. } |
+            Cmd::SetVar { .. } | Cmd::AddVarBy { .. } | Cmd::SubVarBy { .. } |
+            Cmd::MultVarBy { .. } | Cmd::DivVarBy { .. } | Cmd::ModVarBy { .. } |
+            Cmd::AndVarBy { .. } | Cmd::OrVarBy { .. } | Cmd::XorVarBy { .. } |
+            Cmd::IncF { .. } | Cmd::DecF { .. } | Cmd::VarSetF { .. } |
+            Cmd::AddVarByF { .. } | Cmd::SubVarByF { .. } | Cmd::MultVarByF { .. } |
+            Cmd::DivVarByF { .. } => 4,
+            Cmd::Begin { .. } | Cmd::Jump { .. } | Cmd::Jump5 { .. } |
+            Cmd::PushInt { .. } | Cmd::Try { .. } | Cmd::If { .. } | Cmd::IfNot { .. } |
+            Cmd::Else { .. } => 5,
+            _ => 1,
+        }
+    }
 }
 
 // WriteImpl trait for ezpz clean file writing
-trait WriteImpl {
+pub(super) trait WriteImpl {
     fn write(self, f: &mut Vec<u8>, endian: bool);
 }
 
@@ -343,13 +355,10 @@ impl<T> WriteImpl for Vec<T> where T: WriteImpl + Copy, {
     }
 }
 
-impl<T> WriteImpl for &mut Iterator<Item=T> where T: WriteImpl, {
+impl<T> WriteImpl for &mut dyn Iterator<Item=T> where T: WriteImpl, {
     fn write(self, f: &mut Vec<u8>, endian: bool) {
-        loop {
-            match self.next() {
-                Some(b) => WriteImpl::write(b, f, endian),
-                None => break
-            }
+        for b in self {
+            WriteImpl::write(b, f, endian);
         }
     }
 }
@@ -0,0 +1,631 @@
+use super::{Cmd, Command, MscsbFile, Script};
+use super::call_graph::address_pushes;
+use super::error::Result;
+use super::layout::relayout;
+use std::collections::{HashMap, HashSet};
+
+/// Peephole optimizer over every script in a file. Each pass can be turned off
+/// by clearing its field. A pass that leaves a script whose stack depth no
+/// longer checks out, or that adds a path between commands the script didn't
+/// have before, is thrown away for that script. Constants a call uses as its address
+/// are never folded.
+#[derive(Debug, Copy, Clone)]
+pub struct Optimizer {
+    /// `PushInt a; PushInt b; AddI` to `PushInt a+b`, plus branches on constants
+    pub fold_constants: bool,
+    /// `Not; IfNot` to `If` and the reverse
+    pub invert_branches: bool,
+    /// Point branches to jumps at the final target and drop jumps to the next command
+    pub thread_jumps: bool,
+    /// Drop values that are pushed only to be popped
+    pub remove_pops: bool,
+    /// `PushInt` to `PushShort` when the value is below 0x8000, so it reads the same
+    /// whether or not the game sign extends it. Call addresses are left alone.
+    pub narrow_pushes: bool,
+}
+
+/// Number of rewrites each pass made
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct OptimizeStats {
+    pub folded: usize,
+    pub inverted: usize,
+    pub threaded: usize,
+    pub removed_pops: usize,
+    pub narrowed: usize,
+    /// Bytes of bytecode saved across all scripts
+    pub bytes_saved: u32,
+}
+
+impl Default for Optimizer {
+    fn default() -> Optimizer {
+        Optimizer {
+            fold_constants: true,
+            invert_branches: true,
+            thread_jumps: true,
+            remove_pops: true,
+            narrow_pushes: true,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Insn {
+    cmd: Cmd,
+    push_bit: bool,
+    position: u32,
+    // Index of the branch target within the script, `insns.len()` for the end
+    target: Option<usize>,
+    // Pushes the address of a call, which `relayout` moves with its script
+    address: bool,
+    deleted: bool,
+}
+
+#[derive(Clone)]
+struct Body {
+    insns: Vec<Insn>,
+}
+
+impl Optimizer {
+    pub fn new() -> Optimizer {
+        Optimizer::default()
+    }
+
+    /// Optimize every script, moving scripts to close up the space saved. Fails, leaving
+    /// the file alone, if a `PushShort` script address can't follow its script.
+    pub fn optimize(&self, file: &mut MscsbFile) -> Result<OptimizeStats> {
+        let mut stats = OptimizeStats::default();
+        let bodies: Vec<Body> = file.scripts.iter()
+            .map(|script| {
+                let mut body = Body::new(script);
+                self.run(&mut body, &mut stats);
+                body
+            })
+            .collect();
+
+        let before: u32 = file.iter().map(|s| s.bounds.1 - s.bounds.0).sum();
+        let scripts = file.scripts.iter()
+            .zip(bodies)
+            .map(|(script, body)| Some(body.commands(script.bounds.1)))
+            .collect();
+        relayout(file, scripts)?;
+        stats.bytes_saved = before - file.iter().map(|s| s.bounds.1 - s.bounds.0).sum::<u32>();
+        Ok(stats)
+    }
+
+    fn run(&self, body: &mut Body, stats: &mut OptimizeStats) {
+        let balanced = body.check_stack();
+        let verified = |pass: &dyn Fn(&mut Body) -> usize, body: &mut Body| {
+            let before = body.clone();
+            let count = pass(body);
+            if count != 0 && (!body.keeps_flow(&before) || balanced && !body.check_stack()) {
+                *body = before;
+                return 0;
+            }
+            count
+        };
+        loop {
+            let mut changed = 0;
+            if self.fold_constants {
+                let count = verified(&Body::fold_constants, body);
+                stats.folded += count;
+                changed += count;
+            }
+            if self.invert_branches {
+                let count = verified(&Body::invert_branches, body);
+                stats.inverted += count;
+                changed += count;
+            }
+            if self.thread_jumps {
+                let count = verified(&Body::thread_jumps, body);
+                stats.threaded += count;
+                changed += count;
+            }
+            if self.remove_pops {
+                let count = verified(&Body::remove_pops, body);
+                stats.removed_pops += count;
+                changed += count;
+            }
+            if changed == 0 {
+                break;
+            }
+        }
+        if self.narrow_pushes {
+            stats.narrowed += verified(&Body::narrow_pushes, body);
+        }
+    }
+}
+
+impl Body {
+    fn new(script: &Script) -> Body {
+        let index: HashMap<u32, usize> =
+            script.iter()
+                .enumerate()
+                .map(|(i, c)| (c.position, i))
+                .collect();
+        let addresses = address_pushes(script);
+        let insns = script.iter()
+            .enumerate()
+            .map(|(i, command)| Insn {
+                cmd: command.cmd,
+                push_bit: command.push_bit,
+                position: command.position,
+                target: command.cmd.branch_target().and_then(|loc| {
+                    if loc == script.bounds.1 {
+                        Some(script.commands.len())
+                    } else {
+                        index.get(&loc).cloned()
+                    }
+                }),
+                address: addresses.contains_key(&i),
+                deleted: false,
+            })
+            .collect();
+        Body { insns }
+    }
+
+    // Surviving commands, still at their old positions
+    fn commands(&self, end: u32) -> Vec<Command> {
+        let position = |i: usize| self.insns.get(i).map(|insn| insn.position).unwrap_or(end);
+        self.insns.iter()
+            .filter(|insn| !insn.deleted)
+            .map(|insn| {
+                let mut cmd = insn.cmd;
+                if let Some(target) = insn.target {
+                    cmd.set_branch_target(position(target));
+                }
+                Command { cmd, push_bit: insn.push_bit, position: insn.position }
+            })
+            .collect()
+    }
+
+    fn live(&self) -> Vec<usize> {
+        (0..self.insns.len()).filter(|&i| !self.insns[i].deleted).collect()
+    }
+
+    // First live command at or after `i`, `insns.len()` for the end of the script
+    fn resolve(&self, mut i: usize) -> usize {
+        while i < self.insns.len() && self.insns[i].deleted {
+            i += 1;
+        }
+        i
+    }
+
+    fn targets(&self) -> HashSet<usize> {
+        self.insns.iter()
+            .filter(|insn| !insn.deleted)
+            .filter_map(|insn| insn.target.map(|t| self.resolve(t)))
+            .collect()
+    }
+
+    // The `len` live commands starting at `live[at]`, as long as none but the first
+    // can be jumped to
+    fn window(&self, live: &[usize], targets: &HashSet<usize>, at: usize, len: usize) -> Option<Vec<usize>> {
+        let window: Vec<usize> = live[at..].iter().cloned().filter(|&i| !self.insns[i].deleted).take(len).collect();
+        if window.len() != len || self.insns[window[0]].deleted || window[1..].iter().any(|i| targets.contains(i)) {
+            return None;
+        }
+        Some(window)
+    }
+
+    fn constant(&self, i: usize) -> Option<u32> {
+        let insn = &self.insns[i];
+        if !insn.push_bit || insn.address {
+            return None;
+        }
+        match insn.cmd {
+            Cmd::PushInt { val } => Some(val),
+            // Values the game might sign extend can't be folded either way
+            Cmd::PushShort { val } if val < 0x8000 => Some(val as u32),
+            _ => None,
+        }
+    }
+
+    fn fold_constants(&mut self) -> usize {
+        let live = self.live();
+        let targets = self.targets();
+        let mut count = 0;
+        for at in 0..live.len() {
+            if let Some(w) = self.window(&live, &targets, at, 3) {
+                if let (Some(a), Some(b)) = (self.constant(w[0]), self.constant(w[1])) {
+                    if let Some(value) = fold_binary(self.insns[w[2]].cmd, a as i32, b as i32) {
+                        if self.insns[w[2]].push_bit {
+                            self.insns[w[0]].cmd = Cmd::PushInt { val: value as u32 };
+                            self.insns[w[1]].deleted = true;
+                            self.insns[w[2]].deleted = true;
+                            count += 1;
+                            continue;
+                        }
+                    }
+                }
+            }
+            if let Some(w) = self.window(&live, &targets, at, 2) {
+                let a = match self.constant(w[0]) {
+                    Some(a) => a as i32,
+                    None => continue,
+                };
+                let (op, push_bit, target) = (self.insns[w[1]].cmd, self.insns[w[1]].push_bit, self.insns[w[1]].target);
+                let value = match op {
+                    Cmd::NegI if push_bit => a.wrapping_neg(),
+                    Cmd::NotI if push_bit => !a,
+                    Cmd::Not if push_bit => (a == 0) as i32,
+                    Cmd::If { .. } | Cmd::IfNot { .. } if target.is_some() => {
+                        let jumps = matches!(op, Cmd::If { .. }) == (a != 0);
+                        if jumps {
+                            self.insns[w[0]].cmd = Cmd::Jump { loc: 0 };
+                            self.insns[w[0]].push_bit = false;
+                            self.insns[w[0]].target = target;
+                        } else {
+                            self.insns[w[0]].deleted = true;
+                        }
+                        self.insns[w[1]].deleted = true;
+                        count += 1;
+                        continue;
+                    }
+                    _ => continue,
+                };
+                self.insns[w[0]].cmd = Cmd::PushInt { val: value as u32 };
+                self.insns[w[1]].deleted = true;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn invert_branches(&mut self) -> usize {
+        let live = self.live();
+        let targets = self.targets();
+        let mut count = 0;
+        for at in 0..live.len() {
+            let w = match self.window(&live, &targets, at, 2) {
+                Some(w) => w,
+                None => continue,
+            };
+            let (first, second) = (&self.insns[w[0]], &self.insns[w[1]]);
+            match (first.cmd, second.cmd) {
+                // `Not; IfNot x` to `If x`
+                (Cmd::Not, Cmd::If { loc }) | (Cmd::Not, Cmd::IfNot { loc }) if first.push_bit => {
+                    self.insns[w[1]].cmd = invert(second.cmd, loc);
+                    self.insns[w[0]].deleted = true;
+                }
+                // `If x; Jump y; x:` to `IfNot y; x:`
+                (Cmd::If { loc }, Cmd::Jump { .. }) | (Cmd::IfNot { loc }, Cmd::Jump { .. })
+                    if first.target.is_some() && second.target.is_some() &&
+                       first.target.map(|t| self.resolve(t)) == Some(self.resolve(w[1] + 1)) => {
+                    let target = second.target;
+                    self.insns[w[0]].cmd = invert(first.cmd, loc);
+                    self.insns[w[0]].target = target;
+                    self.insns[w[1]].deleted = true;
+                }
+                _ => continue,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    fn thread_jumps(&mut self) -> usize {
+        let mut count = 0;
+        for i in 0..self.insns.len() {
+            if self.insns[i].deleted {
+                continue;
+            }
+            let target = match self.insns[i].target {
+                Some(target) => self.resolve(target),
+                None => continue,
+            };
+            let mut last = target;
+            let mut seen = HashSet::new();
+            let mut cycle = false;
+            while last < self.insns.len() {
+                if !seen.insert(last) {
+                    cycle = true;
+                    break;
+                }
+                match self.insns[last] {
+                    Insn { cmd: Cmd::Jump { .. }, target: Some(next), .. } => last = self.resolve(next),
+                    _ => break,
+                }
+            }
+            if cycle {
+                // Jumps that loop forever are left alone
+                continue;
+            }
+            if last != target {
+                self.insns[i].target = Some(last);
+                count += 1;
+            }
+            if last == self.resolve(i + 1) {
+                match self.insns[i].cmd {
+                    Cmd::Jump { .. } => self.insns[i].deleted = true,
+                    Cmd::If { .. } | Cmd::IfNot { .. } => {
+                        self.insns[i].cmd = Cmd::Pop;
+                        self.insns[i].push_bit = false;
+                        self.insns[i].target = None;
+                    }
+                    _ => continue,
+                }
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn remove_pops(&mut self) -> usize {
+        let live = self.live();
+        let targets = self.targets();
+        let mut count = 0;
+        for at in 0..live.len() {
+            let w = match self.window(&live, &targets, at, 2) {
+                Some(w) => w,
+                None => continue,
+            };
+            let value = self.insns[w[0]].cmd;
+            if !matches!(self.insns[w[1]].cmd, Cmd::Pop) || !self.insns[w[0]].push_bit || !value.produces_value() {
+                continue;
+            }
+            match value {
+                Cmd::PushInt { .. } | Cmd::PushShort { .. } | Cmd::PushVar { .. } => self.insns[w[0]].deleted = true,
+                _ => self.insns[w[0]].push_bit = false,
+            }
+            self.insns[w[1]].deleted = true;
+            count += 1;
+        }
+        count
+    }
+
+    fn narrow_pushes(&mut self) -> usize {
+        let mut count = 0;
+        for insn in self.insns.iter_mut().filter(|insn| !insn.deleted && !insn.address) {
+            if let Cmd::PushInt { val } = insn.cmd {
+                if val < 0x8000 {
+                    insn.cmd = Cmd::PushShort { val: val as u16 };
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    // Whether every path between commands that are still plain commands in both bodies
+    // already existed in `before`. Jumps and deleted commands are looked through, so
+    // threading and folding pass, but a branch that now lands somewhere new doesn't.
+    fn keeps_flow(&self, before: &Body) -> bool {
+        let through: Vec<bool> = (0..self.insns.len())
+            .map(|i| before.transparent(i) || self.transparent(i))
+            .collect();
+        self.flow(&through).is_subset(&before.flow(&through))
+    }
+
+    fn transparent(&self, i: usize) -> bool {
+        self.insns[i].deleted || matches!(self.insns[i].cmd, Cmd::Jump { .. })
+    }
+
+    // Edges between the commands not in `through`, following paths through the rest.
+    // The entry is `usize::MAX` and the end of the script is `insns.len()`.
+    fn flow(&self, through: &[bool]) -> HashSet<(usize, usize)> {
+        let mut edges = HashSet::new();
+        let starts = std::iter::once(usize::MAX).chain((0..self.insns.len()).filter(|&i| !through[i]));
+        for from in starts {
+            let mut work = if from == usize::MAX { vec![0] } else { self.successors(from) };
+            let mut seen = HashSet::new();
+            while let Some(i) = work.pop() {
+                if !seen.insert(i) {
+                    continue;
+                }
+                if i < self.insns.len() && through[i] {
+                    work.extend(self.successors(i));
+                } else {
+                    edges.insert((from, i));
+                }
+            }
+        }
+        edges
+    }
+
+    fn successors(&self, i: usize) -> Vec<usize> {
+        let insn = &self.insns[i];
+        if insn.deleted {
+            return vec![i + 1];
+        }
+        let mut next: Vec<usize> = insn.target.into_iter().collect();
+        if !insn.cmd.ends_flow() {
+            next.push(i + 1);
+        }
+        next
+    }
+
+    // Whether every command is reached with one stack depth and never pops more
+    // than is on the stack
+    fn check_stack(&self) -> bool {
+        let mut depths: Vec<Option<usize>> = vec![None; self.insns.len() + 1];
+        let mut work = vec![(self.resolve(0), 0)];
+        while let Some((i, depth)) = work.pop() {
+            match depths[i] {
+                Some(known) if known == depth => continue,
+                Some(_) => return false,
+                None => depths[i] = Some(depth),
+            }
+            if i == self.insns.len() {
+                continue;
+            }
+            let insn = &self.insns[i];
+            let command = Command { cmd: insn.cmd, push_bit: insn.push_bit, position: insn.position };
+            let (pops, pushes) = command.stack_effect();
+            if depth < pops {
+                return false;
+            }
+            let after = depth - pops + pushes;
+            if let Some(target) = insn.target {
+                work.push((self.resolve(target), after));
+            }
+            if !insn.cmd.ends_flow() {
+                work.push((self.resolve(i + 1), after));
+            }
+        }
+        true
+    }
+}
+
+fn fold_binary(op: Cmd, a: i32, b: i32) -> Option<i32> {
+    Some(match op {
+        Cmd::AddI => a.wrapping_add(b),
+        Cmd::SubI => a.wrapping_sub(b),
+        Cmd::MultI => a.wrapping_mul(b),
+        Cmd::DivI if b != 0 => a.wrapping_div(b),
+        Cmd::ModI if b != 0 => a.wrapping_rem(b),
+        Cmd::AndI => a & b,
+        Cmd::OrI => a | b,
+        Cmd::XorI => a ^ b,
+        // Shifting right could be either arithmetic or logical, so only fold left shifts
+        Cmd::ShiftL if (0..32).contains(&b) => a << b,
+        Cmd::Equals => (a == b) as i32,
+        Cmd::NotEquals => (a != b) as i32,
+        Cmd::LessThan => (a < b) as i32,
+        Cmd::LessOrEqual => (a <= b) as i32,
+        Cmd::Greater => (a > b) as i32,
+        Cmd::GreaterOrEqual => (a >= b) as i32,
+        _ => return None,
+    })
+}
+
+fn invert(cmd: Cmd, loc: u32) -> Cmd {
+    match cmd {
+        Cmd::If { .. } => Cmd::IfNot { loc },
+        _ => Cmd::If { loc },
+    }
+}
+
+impl MscsbFile {
+    /// Run every `Optimizer` pass over the file
+    pub fn optimize(&mut self) -> Result<OptimizeStats> {
+        Optimizer::default().optimize(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    const SOURCE: &str = "
+main:
+    Begin 0, 1
+    PushInt. 2
+    PushInt. 3
+    MultI.
+    PushShort. 1
+    AddI.
+    SetVar 0, 0x0
+    PushVar. 0, 0x0
+    Not.
+    IfNot loc_skip
+    Jump loc_hop
+loc_skip:
+    PushInt. helper
+    CallFunc. 0
+    Pop
+loc_hop:
+    Jump loc_end
+loc_end:
+    End
+
+helper:
+    Begin 0, 0
+    PushInt. 0x12345
+    Return6
+";
+
+    #[test]
+    fn test_optimize() {
+        let mut file = assemble(SOURCE).unwrap();
+        let before = file.scripts[0].bounds.1 - file.scripts[0].bounds.0;
+        let stats = file.optimize().unwrap();
+        assert_eq!(stats.folded, 2);
+        assert_eq!(stats.inverted, 2);
+        assert_eq!(stats.removed_pops, 1);
+        assert!(stats.threaded >= 2);
+        assert_eq!(stats.narrowed, 1);
+
+        let expected = "\
+main:
+    Begin 0, 1
+    PushShort. 0x7
+    SetVar 0, 0x0
+    PushVar. 0, 0x0
+    IfNot loc_end
+    PushInt. helper
+    CallFunc 0
+loc_end:
+    End
+
+helper:
+    Begin 0, 0
+    PushInt. 0x12345
+    Return6
+";
+        let expected = assemble(expected).unwrap();
+        assert_eq!(file.disassemble(), expected.disassemble());
+        let after = file.scripts[0].bounds.1 - file.scripts[0].bounds.0;
+        assert_eq!(stats.bytes_saved, before - after);
+
+        let mut untouched = assemble(SOURCE).unwrap();
+        let off = Optimizer {
+            fold_constants: false,
+            invert_branches: false,
+            thread_jumps: false,
+            remove_pops: false,
+            narrow_pushes: false,
+        };
+        assert_eq!(off.optimize(&mut untouched).unwrap(), OptimizeStats::default());
+        assert_eq!(untouched.disassemble(), assemble(SOURCE).unwrap().disassemble());
+    }
+
+    #[test]
+    fn test_optimize_leaves_call_addresses_and_wide_values() {
+        let source = "
+main:
+    Begin 0, 0
+    PushInt. 0x8
+    PushInt. 0x8
+    AddI.
+    PushShort. 0xFFFF
+    PushShort. 0x1
+    AddI.
+    AddI.
+    PushInt. 0x8000
+    AddI.
+    PushInt. other
+    CallFunc. 0
+    Return6
+other:
+    Begin 0, 0
+    Return6
+";
+        let mut file = assemble(source).unwrap();
+        let other = file.scripts[1].bounds.0;
+        let stats = file.optimize().unwrap();
+        assert_eq!((stats.folded, stats.narrowed), (1, 1));
+        let text = file.disassemble();
+        // 8 + 8 is main's own address, but nothing calls it so it's just a number
+        assert!(text.contains("PushShort. 0x10\n    PushShort. 0xFFFF\n"), "{}", text);
+        assert!(text.contains("PushInt. 0x8000\n"));
+        // The call's address stays a PushInt and follows `other` down
+        assert!(text.contains("PushInt. script_1\n    CallFunc. 0"), "{}", text);
+        assert_eq!(file.scripts[1].bounds.0, other - stats.bytes_saved);
+    }
+
+    #[test]
+    fn test_rewrites_that_move_branches_are_rejected() {
+        let file = assemble(SOURCE).unwrap();
+        let before = Body::new(&file.scripts[0]);
+        let mut body = before.clone();
+        assert_eq!(body.thread_jumps(), 2);
+        assert!(body.keeps_flow(&before));
+
+        // `IfNot loc_skip` sent somewhere it could never go before
+        let branch = before.insns.iter().position(|insn| matches!(insn.cmd, Cmd::IfNot { .. })).unwrap();
+        let mut moved = before.clone();
+        moved.insns[branch].target = Some(1);
+        assert!(!moved.keeps_flow(&before));
+    }
+}
@@ -0,0 +1,772 @@
+use super::{Cmd, Command, DiffOp, MscsbFile, Script, ScriptChange};
+use super::error::{Error, Result};
+use super::hash::{command_token, normalize, Fnv, Reloc};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"MSCP";
+const VERSION: u8 = 2;
+// Scripts are laid out the same way `MscsbFile::write` does
+const SCRIPT_DATA_START: u32 = 0x10;
+// Commands hashed before an insertion point, grown if that isn't unique
+const ANCHOR_LEN: usize = 3;
+
+/// A script of the base file, by `MscsbFile::script_hash`. `nth` tells apart
+/// scripts that hash the same, counting from the start of the file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ScriptId {
+    pub hash: u64,
+    pub nth: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScriptRef {
+    Base(ScriptId),
+    /// Index into the scripts added by the patch
+    Added(u32),
+}
+
+/// Operand that depends on where things end up
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reference {
+    /// Branch to a command index in the same script, the length for its end
+    Command(u32),
+    /// `PushInt` or `PushShort` of the address a call jumps to
+    Script(ScriptRef),
+    /// Push of the index of `Patch::added_strings[n]`, wherever it ends up
+    String(u32),
+}
+
+/// A command whose branch target, script address or string index is stored symbolically
+#[derive(Debug, Clone)]
+pub struct PatchCommand {
+    pub cmd: Cmd,
+    pub push_bit: bool,
+    pub reference: Option<Reference>,
+}
+
+/// Where to insert commands: after the `len` commands that hash to `hash`, or at
+/// the start of the script when `len` is 0
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Anchor {
+    pub hash: u64,
+    pub len: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum PatchEdit {
+    ReplaceScript {
+        script: ScriptId,
+        commands: Vec<PatchCommand>,
+    },
+    /// Commands inserted into a script, which may not branch within the script
+    Insert {
+        script: ScriptId,
+        anchor: Anchor,
+        commands: Vec<PatchCommand>,
+    },
+    RemoveScript {
+        script: ScriptId,
+    },
+}
+
+impl PatchEdit {
+    pub fn script(&self) -> ScriptId {
+        match *self {
+            PatchEdit::ReplaceScript { script, .. } |
+            PatchEdit::Insert { script, .. } |
+            PatchEdit::RemoveScript { script } => script,
+        }
+    }
+}
+
+/// Edits relative to a base file, made with `Patch::diff` and applied with `Patch::apply`
+#[derive(Debug, Clone, Default)]
+pub struct Patch {
+    pub edits: Vec<PatchEdit>,
+    /// New scripts, laid out after the base file's
+    pub added_scripts: Vec<Vec<PatchCommand>>,
+    /// Strings the patch uses that the base file doesn't have. `apply` interns them and
+    /// rewrites the pushes referring to them, so patches adding strings can be combined.
+    pub added_strings: Vec<String>,
+    pub entrypoint: Option<ScriptRef>,
+}
+
+/// Two patches that can't both be applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub script: Option<ScriptId>,
+    pub message: String,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.script {
+            Some(script) => write!(f, "script {:016x}#{}: {}", script.hash, script.nth, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// A command while a patch is being made or applied, referring to scripts by index
+#[derive(Debug, Clone)]
+struct Work {
+    cmd: Cmd,
+    push_bit: bool,
+    target: Option<Target>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Target {
+    Command(usize),
+    Script(usize),
+    /// Index into `Patch::added_strings`
+    String(usize),
+}
+
+impl Work {
+    fn token(&self) -> u64 {
+        command_token(&self.cmd, self.push_bit, self.target.is_some() || self.cmd.branch_target().is_some())
+    }
+}
+
+fn script_ids(file: &MscsbFile) -> Vec<ScriptId> {
+    let mut seen: HashMap<u64, u32> = HashMap::new();
+    (0..file.scripts.len())
+        .map(|i| {
+            let hash = file.script_hash(i);
+            let nth = seen.entry(hash).or_insert(0);
+            *nth += 1;
+            ScriptId { hash, nth: *nth - 1 }
+        })
+        .collect()
+}
+
+fn symbolic(file: &MscsbFile, index: usize) -> Vec<Work> {
+    let script = &file.scripts[index];
+    script.iter()
+        .zip(normalize(script, |val| file.get_script_from_loc(val)))
+        .map(|(command, (_, reloc))| {
+            let target = match reloc {
+                Reloc::Branch { index, .. } => index.map(Target::Command),
+                Reloc::Script(script) => Some(Target::Script(script)),
+                Reloc::None => None,
+            };
+            Work { cmd: command.cmd, push_bit: command.push_bit, target }
+        })
+        .collect()
+}
+
+// Hash of the `len` commands before `at`
+fn anchor_hash(commands: &[Work], at: usize, len: usize) -> u64 {
+    let mut hash = Fnv::new();
+    for work in commands[at - len..at].iter() {
+        hash.write(&work.token().to_le_bytes());
+    }
+    hash.0
+}
+
+fn find_anchor(commands: &[Work], anchor: Anchor) -> Vec<usize> {
+    let len = anchor.len as usize;
+    if len == 0 {
+        return vec![0];
+    }
+    (len..=commands.len()).filter(|&at| anchor_hash(commands, at, len) == anchor.hash).collect()
+}
+
+impl Patch {
+    /// Describe how to turn `base` into `modified`. Fails if strings other than new
+    /// ones at the end of the table changed, or if the new ones are used in ways
+    /// `MscsbFile::string_references` can't follow.
+    pub fn diff(base: &MscsbFile, modified: &MscsbFile) -> Result<Patch> {
+        if !modified.strings.starts_with(&base.strings) {
+            return Err(Error::Format(String::from("strings can only be added to the end of the table")));
+        }
+        let ids = script_ids(base);
+        let diff = base.diff(modified);
+        let mut patch = Patch {
+            added_strings: modified.strings[base.strings.len()..].to_vec(),
+            ..Patch::default()
+        };
+
+        // Where each script of the modified file comes from
+        let mut refs: HashMap<usize, ScriptRef> = HashMap::new();
+        for change in diff.changes.iter() {
+            match *change {
+                ScriptChange::Unchanged { old, new } | ScriptChange::Modified { old, new, .. } => {
+                    refs.insert(new, ScriptRef::Base(ids[old]));
+                }
+                ScriptChange::Added { new } => {
+                    refs.insert(new, ScriptRef::Added(patch.added_scripts.len() as u32));
+                    patch.added_scripts.push(vec![]);
+                }
+                ScriptChange::Removed { old } => patch.edits.push(PatchEdit::RemoveScript { script: ids[old] }),
+            }
+        }
+        // Pushes of added strings, as (script, command) to index into `added_strings`
+        let mut added_strings: HashMap<(usize, usize), usize> = HashMap::new();
+        if !patch.added_strings.is_empty() {
+            for (script, command, string) in modified.string_references()? {
+                if let Some(k) = (string as usize).checked_sub(base.strings.len()) {
+                    added_strings.insert((script, command), k);
+                }
+            }
+        }
+        let modified_symbolic = |new: usize| -> Vec<Work> {
+            let mut commands = symbolic(modified, new);
+            for (i, work) in commands.iter_mut().enumerate() {
+                if let Some(&k) = added_strings.get(&(new, i)) {
+                    work.target = Some(Target::String(k));
+                }
+            }
+            commands
+        };
+        let convert = |commands: &[Work]| -> Vec<PatchCommand> {
+            commands.iter()
+                .map(|work| PatchCommand {
+                    cmd: work.cmd,
+                    push_bit: work.push_bit,
+                    reference: work.target.map(|target| match target {
+                        Target::Command(i) => Reference::Command(i as u32),
+                        Target::Script(s) => Reference::Script(refs[&s]),
+                        Target::String(k) => Reference::String(k as u32),
+                    }),
+                })
+                .collect()
+        };
+
+        for change in diff.changes.iter() {
+            match *change {
+                ScriptChange::Added { new } => {
+                    if let ScriptRef::Added(k) = refs[&new] {
+                        patch.added_scripts[k as usize] = convert(&modified_symbolic(new));
+                    }
+                }
+                ScriptChange::Modified { old, new, ref ops, .. } => {
+                    let base_commands = symbolic(base, old);
+                    let new_commands = modified_symbolic(new);
+                    match inserts(&base_commands, &new_commands, ops) {
+                        Some(runs) => {
+                            for (at, len, first) in runs {
+                                patch.edits.push(PatchEdit::Insert {
+                                    script: ids[old],
+                                    anchor: anchor_for(&base_commands, at),
+                                    commands: convert(&new_commands[first..first + len]),
+                                });
+                            }
+                        }
+                        None => patch.edits.push(PatchEdit::ReplaceScript {
+                            script: ids[old],
+                            commands: convert(&new_commands),
+                        }),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let base_entry = base.get_script_from_loc(base.entrypoint);
+        if let Some(entry) = modified.get_script_from_loc(modified.entrypoint) {
+            let entry = refs[&entry];
+            if base_entry.map(|i| ScriptRef::Base(ids[i])) != Some(entry) {
+                patch.entrypoint = Some(entry);
+            }
+        }
+        Ok(patch)
+    }
+
+    /// Apply the patch to `base`, failing if a script it edits isn't there. Added strings
+    /// are interned, so ones `base` already has are reused.
+    pub fn apply(&self, base: &MscsbFile) -> Result<MscsbFile> {
+        let ids = script_ids(base);
+        let by_id: HashMap<ScriptId, usize> = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+        let mut scripts: Vec<Option<Vec<Work>>> = (0..base.scripts.len()).map(|i| Some(symbolic(base, i))).collect();
+        let added_start = scripts.len();
+        let resolve = |script: ScriptRef| -> Result<usize> {
+            match script {
+                ScriptRef::Base(id) => by_id.get(&id).cloned()
+                    .ok_or_else(|| Error::Format(format!("script {:016x}#{} isn't in the base file", id.hash, id.nth))),
+                ScriptRef::Added(k) if (k as usize) < self.added_scripts.len() => Ok(added_start + k as usize),
+                ScriptRef::Added(k) => Err(Error::Format(format!("no added script {}", k))),
+            }
+        };
+        let to_work = |commands: &[PatchCommand]| -> Result<Vec<Work>> {
+            commands.iter()
+                .map(|command| Ok(Work {
+                    cmd: command.cmd,
+                    push_bit: command.push_bit,
+                    target: match command.reference {
+                        Some(Reference::Command(i)) => Some(Target::Command(i as usize)),
+                        Some(Reference::Script(script)) => Some(Target::Script(resolve(script)?)),
+                        Some(Reference::String(k)) if (k as usize) < self.added_strings.len() => {
+                            Some(Target::String(k as usize))
+                        }
+                        Some(Reference::String(k)) => return Err(Error::Format(format!("no added string {}", k))),
+                        None => None,
+                    },
+                }))
+                .collect()
+        };
+        for commands in self.added_scripts.iter() {
+            scripts.push(Some(to_work(commands)?));
+        }
+
+        for edit in self.edits.iter() {
+            let index = resolve(ScriptRef::Base(edit.script()))?;
+            let script = scripts[index].as_mut()
+                .ok_or_else(|| Error::Format(String::from("script edited after being removed")))?;
+            match *edit {
+                PatchEdit::RemoveScript { .. } => scripts[index] = None,
+                PatchEdit::ReplaceScript { ref commands, .. } => *script = to_work(commands)?,
+                PatchEdit::Insert { anchor, ref commands, .. } => {
+                    let at = match find_anchor(script, anchor)[..] {
+                        [at] => at,
+                        [] => return Err(Error::Format(String::from("insertion point not found"))),
+                        _ => return Err(Error::Format(String::from("insertion point is ambiguous"))),
+                    };
+                    let inserted = to_work(commands)?;
+                    for work in script.iter_mut() {
+                        if let Some(Target::Command(ref mut i)) = work.target {
+                            if *i >= at {
+                                *i += inserted.len();
+                            }
+                        }
+                    }
+                    script.splice(at..at, inserted);
+                }
+            }
+        }
+
+        let mut file = MscsbFile::new(vec![], base.strings.clone(), 0).with_platform(base.platform);
+        let string_indices: Vec<u32> = self.added_strings.iter().map(|string| file.intern(string)).collect();
+
+        let mut starts = vec![None; scripts.len()];
+        let mut position = SCRIPT_DATA_START;
+        for (start, script) in starts.iter_mut().zip(scripts.iter()) {
+            if let Some(script) = script {
+                *start = Some(position);
+                position += script.iter().map(|work| work.cmd.size()).sum::<u32>();
+            }
+        }
+        let mut laid_out = vec![];
+        for (script, start) in scripts.iter().zip(starts.iter()) {
+            let (script, start) = match (script, *start) {
+                (Some(script), Some(start)) => (script, start),
+                _ => continue,
+            };
+            let mut positions = Vec::with_capacity(script.len() + 1);
+            let mut position = start;
+            for work in script.iter() {
+                positions.push(position);
+                position += work.cmd.size();
+            }
+            positions.push(position);
+            let mut commands = Vec::with_capacity(script.len());
+            for (work, &position) in script.iter().zip(positions.iter()) {
+                let mut cmd = work.cmd;
+                match work.target {
+                    Some(Target::Command(i)) => {
+                        let loc = *positions.get(i).ok_or_else(|| Error::Format(String::from("branch past the end of a script")))?;
+                        cmd.set_branch_target(loc);
+                    }
+                    Some(Target::Script(s)) => {
+                        let start = starts[s].ok_or_else(|| Error::Format(String::from("reference to a removed script")))?;
+                        match cmd {
+                            Cmd::PushInt { ref mut val } => *val = start,
+                            Cmd::PushShort { ref mut val } if start <= u16::MAX as u32 => *val = start as u16,
+                            _ => return Err(Error::Format(format!("script address 0x{:X} can't be pushed by {}", start, cmd.name()))),
+                        }
+                    }
+                    Some(Target::String(k)) => {
+                        let string = string_indices[k];
+                        match cmd {
+                            Cmd::PushInt { ref mut val } => *val = string,
+                            Cmd::PushShort { ref mut val } if string <= u16::MAX as u32 => *val = string as u16,
+                            _ => return Err(Error::Format(format!("string {} can't be pushed by {}", string, cmd.name()))),
+                        }
+                    }
+                    None => {}
+                }
+                commands.push(Command { cmd, push_bit: work.push_bit, position });
+            }
+            laid_out.push(Script { commands, bounds: (start, position) });
+        }
+
+        let entrypoint = match self.entrypoint {
+            Some(script) => starts[resolve(script)?],
+            None => match base.get_script_from_loc(base.entrypoint) {
+                Some(i) => starts[i],
+                None => Some(base.entrypoint),
+            },
+        };
+        file.entrypoint = entrypoint.ok_or_else(|| Error::Format(String::from("entrypoint script was removed")))?;
+        file.scripts = laid_out;
+        Ok(file)
+    }
+
+    /// Ways this patch and `other` would step on each other if both were applied to
+    /// the same base
+    pub fn conflicts(&self, other: &Patch) -> Vec<Conflict> {
+        let mut conflicts = vec![];
+        for ours in self.edits.iter() {
+            for theirs in other.edits.iter() {
+                if ours.script() != theirs.script() {
+                    continue;
+                }
+                let message = match (ours, theirs) {
+                    (PatchEdit::Insert { anchor: a, .. }, PatchEdit::Insert { anchor: b, .. }) => {
+                        if a != b {
+                            continue;
+                        }
+                        "both insert at the same place"
+                    }
+                    (PatchEdit::RemoveScript { .. }, PatchEdit::RemoveScript { .. }) => continue,
+                    (PatchEdit::RemoveScript { .. }, _) | (_, PatchEdit::RemoveScript { .. }) => {
+                        "one removes a script the other edits"
+                    }
+                    _ => "both change the script",
+                };
+                conflicts.push(Conflict { script: Some(ours.script()), message: String::from(message) });
+            }
+        }
+        if let (Some(a), Some(b)) = (self.entrypoint, other.entrypoint) {
+            if a != b {
+                conflicts.push(Conflict { script: None, message: String::from("both change the entrypoint") });
+            }
+        }
+        conflicts
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut f = vec![];
+        f.extend_from_slice(MAGIC);
+        f.push(VERSION);
+        f.write_u32::<LittleEndian>(self.added_strings.len() as u32).unwrap();
+        for string in self.added_strings.iter() {
+            write_bytes(&mut f, string.as_bytes());
+        }
+        write_script_ref(&mut f, self.entrypoint);
+        f.write_u32::<LittleEndian>(self.added_scripts.len() as u32).unwrap();
+        for commands in self.added_scripts.iter() {
+            write_commands(&mut f, commands);
+        }
+        f.write_u32::<LittleEndian>(self.edits.len() as u32).unwrap();
+        for edit in self.edits.iter() {
+            match *edit {
+                PatchEdit::ReplaceScript { script, ref commands } => {
+                    f.push(0);
+                    write_script_id(&mut f, script);
+                    write_commands(&mut f, commands);
+                }
+                PatchEdit::Insert { script, anchor, ref commands } => {
+                    f.push(1);
+                    write_script_id(&mut f, script);
+                    f.write_u64::<LittleEndian>(anchor.hash).unwrap();
+                    f.write_u32::<LittleEndian>(anchor.len).unwrap();
+                    write_commands(&mut f, commands);
+                }
+                PatchEdit::RemoveScript { script } => {
+                    f.push(2);
+                    write_script_id(&mut f, script);
+                }
+            }
+        }
+        f
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Patch> {
+        let mut f = Cursor::new(bytes);
+        let mut magic = [0; 4];
+        f.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Format(String::from("not a patch file")));
+        }
+        let version = f.read_u8()?;
+        if version != VERSION {
+            return Err(Error::Format(format!("unsupported patch version {}", version)));
+        }
+        let mut patch = Patch::default();
+        for _ in 0..f.read_u32::<LittleEndian>()? {
+            let bytes = read_bytes(&mut f)?;
+            patch.added_strings.push(String::from_utf8(bytes).map_err(|_| Error::Format(String::from("string isn't UTF-8")))?);
+        }
+        patch.entrypoint = read_script_ref(&mut f)?;
+        for _ in 0..f.read_u32::<LittleEndian>()? {
+            patch.added_scripts.push(read_commands(&mut f)?);
+        }
+        for _ in 0..f.read_u32::<LittleEndian>()? {
+            let kind = f.read_u8()?;
+            let script = read_script_id(&mut f)?;
+            patch.edits.push(match kind {
+                0 => PatchEdit::ReplaceScript { script, commands: read_commands(&mut f)? },
+                1 => {
+                    let hash = f.read_u64::<LittleEndian>()?;
+                    let len = f.read_u32::<LittleEndian>()?;
+                    PatchEdit::Insert { script, anchor: Anchor { hash, len }, commands: read_commands(&mut f)? }
+                }
+                2 => PatchEdit::RemoveScript { script },
+                _ => return Err(Error::Format(format!("unknown edit kind {}", kind))),
+            });
+        }
+        Ok(patch)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Patch> {
+        Patch::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+}
+
+// Runs of inserted commands as (base index to insert at, length, first index in the
+// new script), if the change is nothing but self-contained insertions
+fn inserts(base: &[Work], new: &[Work], ops: &[DiffOp]) -> Option<Vec<(usize, usize, usize)>> {
+    let mut runs: Vec<(usize, usize, usize)> = vec![];
+    let mut at = 0;
+    for op in ops {
+        match *op {
+            DiffOp::Delete { .. } => return None,
+            DiffOp::Equal { old, .. } => at = old + 1,
+            DiffOp::Insert { new: j } => {
+                if let Some(Target::Command(_)) = new[j].target {
+                    return None;
+                }
+                match runs.last_mut() {
+                    Some(run) if run.0 == at && run.2 + run.1 == j => run.1 += 1,
+                    _ => runs.push((at, 1, j)),
+                }
+            }
+        }
+    }
+    // Every insertion point needs an anchor that only matches once
+    if runs.iter().any(|&(at, _, _)| find_anchor(base, anchor_for(base, at)).len() != 1) {
+        return None;
+    }
+    Some(runs)
+}
+
+fn anchor_for(commands: &[Work], at: usize) -> Anchor {
+    let mut len = ANCHOR_LEN.min(at);
+    loop {
+        let anchor = Anchor { hash: if len == 0 { 0 } else { anchor_hash(commands, at, len) }, len: len as u32 };
+        if len == at || find_anchor(commands, anchor).len() == 1 {
+            return anchor;
+        }
+        len += 1;
+    }
+}
+
+fn write_bytes(f: &mut Vec<u8>, bytes: &[u8]) {
+    f.write_u32::<LittleEndian>(bytes.len() as u32).unwrap();
+    f.extend_from_slice(bytes);
+}
+
+fn read_bytes(f: &mut Cursor<&[u8]>) -> Result<Vec<u8>> {
+    let len = f.read_u32::<LittleEndian>()? as usize;
+    if len > f.get_ref().len() {
+        return Err(Error::Format(String::from("truncated patch")));
+    }
+    let mut bytes = vec![0; len];
+    f.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn write_script_id(f: &mut Vec<u8>, script: ScriptId) {
+    f.write_u64::<LittleEndian>(script.hash).unwrap();
+    f.write_u32::<LittleEndian>(script.nth).unwrap();
+}
+
+fn read_script_id(f: &mut Cursor<&[u8]>) -> Result<ScriptId> {
+    Ok(ScriptId { hash: f.read_u64::<LittleEndian>()?, nth: f.read_u32::<LittleEndian>()? })
+}
+
+fn write_script_ref(f: &mut Vec<u8>, script: Option<ScriptRef>) {
+    match script {
+        None => f.push(0),
+        Some(ScriptRef::Base(id)) => {
+            f.push(1);
+            write_script_id(f, id);
+        }
+        Some(ScriptRef::Added(k)) => {
+            f.push(2);
+            f.write_u32::<LittleEndian>(k).unwrap();
+        }
+    }
+}
+
+fn read_script_ref(f: &mut Cursor<&[u8]>) -> Result<Option<ScriptRef>> {
+    Ok(match f.read_u8()? {
+        0 => None,
+        1 => Some(ScriptRef::Base(read_script_id(f)?)),
+        2 => Some(ScriptRef::Added(f.read_u32::<LittleEndian>()?)),
+        tag => return Err(Error::Format(format!("unknown script reference {}", tag))),
+    })
+}
+
+fn write_commands(f: &mut Vec<u8>, commands: &[PatchCommand]) {
+    f.write_u32::<LittleEndian>(commands.len() as u32).unwrap();
+    for command in commands {
+        write_bytes(f, command.cmd.name().as_bytes());
+        f.push(command.push_bit as u8);
+        let operands = command.cmd.operands();
+        f.push(operands.len() as u8);
+        for operand in operands {
+            f.write_u32::<LittleEndian>(operand).unwrap();
+        }
+        match command.reference {
+            None => f.push(0),
+            Some(Reference::Command(i)) => {
+                f.push(1);
+                f.write_u32::<LittleEndian>(i).unwrap();
+            }
+            Some(Reference::Script(script)) => {
+                f.push(2);
+                write_script_ref(f, Some(script));
+            }
+            Some(Reference::String(k)) => {
+                f.push(3);
+                f.write_u32::<LittleEndian>(k).unwrap();
+            }
+        }
+    }
+}
+
+fn read_commands(f: &mut Cursor<&[u8]>) -> Result<Vec<PatchCommand>> {
+    let count = f.read_u32::<LittleEndian>()?;
+    let mut commands = vec![];
+    for _ in 0..count {
+        let name = String::from_utf8(read_bytes(f)?).unwrap_or_default();
+        let push_bit = f.read_u8()? != 0;
+        let mut operands = vec![];
+        for _ in 0..f.read_u8()? {
+            operands.push(f.read_u32::<LittleEndian>()?);
+        }
+        let cmd = Cmd::from_parts(&name, &operands)
+            .ok_or_else(|| Error::Format(format!("bad command {} {:?}", name, operands)))?;
+        let reference = match f.read_u8()? {
+            0 => None,
+            1 => Some(Reference::Command(f.read_u32::<LittleEndian>()?)),
+            2 => read_script_ref(f)?.map(Reference::Script),
+            3 => Some(Reference::String(f.read_u32::<LittleEndian>()?)),
+            tag => return Err(Error::Format(format!("unknown reference {}", tag))),
+        };
+        commands.push(PatchCommand { cmd, push_bit, reference });
+    }
+    Ok(commands)
+}
+
+impl MscsbFile {
+    /// Make a patch that turns this file into `modified`, see `Patch::diff`
+    pub fn make_patch(&self, modified: &MscsbFile) -> Result<Patch> {
+        Patch::diff(self, modified)
+    }
+
+    pub fn apply_patch(&self, patch: &Patch) -> Result<MscsbFile> {
+        patch.apply(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    const BASE: &str = "
+.entrypoint main
+.string \"hello\"
+main:
+    Begin 0, 1
+    PushShort. 0x0
+    SetVar 0, 0x0
+loc_top:
+    PushVar. 0, 0x0
+    PushShort. 0x3
+    LessThan.
+    IfNot loc_end
+    PushInt. helper
+    CallFunc 0
+    IncI 0, 0x0
+    Jump loc_top
+loc_end:
+    End
+
+helper:
+    Begin 0, 0
+    PushShort. 0x1
+    Sys 1, 0x10
+    End
+
+unused:
+    Begin 0, 0
+    End
+";
+
+    #[test]
+    fn test_patch_round_trip() {
+        let base = assemble(BASE).unwrap();
+        let text = BASE
+            .replace(".string \"hello\"\n", ".string \"hello\"\n.string \"added\"\n")
+            .replace("    PushInt. helper\n", "    PushShort. 0x2\n    Sys 1, 0x20\n    PushInt. extra\n    CallFunc 0\n    PushInt. helper\n")
+            .replace("Sys 1, 0x10", "Sys 1, 0x11")
+            .replace("unused:\n    Begin 0, 0\n    End\n", "extra:\n    Begin 1, 0\n    PushShort. 0x7\n    Return8\n");
+        let modified = assemble(&text).unwrap();
+
+        let patch = Patch::diff(&base, &modified).unwrap();
+        assert_eq!(patch.added_strings, vec![String::from("added")]);
+        assert_eq!(patch.added_scripts.len(), 1);
+        let kinds: Vec<u8> = patch.edits.iter()
+            .map(|edit| match edit {
+                PatchEdit::ReplaceScript { .. } => 0,
+                PatchEdit::Insert { .. } => 1,
+                PatchEdit::RemoveScript { .. } => 2,
+            })
+            .collect();
+        assert_eq!(kinds, vec![2, 1, 0]);
+
+        let patch = Patch::from_bytes(&patch.to_bytes()).unwrap();
+        let patched = base.apply_patch(&patch).unwrap();
+        assert_eq!(patched.disassemble(), modified.disassemble());
+
+        // Something else already changed `helper`, so both patches can't apply
+        let other = Patch::diff(&base, &assemble(&BASE.replace("Sys 1, 0x10", "Sys 1, 0x12")).unwrap()).unwrap();
+        let conflicts = patch.conflicts(&other);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].script, Some(patch.edits[2].script()));
+        let moved = base.apply_patch(&other).unwrap();
+        assert!(patch.apply(&moved).is_err());
+        assert!(Patch::from_bytes(b"MSCP\x09").is_err());
+    }
+
+    #[test]
+    fn test_patches_adding_strings() {
+        let base = assemble(BASE).unwrap();
+        let printing = |text: &str, string: &str| {
+            assemble(&text.replace(".string \"hello\"\n", &format!(".string \"hello\"\n.string \"{}\"\n", string))).unwrap()
+        };
+        let a = BASE.replace("    Sys 1, 0x10\n", "    Sys 1, 0x10\n    PushShort. 0x1\n    PrintF 1\n");
+        let a = Patch::diff(&base, &printing(&a, "from a")).unwrap();
+        let b = BASE.replace("unused:\n    Begin 0, 0\n", "unused:\n    Begin 0, 0\n    PushShort. 0x1\n    PrintF 1\n");
+        let b = Patch::diff(&base, &printing(&b, "from b")).unwrap();
+        assert!(a.conflicts(&b).is_empty());
+
+        // Both patches use index 1 for their string, so the second one has to move
+        let both = b.apply(&a.apply(&base).unwrap()).unwrap();
+        assert_eq!(both.strings, vec!["hello", "from a", "from b"]);
+        assert_eq!(both.printf_string(1, 4), Some(1));
+        assert_eq!(both.printf_string(2, 2), Some(2));
+
+        // A base that already has the string keeps using its copy
+        let mut has = base.clone();
+        has.strings.extend(vec![String::from("other"), String::from("from a")]);
+        let reused = a.apply(&has).unwrap();
+        assert_eq!(reused.strings.len(), 3);
+        assert_eq!(reused.printf_string(1, 4), Some(2));
+        assert_eq!(Patch::from_bytes(&b.to_bytes()).unwrap().apply(&base).unwrap().printf_string(2, 2), Some(1));
+    }
+}
@@ -0,0 +1,239 @@
+use super::{Cmd, MscsbFile, Script};
+use super::error::{Error, Result};
+use std::fmt;
+
+/// Type of a value on the stack
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValueType {
+    Int,
+    Float,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ValueType::Int => "int",
+            ValueType::Float => "float",
+        })
+    }
+}
+
+/// A single `%` conversion. `%s` takes an index into `MscsbFile::strings`, so its
+/// argument is an int.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatSpec {
+    /// Byte offset of the `%`
+    pub offset: usize,
+    /// The whole specifier, such as `%-4.2f`
+    pub text: String,
+    pub conversion: char,
+    pub arg_type: ValueType,
+}
+
+/// A parsed printf format string. Flags (`-+ #0`), a width, a precision and the
+/// `h`/`l`/`ll` length modifiers are accepted, `*` widths and `%n` are not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatString {
+    pub specs: Vec<FormatSpec>,
+}
+
+impl FormatString {
+    pub fn parse(text: &str) -> Result<FormatString> {
+        let mut specs = vec![];
+        let mut chars = text.char_indices().peekable();
+        while let Some((offset, c)) = chars.next() {
+            if c != '%' {
+                continue;
+            }
+            let error = |message: &str| Error::Format(format!("bad format at byte {}: {}", offset, message));
+            let mut end = offset + 1;
+            let mut conversion = None;
+            while let Some(&(i, c)) = chars.peek() {
+                chars.next();
+                end = i + c.len_utf8();
+                match c {
+                    '-' | '+' | ' ' | '#' | '.' | 'h' | 'l' | '0'..='9' => continue,
+                    '*' => return Err(error("'*' widths are not supported")),
+                    _ => {
+                        conversion = Some(c);
+                        break;
+                    }
+                }
+            }
+            let arg_type = match conversion {
+                Some('%') if end == offset + 2 => continue,
+                Some('d') | Some('i') | Some('u') | Some('x') | Some('X') | Some('o') | Some('c') |
+                Some('s') => ValueType::Int,
+                Some('f') | Some('F') | Some('e') | Some('E') | Some('g') | Some('G') => ValueType::Float,
+                Some(c) => return Err(error(&format!("unsupported conversion '%{}'", c))),
+                None => return Err(error("unterminated conversion")),
+            };
+            specs.push(FormatSpec {
+                offset,
+                text: String::from(&text[offset..end]),
+                conversion: conversion.unwrap(),
+                arg_type,
+            });
+        }
+        Ok(FormatString { specs })
+    }
+
+    /// Indices of the arguments that are string indices, the format string not included
+    pub fn string_args(&self) -> impl Iterator<Item = usize> + '_ {
+        self.specs.iter().enumerate().filter(|(_, spec)| spec.conversion == 's').map(|(i, _)| i)
+    }
+
+    /// Problems with passing arguments of these types, the format string not included.
+    /// `None` types are unknown and never reported.
+    pub fn check(&self, args: &[Option<ValueType>]) -> Vec<String> {
+        let mut problems = vec![];
+        if args.len() != self.specs.len() {
+            problems.push(format!(
+                "format expects {} arg{} but {} {} passed",
+                self.specs.len(), if self.specs.len() == 1 { "" } else { "s" },
+                args.len(), if args.len() == 1 { "is" } else { "are" }
+            ));
+        }
+        for (i, (spec, arg)) in self.specs.iter().zip(args.iter()).enumerate() {
+            match *arg {
+                Some(arg) if arg != spec.arg_type => problems.push(format!(
+                    "arg {} is {} {} but {} expects {} {}",
+                    i + 1, article(arg), arg, spec.text, article(spec.arg_type), spec.arg_type
+                )),
+                _ => {}
+            }
+        }
+        problems
+    }
+}
+
+fn article(value_type: ValueType) -> &'static str {
+    match value_type {
+        ValueType::Int => "an",
+        ValueType::Float => "a",
+    }
+}
+
+/// Type of the value command `index` leaves on the stack, if it can be known
+pub(crate) fn value_type(script: &Script, index: usize) -> Option<ValueType> {
+    Some(match script.commands[index].cmd {
+        Cmd::AddF | Cmd::SubF | Cmd::MultF | Cmd::DivF | Cmd::NegF | Cmd::IntToFloat { .. } => ValueType::Float,
+        Cmd::PushShort { .. } | Cmd::FloatToInt { .. } |
+        Cmd::AddI | Cmd::SubI | Cmd::MultI | Cmd::DivI | Cmd::ModI | Cmd::NegI |
+        Cmd::AndI | Cmd::OrI | Cmd::NotI | Cmd::XorI | Cmd::ShiftL | Cmd::ShiftR | Cmd::Not |
+        Cmd::Equals | Cmd::NotEquals | Cmd::LessThan | Cmd::LessOrEqual |
+        Cmd::Greater | Cmd::GreaterOrEqual |
+        Cmd::EqualsF | Cmd::NotEqualsF | Cmd::LessThanF | Cmd::LessOrEqualF |
+        Cmd::GreaterF | Cmd::GreaterOrEqualF => ValueType::Int,
+        // PushInt also pushes float constants as raw bits
+        _ => return None,
+    })
+}
+
+/// For the `PrintF` at `index`, the command pushing its format string and the string's index.
+/// `sources` is `Script::operand_sources` for the script.
+pub(crate) fn format_source(script: &Script, sources: &[Vec<Option<usize>>], index: usize) -> Option<(usize, u32)> {
+    match script.commands[index].cmd {
+        Cmd::PrintF { arg_count } if arg_count > 0 => {}
+        _ => return None,
+    }
+    // The format string is the first argument
+    let source = sources[index][0]?;
+    match script.commands[source].cmd {
+        Cmd::PushInt { val } => Some((source, val)),
+        Cmd::PushShort { val } => Some((source, val as u32)),
+        _ => None,
+    }
+}
+
+// Problems with the `PrintF` at `index`, given the script's operand sources
+pub(crate) fn check(file: &MscsbFile, script: &Script, sources: &[Vec<Option<usize>>], index: usize) -> Vec<String> {
+    let (_, string) = match format_source(script, sources, index) {
+        Some(found) => found,
+        None => return vec![],
+    };
+    let text = match file.strings.get(string as usize) {
+        Some(text) => text,
+        None => return vec![format!("format string {} is out of range", string)],
+    };
+    match FormatString::parse(text) {
+        Ok(format) => {
+            let args: Vec<Option<ValueType>> = sources[index][1..].iter()
+                .map(|source| source.and_then(|source| value_type(script, source)))
+                .collect();
+            format.check(&args)
+        }
+        Err(e) => vec![e.to_string()],
+    }
+}
+
+impl MscsbFile {
+    /// Index of the string the `PrintF` at `index` in a script formats, if it's pushed
+    /// by straight-line code before it
+    pub fn printf_string(&self, script: usize, index: usize) -> Option<u32> {
+        let script = &self.scripts[script];
+        format_source(script, &script.operand_sources(), index).map(|(_, string)| string)
+    }
+
+    /// Ways the `PrintF` at `index` disagrees with its format string
+    pub fn check_printf(&self, script: usize, index: usize) -> Vec<String> {
+        let script = &self.scripts[script];
+        check(self, script, &script.operand_sources(), index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn test_printf() {
+        let format = FormatString::parse("%d%% of %-4.2f, %lx\n").unwrap();
+        let conversions: Vec<(&str, ValueType)> = format.specs.iter().map(|s| (&s.text[..], s.arg_type)).collect();
+        assert_eq!(conversions, vec![("%d", ValueType::Int), ("%-4.2f", ValueType::Float), ("%lx", ValueType::Int)]);
+        assert_eq!(format.specs[1].offset, 8);
+        assert!(FormatString::parse("%*d").is_err());
+        let strings = FormatString::parse("%s: %5s %d").unwrap();
+        assert_eq!(strings.string_args().collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(strings.check(&[Some(ValueType::Int), None]), vec!["format expects 3 args but 2 are passed"]);
+        assert!(FormatString::parse("%n").is_err());
+        assert!(FormatString::parse("50%").is_err());
+
+        let file = assemble(r#"
+.string "%d and %f\n"
+.string "%d\n"
+main:
+    Begin 0, 1
+    PushShort. 0x0
+    PushShort. 0x1
+    PushVar. 0, 0x0
+    IntToFloat 0
+    PrintF 3
+    PushShort. 0x0
+    PushShort. 0x5
+    PushShort. 0x2
+    PushShort. 0x3
+    AddI.
+    PrintF 3
+    PushShort. 0x1
+    PushInt. 0x3F800000
+    PushShort. 0x2
+    PrintF 3
+    PushShort. 0x7
+    PrintF 1
+    End
+"#).unwrap();
+        assert_eq!(file.printf_string(0, 5), Some(0));
+        assert!(file.check_printf(0, 5).is_empty());
+        assert_eq!(file.check_printf(0, 11), vec!["arg 2 is an int but %f expects a float"]);
+        assert_eq!(file.check_printf(0, 15), vec!["format expects 1 arg but 2 are passed"]);
+        assert_eq!(file.check_printf(0, 17), vec!["format string 7 is out of range"]);
+
+        let problems: Vec<String> = file.validate().into_iter().map(|d| d.message).collect();
+        assert!(problems.contains(&String::from("format expects 1 arg but 2 are passed")));
+        let listing = file.disassemble();
+        assert!(listing.contains("PrintF 3                ; \"%d and %f\\n\"\n"));
+        assert!(listing.contains("; \"%d\\n\" - format expects 1 arg but 2 are passed"));
+    }
+}
@@ -0,0 +1,336 @@
+// pyo3 0.22's generated wrappers for methods returning `PyResult` trip this lint
+#![allow(clippy::useless_conversion)]
+
+use super::{Cmd, Command, MscsbFile, Platform, Script};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyList};
+
+/// An instruction and its operands, immutable from Python
+#[pyclass(name = "Cmd", module = "msc", frozen)]
+#[derive(Clone)]
+struct PyCmd {
+    cmd: Cmd,
+}
+
+#[pymethods]
+impl PyCmd {
+    #[new]
+    #[pyo3(signature = (name, operands = vec![]))]
+    fn new(name: &str, operands: Vec<u32>) -> PyResult<PyCmd> {
+        Cmd::from_parts(name, &operands)
+            .map(|cmd| PyCmd { cmd })
+            .ok_or_else(|| PyValueError::new_err(format!("bad instruction {} {:?}", name, operands)))
+    }
+
+    #[getter]
+    fn name(&self) -> &'static str {
+        self.cmd.name()
+    }
+
+    #[getter]
+    fn operands(&self) -> Vec<u32> {
+        self.cmd.operands()
+    }
+
+    #[getter]
+    fn opcode(&self) -> u8 {
+        self.cmd.value()
+    }
+
+    #[getter]
+    fn size(&self) -> u32 {
+        self.cmd.size()
+    }
+
+    fn __eq__(&self, other: &PyCmd) -> bool {
+        self.cmd.name() == other.cmd.name() && self.cmd.operands() == other.cmd.operands()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Cmd({:?}, {:?})", self.cmd.name(), self.cmd.operands())
+    }
+}
+
+#[pyclass(name = "Command", module = "msc")]
+struct PyCommand {
+    cmd: Cmd,
+    #[pyo3(get, set)]
+    push_bit: bool,
+    #[pyo3(get, set)]
+    position: u32,
+}
+
+#[pymethods]
+impl PyCommand {
+    /// The push bit defaults to set for commands that produce a value
+    #[new]
+    #[pyo3(signature = (cmd, push_bit = None, position = 0))]
+    fn new(cmd: PyCmd, push_bit: Option<bool>, position: u32) -> PyCommand {
+        PyCommand {
+            push_bit: push_bit.unwrap_or_else(|| cmd.cmd.produces_value()),
+            cmd: cmd.cmd,
+            position,
+        }
+    }
+
+    #[getter]
+    fn cmd(&self) -> PyCmd {
+        PyCmd { cmd: self.cmd }
+    }
+
+    #[setter]
+    fn set_cmd(&mut self, cmd: PyCmd) {
+        self.cmd = cmd.cmd;
+    }
+
+    #[getter]
+    fn name(&self) -> &'static str {
+        self.cmd.name()
+    }
+
+    #[getter]
+    fn operands(&self) -> Vec<u32> {
+        self.cmd.operands()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Command({:?}, {:?}, push_bit={}, position=0x{:X})",
+                self.cmd.name(), self.cmd.operands(), self.push_bit, self.position)
+    }
+}
+
+impl PyCommand {
+    fn from_command(command: &Command) -> PyCommand {
+        PyCommand { cmd: command.cmd, push_bit: command.push_bit, position: command.position }
+    }
+
+    fn to_command(&self) -> Command {
+        Command { cmd: self.cmd, push_bit: self.push_bit, position: self.position }
+    }
+}
+
+/// A script. `commands` is a list of `Command` that can be changed in place.
+#[pyclass(name = "Script", module = "msc")]
+struct PyScript {
+    commands: Py<PyList>,
+    #[pyo3(get, set)]
+    bounds: (u32, u32),
+}
+
+#[pymethods]
+impl PyScript {
+    #[new]
+    #[pyo3(signature = (commands = None, bounds = (0x10, 0x10)))]
+    fn new(py: Python, commands: Option<Bound<PyList>>, bounds: (u32, u32)) -> PyScript {
+        let commands = commands.unwrap_or_else(|| PyList::empty_bound(py));
+        PyScript { commands: commands.unbind(), bounds }
+    }
+
+    #[getter]
+    fn commands(&self, py: Python) -> Py<PyList> {
+        self.commands.clone_ref(py)
+    }
+
+    #[setter]
+    fn set_commands(&mut self, commands: Bound<PyList>) {
+        self.commands = commands.unbind();
+    }
+
+    fn __len__(&self, py: Python) -> usize {
+        self.commands.bind(py).len()
+    }
+
+    fn __repr__(&self, py: Python) -> String {
+        format!("Script(0x{:X} - 0x{:X}, {} commands)", self.bounds.0, self.bounds.1, self.__len__(py))
+    }
+}
+
+impl PyScript {
+    fn from_script(py: Python, script: &Script) -> PyResult<PyScript> {
+        let commands = PyList::empty_bound(py);
+        for command in script.iter() {
+            commands.append(Py::new(py, PyCommand::from_command(command))?)?;
+        }
+        Ok(PyScript { commands: commands.unbind(), bounds: script.bounds })
+    }
+
+    fn to_script(&self, py: Python) -> PyResult<Script> {
+        let commands = self.commands.bind(py)
+            .iter()
+            .map(|command| Ok(command.downcast::<PyCommand>()?.borrow().to_command()))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(Script { commands, bounds: self.bounds })
+    }
+}
+
+/// An mscsb file. `scripts` and `strings` are lists that can be changed in place
+/// and are read back when the file is written.
+#[pyclass(name = "MscsbFile", module = "msc")]
+struct PyMscsbFile {
+    scripts: Py<PyList>,
+    strings: Py<PyList>,
+    #[pyo3(get, set)]
+    entrypoint: u32,
+    platform: Platform,
+}
+
+#[pymethods]
+impl PyMscsbFile {
+    #[new]
+    fn new(py: Python) -> PyMscsbFile {
+        PyMscsbFile {
+            scripts: PyList::empty_bound(py).unbind(),
+            strings: PyList::empty_bound(py).unbind(),
+            entrypoint: 0x10,
+            platform: Platform::default(),
+        }
+    }
+
+    #[staticmethod]
+    fn open(py: Python, path: &str) -> PyResult<PyMscsbFile> {
+        let bytes = std::fs::read(path).map_err(|e| PyIOError::new_err(format!("{}: {}", path, e)))?;
+        PyMscsbFile::from_bytes(py, &bytes)
+    }
+
+    #[staticmethod]
+    fn from_bytes(py: Python, data: &[u8]) -> PyResult<PyMscsbFile> {
+        let file = MscsbFile::from_bytes(data)
+            .ok_or_else(|| PyValueError::new_err("not a valid mscsb file"))?;
+        PyMscsbFile::from_file(py, &file)
+    }
+
+    /// Assemble text in the format `disassemble` produces
+    #[staticmethod]
+    fn assemble(py: Python, text: &str) -> PyResult<PyMscsbFile> {
+        let file = MscsbFile::assemble(text).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        PyMscsbFile::from_file(py, &file)
+    }
+
+    fn write<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let mut bytes = vec![];
+        self.to_file(py)?.write(&mut bytes);
+        Ok(PyBytes::new_bound(py, &bytes))
+    }
+
+    fn write_to_file(&self, py: Python, path: &str) -> PyResult<()> {
+        self.to_file(py)?
+            .write_to_file(path)
+            .map_err(|e| PyIOError::new_err(format!("{}: {}", path, e)))
+    }
+
+    fn disassemble(&self, py: Python) -> PyResult<String> {
+        Ok(self.to_file(py)?.disassemble())
+    }
+
+    #[getter]
+    fn scripts(&self, py: Python) -> Py<PyList> {
+        self.scripts.clone_ref(py)
+    }
+
+    #[setter]
+    fn set_scripts(&mut self, scripts: Bound<PyList>) {
+        self.scripts = scripts.unbind();
+    }
+
+    #[getter]
+    fn strings(&self, py: Python) -> Py<PyList> {
+        self.strings.clone_ref(py)
+    }
+
+    #[setter]
+    fn set_strings(&mut self, strings: Bound<PyList>) {
+        self.strings = strings.unbind();
+    }
+
+    /// `"wiiu"` or `"3ds"`, set to convert the file when it's written
+    #[getter]
+    fn platform(&self) -> String {
+        self.platform.to_string()
+    }
+
+    #[setter]
+    fn set_platform(&mut self, platform: &str) -> PyResult<()> {
+        self.platform = platform.parse().map_err(|e: crate::Error| PyValueError::new_err(e.to_string()))?;
+        Ok(())
+    }
+
+    fn __repr__(&self, py: Python) -> String {
+        format!("MscsbFile({} scripts, {} strings)", self.scripts.bind(py).len(), self.strings.bind(py).len())
+    }
+}
+
+impl PyMscsbFile {
+    fn from_file(py: Python, file: &MscsbFile) -> PyResult<PyMscsbFile> {
+        let scripts = PyList::empty_bound(py);
+        for script in file.iter() {
+            scripts.append(Py::new(py, PyScript::from_script(py, script)?)?)?;
+        }
+        Ok(PyMscsbFile {
+            scripts: scripts.unbind(),
+            strings: PyList::new_bound(py, file.strings.iter()).unbind(),
+            entrypoint: file.entrypoint,
+            platform: file.platform,
+        })
+    }
+
+    fn to_file(&self, py: Python) -> PyResult<MscsbFile> {
+        let scripts = self.scripts.bind(py)
+            .iter()
+            .map(|script| script.downcast::<PyScript>()?.borrow().to_script(py))
+            .collect::<PyResult<Vec<_>>>()?;
+        let strings = self.strings.bind(py).extract::<Vec<String>>()?;
+        Ok(MscsbFile { scripts, strings, entrypoint: self.entrypoint, platform: self.platform })
+    }
+}
+
+#[pymodule]
+fn msc(m: &Bound<PyModule>) -> PyResult<()> {
+    m.add_class::<PyCmd>()?;
+    m.add_class::<PyCommand>()?;
+    m.add_class::<PyScript>()?;
+    m.add_class::<PyMscsbFile>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pyo3::types::IntoPyDict;
+
+    #[test]
+    fn test_python_round_trip() {
+        let mut bytes = vec![];
+        crate::assemble("main:\n    Begin 0, 0\n    PushShort. 0x1\n    Sys 1, 0x10\n    End\n")
+            .unwrap()
+            .write(&mut bytes);
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new_bound(py, "msc").unwrap();
+            msc(&module).unwrap();
+            let locals = [("msc", module.into_any()), ("data", PyBytes::new_bound(py, &bytes).into_any())]
+                .into_py_dict_bound(py);
+            py.run_bound(r#"
+f = msc.MscsbFile.from_bytes(data)
+script = f.scripts[0]
+assert len(script) == 4
+assert script.commands[1].name == "PushShort" and script.commands[1].operands == [1]
+assert script.commands[2].cmd == msc.Cmd("Sys", [1, 0x10])
+script.commands[1].cmd = msc.Cmd("PushShort", [7])
+script.commands.insert(3, msc.Command(msc.Cmd("Nop"), position=0x1B))
+script.bounds = (0x10, 0x1D)
+f.strings.append("added")
+again = msc.MscsbFile.from_bytes(f.write())
+assert again.strings == ["added"]
+assert [c.name for c in again.scripts[0].commands] == ["Begin", "PushShort", "Sys", "Nop", "End"]
+assert again.scripts[0].commands[1].operands == [7]
+assert "PushShort. 0x7" in again.disassemble()
+try:
+    msc.Cmd("Bogus")
+    raise AssertionError("expected ValueError")
+except ValueError:
+    pass
+"#, None, Some(&locals)).unwrap();
+        });
+    }
+}
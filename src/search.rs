@@ -0,0 +1,310 @@
+use super::{Command, MscCorpus, MscsbFile};
+use super::error::{Error, Result};
+use super::sys_catalog::parse_number;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperandPattern {
+    /// `_`
+    Any,
+    Value(u32),
+    /// `!n`, anything but this value
+    Not(u32),
+    /// `$name`, any value, which must be the same everywhere the name is used
+    Capture(String),
+}
+
+/// Matches a single command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsnPattern {
+    /// Mnemonic as printed by the disassembler, `None` for any command
+    pub name: Option<String>,
+    /// Only match commands with the push bit set
+    pub push_bit: bool,
+    /// `None` matches any operands
+    pub operands: Option<Vec<OperandPattern>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Element {
+    Insn {
+        pattern: InsnPattern,
+        optional: bool,
+    },
+    /// Skip up to `max` commands, or any number if `None`
+    Gap {
+        max: Option<usize>,
+    },
+}
+
+/// A sequence of commands to look for. The text form has one element per line or
+/// `;`, each a mnemonic (or `_` for any) optionally followed by `.` to require the
+/// push bit and comma separated operand patterns (`_`, a number, `!n` or `$name`).
+/// A trailing `?` makes an element optional and `...` or `...n` skips commands.
+///
+/// `PushInt $script; ...2; CallFunc _` or `SetVar !0, 7`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    pub elements: Vec<Element>,
+}
+
+/// A place a pattern matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub script: usize,
+    /// Index of the first matched command in the script
+    pub command: usize,
+    pub position: u32,
+    /// Number of commands covered, including skipped ones
+    pub len: usize,
+    pub captures: BTreeMap<String, u32>,
+}
+
+/// Result of `Pattern::find_in_dir`
+#[derive(Debug)]
+pub struct DirSearch {
+    /// Sorted by path
+    pub matches: Vec<(PathBuf, SearchMatch)>,
+    /// Files that couldn't be read or parsed, which were skipped
+    pub errors: Vec<(PathBuf, Error)>,
+}
+
+impl InsnPattern {
+    fn matches(&self, command: &Command, captures: &mut BTreeMap<String, u32>) -> bool {
+        if self.name.as_ref().is_some_and(|name| name != command.cmd.name()) ||
+           (self.push_bit && !command.push_bit) {
+            return false;
+        }
+        let patterns = match self.operands {
+            Some(ref patterns) => patterns,
+            None => return true,
+        };
+        let operands = command.cmd.operands();
+        if operands.len() != patterns.len() {
+            return false;
+        }
+        for (pattern, &value) in patterns.iter().zip(operands.iter()) {
+            let ok = match *pattern {
+                OperandPattern::Any => true,
+                OperandPattern::Value(expected) => value == expected,
+                OperandPattern::Not(unwanted) => value != unwanted,
+                OperandPattern::Capture(ref name) => *captures.entry(name.clone()).or_insert(value) == value,
+            };
+            if !ok {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl Pattern {
+    pub fn parse(text: &str) -> Result<Pattern> {
+        let mut elements = vec![];
+        for part in text.split(['\n', ';']).map(str::trim).filter(|part| !part.is_empty()) {
+            let error = |message: &str| Error::Format(format!("bad pattern element '{}': {}", part, message));
+            if let Some(max) = part.strip_prefix("...") {
+                let max = if max.is_empty() {
+                    None
+                } else {
+                    Some(parse_number(max).ok_or_else(|| error("bad gap length"))? as usize)
+                };
+                elements.push(Element::Gap { max });
+                continue;
+            }
+            let (part, optional) = match part.strip_suffix('?') {
+                Some(part) => (part.trim_end(), true),
+                None => (part, false),
+            };
+            let (mnemonic, operands) = match part.find(char::is_whitespace) {
+                Some(split) => (&part[..split], Some(part[split..].trim())),
+                None => (part, None),
+            };
+            let (name, push_bit) = match mnemonic.strip_suffix('.') {
+                Some(name) => (name, true),
+                None => (mnemonic, false),
+            };
+            let operands = match operands {
+                Some(operands) => Some(
+                    operands.split(',')
+                        .map(|operand| parse_operand(operand.trim()).ok_or_else(|| error("bad operand")))
+                        .collect::<Result<Vec<_>>>()?
+                ),
+                None => None,
+            };
+            let name = if name == "_" {
+                None
+            } else {
+                let counts: Vec<usize> = (0..=2).filter(|&n| crate::Cmd::from_parts(name, &vec![0; n]).is_some()).collect();
+                match operands {
+                    _ if counts.is_empty() => return Err(error("unknown instruction")),
+                    Some(ref operands) if !counts.contains(&operands.len()) => {
+                        return Err(error("wrong number of operands"));
+                    }
+                    _ => {}
+                }
+                Some(String::from(name))
+            };
+            elements.push(Element::Insn { pattern: InsnPattern { name, push_bit, operands }, optional });
+        }
+        if elements.is_empty() {
+            return Err(Error::Format(String::from("empty pattern")));
+        }
+        Ok(Pattern { elements })
+    }
+
+    /// Every place in the file the pattern starts matching, in order
+    pub fn find(&self, file: &MscsbFile) -> Vec<SearchMatch> {
+        let mut matches = vec![];
+        for (s, script) in file.scripts.iter().enumerate() {
+            for start in 0..script.commands.len() {
+                let mut captures = BTreeMap::new();
+                if let Some(end) = self.match_at(0, &script.commands, start, &mut captures) {
+                    matches.push(SearchMatch {
+                        script: s,
+                        command: start,
+                        position: script.commands[start].position,
+                        len: end - start,
+                        captures,
+                    });
+                }
+            }
+        }
+        matches
+    }
+
+    /// Search every file of `corpus`, sorted by path
+    pub fn find_in_corpus(&self, corpus: &MscCorpus) -> Vec<(PathBuf, SearchMatch)> {
+        corpus.iter()
+            .flat_map(|entry| self.find(&entry.file).into_iter().map(move |m| (entry.path.clone(), m)))
+            .collect()
+    }
+
+    /// Search every `.mscsb` file under `dir`, including subdirectories. Files that
+    /// can't be read or parsed are skipped, only failing to walk `dir` is an error.
+    pub fn find_in_dir<P: AsRef<Path>>(&self, dir: P) -> Result<DirSearch> {
+        let corpus = MscCorpus::load(dir)?;
+        Ok(DirSearch { matches: self.find_in_corpus(&corpus), errors: corpus.errors })
+    }
+
+    // Index just past the match, trying shorter gaps and taking optional elements first
+    fn match_at(&self, element: usize, commands: &[Command], at: usize, captures: &mut BTreeMap<String, u32>) -> Option<usize> {
+        let rest = match self.elements.get(element) {
+            Some(rest) => rest,
+            None => return Some(at),
+        };
+        match *rest {
+            Element::Insn { ref pattern, optional } => {
+                if let Some(command) = commands.get(at) {
+                    let mut tried = captures.clone();
+                    if pattern.matches(command, &mut tried) {
+                        if let Some(end) = self.match_at(element + 1, commands, at + 1, &mut tried) {
+                            *captures = tried;
+                            return Some(end);
+                        }
+                    }
+                }
+                if optional {
+                    return self.match_at(element + 1, commands, at, captures);
+                }
+                None
+            }
+            Element::Gap { max } => {
+                let most = max.unwrap_or(usize::MAX).min(commands.len() - at.min(commands.len()));
+                for skip in 0..=most {
+                    let mut tried = captures.clone();
+                    if let Some(end) = self.match_at(element + 1, commands, at + skip, &mut tried) {
+                        *captures = tried;
+                        return Some(end);
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Pattern> {
+        Pattern::parse(text)
+    }
+}
+
+fn parse_operand(text: &str) -> Option<OperandPattern> {
+    Some(match text {
+        "_" => OperandPattern::Any,
+        _ if text.starts_with('$') && text.len() > 1 => OperandPattern::Capture(String::from(&text[1..])),
+        _ if text.starts_with('!') => OperandPattern::Not(parse_number(&text[1..])?),
+        _ => OperandPattern::Value(parse_number(text)?),
+    })
+}
+
+impl MscsbFile {
+    /// Find every match of a pattern, see `Pattern`
+    pub fn search(&self, pattern: &Pattern) -> Vec<SearchMatch> {
+        pattern.find(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn test_search() {
+        let file = assemble("
+main:
+    Begin 0, 1
+    PushInt. 0x5
+    PushShort. 0x1
+    PushVar. 0, 0x0
+    Sys. 3, 0x2F
+    SetVar 1, 0x7
+    PushInt. 0x5
+    PushInt. 0x5
+    AddI.
+    SetVar 0, 0x7
+    End
+").unwrap();
+
+        let matches = file.search(&"PushInt $x; ...3; Sys 3, 0x2F".parse().unwrap());
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].command, matches[0].len, matches[0].position), (1, 4, 0x15));
+        assert_eq!(matches[0].captures["x"], 5);
+
+        let globals = file.search(&Pattern::parse("SetVar !0, 7").unwrap());
+        assert_eq!(globals.iter().map(|m| m.command).collect::<Vec<_>>(), vec![5]);
+
+        let same = file.search(&Pattern::parse("PushInt. $a\nPushInt. $a\nPop?\nAddI.").unwrap());
+        assert_eq!(same.iter().map(|m| m.command).collect::<Vec<_>>(), vec![6]);
+        assert!(file.search(&Pattern::parse("PushInt $a; PushShort $a").unwrap()).is_empty());
+        assert_eq!(file.search(&Pattern::parse("_ 0, _").unwrap()).len(), 3);
+
+        assert!(Pattern::parse("Bogus").is_err());
+        assert!(Pattern::parse("SetVar 1").is_err());
+        assert!(Pattern::parse("PushInt $").is_err());
+    }
+
+    #[test]
+    fn test_find_in_dir() {
+        let root = std::env::temp_dir().join(format!("msc-search-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        let file = assemble("main:\n    Begin 0, 0\n    Sys 0, 0x10\n    End\n").unwrap();
+        file.write_to_file(root.join("a.mscsb")).unwrap();
+        file.write_to_file(root.join("nested/c.mscsb")).unwrap();
+        std::fs::write(root.join("b.mscsb"), b"not msc").unwrap();
+
+        let search = Pattern::parse("Sys 0, 0x10").unwrap().find_in_dir(&root).unwrap();
+        let paths: Vec<_> = search.matches.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(paths, vec![root.join("a.mscsb"), root.join("nested/c.mscsb")]);
+        assert_eq!(search.errors.len(), 1);
+        assert_eq!(search.errors[0].0, root.join("b.mscsb"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+// Deserialization goes through these unchecked mirrors so data that `MscsbFile::write`
+// would mangle is rejected up front. Anything `write` lays out faithfully is accepted,
+// including branches and an entrypoint that point outside every script.
+use super::{Command, MscsbFile, Platform, Script};
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+#[derive(Deserialize)]
+pub struct RawScript {
+    commands: Vec<Command>,
+    bounds: (u32, u32),
+}
+
+#[derive(Deserialize)]
+pub struct RawFile {
+    scripts: Vec<Script>,
+    strings: Vec<String>,
+    entrypoint: u32,
+    #[serde(default)]
+    platform: Platform,
+}
+
+impl TryFrom<RawScript> for Script {
+    type Error = String;
+
+    fn try_from(raw: RawScript) -> Result<Script, String> {
+        let (start, end) = raw.bounds;
+        let mut position = start;
+        for (i, command) in raw.commands.iter().enumerate() {
+            if command.position != position {
+                return Err(format!(
+                    "command {} ({}) of script at 0x{:X} is at 0x{:X}, expected 0x{:X}",
+                    i, command.cmd.name(), start, command.position, position
+                ));
+            }
+            position += command.cmd.size();
+        }
+        if position != end {
+            return Err(format!(
+                "script at 0x{:X} ends at 0x{:X} but its bounds end at 0x{:X}",
+                start, position, end
+            ));
+        }
+        Ok(Script { commands: raw.commands, bounds: raw.bounds })
+    }
+}
+
+impl TryFrom<RawFile> for MscsbFile {
+    type Error = String;
+
+    fn try_from(raw: RawFile) -> Result<MscsbFile, String> {
+        // The writer lays scripts out back to back after 0x10 bytes of padding
+        let mut position = 0x10;
+        for (i, script) in raw.scripts.iter().enumerate() {
+            if script.bounds.0 != position {
+                return Err(format!(
+                    "script {} starts at 0x{:X}, expected 0x{:X}", i, script.bounds.0, position
+                ));
+            }
+            position = script.bounds.1;
+        }
+        if let Some(i) = raw.strings.iter().position(|s| s.contains('\0')) {
+            return Err(format!("string {} contains a null byte", i));
+        }
+        Ok(MscsbFile {
+            scripts: raw.scripts,
+            strings: raw.strings,
+            entrypoint: raw.entrypoint,
+            platform: raw.platform,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::Cmd;
+
+    #[test]
+    fn test_json_round_trip() {
+        let commands = vec![
+            Command { cmd: Cmd::Begin { arg_count: 0, var_count: 0 }, push_bit: false, position: 0x10 },
+            Command { cmd: Cmd::PushInt { val: 1 }, push_bit: true, position: 0x15 },
+            Command { cmd: Cmd::IfNot { loc: 0x1F }, push_bit: false, position: 0x1A },
+            Command { cmd: Cmd::End, push_bit: false, position: 0x1F },
+        ];
+        let file = MscsbFile::new(
+            vec![Script { commands, bounds: (0x10, 0x20) }],
+            vec![String::from("hi")],
+            0x10,
+        );
+        let json = serde_json::to_string(&file).unwrap();
+        let back: MscsbFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.disassemble(), file.disassemble());
+
+        let moved = json.replace("\"position\":26", "\"position\":27");
+        let error = serde_json::from_str::<MscsbFile>(&moved).unwrap_err().to_string();
+        assert!(error.starts_with("command 2 (IfNot) of script at 0x10 is at 0x1B, expected 0x1A"), "{}", error);
+
+        // `write` keeps branches outside the script as they are, so they load too
+        let outside = json.replace("\"loc\":31", "\"loc\":64");
+        let back: MscsbFile = serde_json::from_str(&outside).unwrap();
+        assert!(back.disassemble().contains("IfNot 0x40"));
+        let mut bytes = vec![];
+        back.write(&mut bytes);
+        assert_eq!(MscsbFile::from_bytes(&bytes).unwrap().disassemble(), back.disassemble());
+    }
+}
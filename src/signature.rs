@@ -0,0 +1,237 @@
+use super::MscsbFile;
+use super::hash::{normalize, Fnv};
+use std::collections::{HashMap, HashSet};
+
+/// Fingerprint of a script that survives the script moving or being lightly edited,
+/// for pairing up scripts between builds of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Signature {
+    /// `MscsbFile::script_hash` of the script
+    pub hash: u64,
+    /// Number of commands
+    pub len: usize,
+    /// Sorted hashes of every run of three commands, ignoring branch targets and
+    /// script addresses
+    pub shingles: Vec<u64>,
+}
+
+impl Signature {
+    /// Jaccard similarity of the two scripts' shingles, 1.0 for identical hashes
+    pub fn similarity(&self, other: &Signature) -> f32 {
+        if self.hash == other.hash {
+            return 1.0;
+        }
+        let (mut i, mut j, mut shared) = (0, 0, 0);
+        while i < self.shingles.len() && j < other.shingles.len() {
+            if self.shingles[i] == other.shingles[j] {
+                shared += 1;
+                i += 1;
+                j += 1;
+            } else if self.shingles[i] < other.shingles[j] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        let union = self.shingles.len() + other.shingles.len() - shared;
+        if union == 0 {
+            0.0
+        } else {
+            shared as f32 / union as f32
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ScriptMatch {
+    pub old: usize,
+    pub new: usize,
+    /// 1.0 for a unique exact match, lower the less alike the scripts are
+    pub confidence: f32,
+}
+
+// Confidence given to exact matches when several scripts share the same hash
+const AMBIGUOUS: f32 = 0.75;
+
+/// Pairs scripts between two versions of a file
+#[derive(Debug, Copy, Clone)]
+pub struct SignatureMatcher {
+    /// Fuzzy matches below this similarity are left unpaired
+    pub threshold: f32,
+}
+
+impl Default for SignatureMatcher {
+    fn default() -> SignatureMatcher {
+        SignatureMatcher { threshold: 0.5 }
+    }
+}
+
+impl SignatureMatcher {
+    pub fn new() -> SignatureMatcher {
+        SignatureMatcher::default()
+    }
+
+    pub fn match_files(&self, old: &MscsbFile, new: &MscsbFile) -> Vec<ScriptMatch> {
+        self.match_signatures(&old.signatures(), &new.signatures())
+    }
+
+    /// Match previously saved signatures, each script is used at most once.
+    /// Results are sorted by old index.
+    pub fn match_signatures(&self, old: &[Signature], new: &[Signature]) -> Vec<ScriptMatch> {
+        let mut matches = vec![];
+        let mut old_used = vec![false; old.len()];
+        let mut new_used = vec![false; new.len()];
+        let distance = |i: usize, j: usize| {
+            let a = i as f32 / old.len().max(1) as f32;
+            let b = j as f32 / new.len().max(1) as f32;
+            (a - b).abs()
+        };
+
+        let mut by_hash: HashMap<u64, (Vec<usize>, Vec<usize>)> = HashMap::new();
+        for (i, signature) in old.iter().enumerate() {
+            by_hash.entry(signature.hash).or_default().0.push(i);
+        }
+        for (j, signature) in new.iter().enumerate() {
+            by_hash.entry(signature.hash).or_default().1.push(j);
+        }
+        for (olds, news) in by_hash.values() {
+            if olds.is_empty() || news.is_empty() {
+                continue;
+            }
+            let confidence = if olds.len() == 1 && news.len() == 1 { 1.0 } else { AMBIGUOUS };
+            let mut pairs: Vec<(usize, usize)> =
+                olds.iter().flat_map(|&i| news.iter().map(move |&j| (i, j))).collect();
+            pairs.sort_by(|a, b| distance(a.0, a.1).partial_cmp(&distance(b.0, b.1)).unwrap());
+            for (i, j) in pairs {
+                if !old_used[i] && !new_used[j] {
+                    old_used[i] = true;
+                    new_used[j] = true;
+                    matches.push(ScriptMatch { old: i, new: j, confidence });
+                }
+            }
+        }
+
+        let mut candidates = vec![];
+        for i in (0..old.len()).filter(|&i| !old_used[i]) {
+            for j in (0..new.len()).filter(|&j| !new_used[j]) {
+                let similarity = old[i].similarity(&new[j]);
+                if similarity >= self.threshold {
+                    candidates.push((similarity, distance(i, j), i, j));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap().then(a.1.partial_cmp(&b.1).unwrap()));
+        for (similarity, _, i, j) in candidates {
+            if !old_used[i] && !new_used[j] {
+                old_used[i] = true;
+                new_used[j] = true;
+                // A fuzzy match is never as sure as an exact one
+                matches.push(ScriptMatch { old: i, new: j, confidence: similarity.min(0.99) });
+            }
+        }
+        matches.sort_by_key(|m| m.old);
+        matches
+    }
+}
+
+impl MscsbFile {
+    pub fn signature(&self, index: usize) -> Signature {
+        let script = &self.scripts[index];
+        let tokens: Vec<u64> = normalize(script, |val| self.get_script_from_loc(val))
+            .iter()
+            .map(|(key, _)| key.token())
+            .collect();
+        let shingles: HashSet<u64> = tokens.windows(3.min(tokens.len()).max(1))
+            .map(|window| {
+                let mut hash = Fnv::new();
+                for token in window {
+                    hash.write(&token.to_le_bytes());
+                }
+                hash.0
+            })
+            .collect();
+        let mut shingles: Vec<u64> = shingles.into_iter().collect();
+        shingles.sort_unstable();
+        Signature { hash: self.script_hash(index), len: script.commands.len(), shingles }
+    }
+
+    pub fn signatures(&self) -> Vec<Signature> {
+        (0..self.scripts.len()).map(|i| self.signature(i)).collect()
+    }
+
+    /// Pair this file's scripts with those in `new` using the default `SignatureMatcher`
+    pub fn match_scripts(&self, new: &MscsbFile) -> Vec<ScriptMatch> {
+        SignatureMatcher::default().match_files(self, new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::assemble;
+
+    #[test]
+    fn test_match_scripts() {
+        let old = assemble("
+a:
+    Begin 0, 1
+    PushShort. 0x3
+    SetVar 0, 0x0
+loc_top:
+    PushVar. 0, 0x0
+    IfNot loc_end
+    PushInt. b
+    CallFunc 0
+    DecI 0, 0x0
+    Jump loc_top
+loc_end:
+    End
+b:
+    Begin 0, 0
+    PushShort. 0x1
+    PushShort. 0x2
+    Sys. 2, 0x10
+    Return6
+c:
+    Begin 0, 0
+    End
+").unwrap();
+        // Scripts reordered and moved, `a` slightly edited and `c` dropped
+        let new = assemble("
+d:
+    Begin 1, 0
+    PushVar. 0, 0x0
+    Sys 1, 0x44
+    End
+b:
+    Begin 0, 0
+    PushShort. 0x1
+    PushShort. 0x2
+    Sys. 2, 0x10
+    Return6
+a:
+    Begin 0, 1
+    PushShort. 0x3
+    SetVar 0, 0x0
+loc_top:
+    PushVar. 0, 0x0
+    IfNot loc_end
+    PushInt. b
+    CallFunc 0
+    PushShort. 0x0
+    Sys 1, 0x44
+    DecI 0, 0x0
+    Jump loc_top
+loc_end:
+    End
+").unwrap();
+        assert_eq!(old.signature(1).hash, new.signature(1).hash);
+        assert_ne!(old.scripts[1].bounds, new.scripts[1].bounds);
+
+        let matches = old.match_scripts(&new);
+        let pairs: Vec<(usize, usize)> = matches.iter().map(|m| (m.old, m.new)).collect();
+        assert_eq!(pairs, vec![(0, 2), (1, 1)]);
+        assert_eq!(matches[1].confidence, 1.0);
+        assert!(matches[0].confidence >= 0.5 && matches[0].confidence < 1.0);
+    }
+}
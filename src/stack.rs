@@ -0,0 +1,126 @@
+use super::{Cmd, Command, Script};
+
+impl Cmd {
+    /// Number of values this command pops off the stack
+    pub fn pops(&self) -> usize {
+        match *self {
+            Cmd::Return6 | Cmd::Return8 => 1,
+            Cmd::AddI | Cmd::SubI | Cmd::MultI | Cmd::DivI | Cmd::ModI |
+            Cmd::AndI | Cmd::OrI | Cmd::XorI | Cmd::ShiftL | Cmd::ShiftR => 2,
+            Cmd::NegI | Cmd::NotI | Cmd::Not | Cmd::NegF => 1,
+            Cmd::SetVar { .. } | Cmd::AddVarBy { .. } | Cmd::SubVarBy { .. } |
+            Cmd::MultVarBy { .. } | Cmd::DivVarBy { .. } | Cmd::ModVarBy { .. } |
+            Cmd::AndVarBy { .. } | Cmd::OrVarBy { .. } | Cmd::XorVarBy { .. } => 1,
+            Cmd::Equals | Cmd::NotEquals | Cmd::LessThan | Cmd::LessOrEqual |
+            Cmd::Greater | Cmd::GreaterOrEqual => 2,
+            Cmd::PrintF { arg_count } => arg_count as usize,
+            Cmd::Sys { arg_count, .. } => arg_count as usize,
+            // The script address sits below the arguments
+            Cmd::CallFunc { arg_count } |
+            Cmd::CallFunc2 { arg_count } |
+            Cmd::CallFunc3 { arg_count } => arg_count as usize + 1,
+            Cmd::Pop => 1,
+            Cmd::If { .. } | Cmd::IfNot { .. } => 1,
+            Cmd::AddF | Cmd::SubF | Cmd::MultF | Cmd::DivF => 2,
+            Cmd::VarSetF { .. } | Cmd::AddVarByF { .. } | Cmd::SubVarByF { .. } |
+            Cmd::MultVarByF { .. } | Cmd::DivVarByF { .. } => 1,
+            Cmd::EqualsF | Cmd::NotEqualsF | Cmd::LessThanF | Cmd::LessOrEqualF |
+            Cmd::GreaterF | Cmd::GreaterOrEqualF => 2,
+            _ => 0,
+        }
+    }
+
+    /// Whether this command produces a value (only pushed if the push bit is set)
+    pub fn produces_value(&self) -> bool {
+        matches!(*self,
+            Cmd::PushInt { .. } | Cmd::PushVar { .. } | Cmd::PushShort { .. } |
+            Cmd::AddI | Cmd::SubI | Cmd::MultI | Cmd::DivI | Cmd::ModI | Cmd::NegI |
+            Cmd::AndI | Cmd::OrI | Cmd::NotI | Cmd::XorI | Cmd::ShiftL | Cmd::ShiftR |
+            Cmd::Equals | Cmd::NotEquals | Cmd::LessThan | Cmd::LessOrEqual |
+            Cmd::Greater | Cmd::GreaterOrEqual | Cmd::Not |
+            Cmd::Sys { .. } |
+            Cmd::CallFunc { .. } | Cmd::CallFunc2 { .. } | Cmd::CallFunc3 { .. } |
+            Cmd::AddF | Cmd::SubF | Cmd::MultF | Cmd::DivF | Cmd::NegF |
+            Cmd::EqualsF | Cmd::NotEqualsF | Cmd::LessThanF | Cmd::LessOrEqualF |
+            Cmd::GreaterF | Cmd::GreaterOrEqualF
+        )
+    }
+
+    /// Absolute location this command can transfer control to, if any
+    pub fn branch_target(&self) -> Option<u32> {
+        match *self {
+            Cmd::Jump { loc } | Cmd::Jump5 { loc } | Cmd::Try { loc } |
+            Cmd::If { loc } | Cmd::IfNot { loc } | Cmd::Else { loc } => Some(loc),
+            _ => None,
+        }
+    }
+
+    /// Change the location a branch goes to, does nothing for other commands
+    pub fn set_branch_target(&mut self, target: u32) {
+        match self {
+            Cmd::Jump { loc } | Cmd::Jump5 { loc } | Cmd::Try { loc } |
+            Cmd::If { loc } | Cmd::IfNot { loc } | Cmd::Else { loc } => *loc = target,
+            _ => {}
+        }
+    }
+
+    /// Whether execution can never continue to the next command
+    pub fn ends_flow(&self) -> bool {
+        matches!(*self,
+            Cmd::Jump { .. } | Cmd::Jump5 { .. } | Cmd::Else { .. } |
+            Cmd::Return6 | Cmd::Return7 | Cmd::Return8 | Cmd::Return9 |
+            Cmd::End | Cmd::Exit
+        )
+    }
+}
+
+impl Command {
+    /// Number of values (popped, pushed) when this command runs
+    pub fn stack_effect(&self) -> (usize, usize) {
+        let pushes = if self.push_bit && self.cmd.produces_value() { 1 } else { 0 };
+        (self.cmd.pops(), pushes)
+    }
+}
+
+impl Script {
+    /// For every command, the index of the command that produced each value it pops
+    /// (deepest first). `None` means the producer isn't known from straight-line code,
+    /// such as values flowing in from another branch.
+    pub fn operand_sources(&self) -> Vec<Vec<Option<usize>>> {
+        let targets: std::collections::HashSet<u32> =
+            self.commands
+                .iter()
+                .filter_map(|c| c.cmd.branch_target())
+                .collect();
+
+        let mut stack: Vec<Option<usize>> = vec![];
+        let mut sources = Vec::with_capacity(self.commands.len());
+        let mut after_jump = false;
+        for (i, command) in self.commands.iter().enumerate() {
+            if after_jump || targets.contains(&command.position) {
+                stack.clear();
+            }
+            let (pops, pushes) = command.stack_effect();
+            let mut operands = vec![None; pops];
+            for slot in operands.iter_mut().rev() {
+                *slot = stack.pop().unwrap_or(None);
+            }
+            match command.cmd {
+                // Converts in place, so the value is now this command's result
+                Cmd::IntToFloat { stack_pos } | Cmd::FloatToInt { stack_pos } => {
+                    let len = stack.len();
+                    if (stack_pos as usize) < len {
+                        stack[len - 1 - stack_pos as usize] = Some(i);
+                    }
+                }
+                _ => {}
+            }
+            if pushes != 0 {
+                stack.push(Some(i));
+            }
+            sources.push(operands);
+            after_jump = command.cmd.ends_flow();
+        }
+        sources
+    }
+}
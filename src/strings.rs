@@ -0,0 +1,309 @@
+use super::{printf, ArgType, Cmd, MscsbFile, Script, SysCatalog};
+use super::error::{Error, Result};
+use super::printf::FormatString;
+use std::collections::HashMap;
+
+impl MscsbFile {
+    /// Index of `string` in the string table, adding it to the end if it isn't there
+    pub fn intern(&mut self, string: &str) -> u32 {
+        match self.strings.iter().position(|s| s == string) {
+            Some(index) => index as u32,
+            None => {
+                self.strings.push(String::from(string));
+                self.strings.len() as u32 - 1
+            }
+        }
+    }
+
+    /// Every command that pushes a string index, as `(script, command, string)`, using the
+    /// built-in sys call catalog. See `string_references_with`.
+    pub fn string_references(&self) -> Result<Vec<(usize, usize, u32)>> {
+        self.string_references_with(SysCatalog::builtin_shared())
+    }
+
+    /// Every command that pushes a string index: `PrintF` format strings and `%s`
+    /// arguments, and `Sys` arguments `catalog` types as strings. Fails if one of these
+    /// isn't pushed as a constant or is out of range, since the table can't be safely
+    /// rearranged then.
+    pub fn string_references_with(&self, catalog: &SysCatalog) -> Result<Vec<(usize, usize, u32)>> {
+        let mut references = vec![];
+        for (script_index, script) in self.scripts.iter().enumerate() {
+            let sources = script.operand_sources();
+            for (i, command) in script.iter().enumerate() {
+                for arg in string_args(self, catalog, script, &sources, i) {
+                    match arg {
+                        StringArg::Constant { string, .. } if string as usize >= self.strings.len() => {
+                            return Err(Error::Format(format!(
+                                "{} at 0x{:X} uses string {} which is out of range",
+                                command.cmd.name(), command.position, string
+                            )));
+                        }
+                        StringArg::Constant { source, string } => references.push((script_index, source, string)),
+                        StringArg::Unknown { arg } => {
+                            return Err(Error::Format(format!(
+                                "{} at 0x{:X} doesn't push string argument {} as a constant",
+                                command.cmd.name(), command.position, arg
+                            )));
+                        }
+                        StringArg::BadFormat => {
+                            return Err(Error::Format(format!(
+                                "PrintF at 0x{:X} has a format string that doesn't parse, so its string arguments aren't known",
+                                command.position
+                            )));
+                        }
+                    }
+                }
+            }
+        }
+        references.sort_unstable();
+        references.dedup();
+        Ok(references)
+    }
+
+    /// Remove strings nothing refers to, returning their old indices
+    pub fn remove_unused_strings(&mut self) -> Result<Vec<u32>> {
+        self.remove_unused_strings_with(SysCatalog::builtin_shared())
+    }
+
+    /// `remove_unused_strings` with `Sys` string arguments typed by `catalog`
+    pub fn remove_unused_strings_with(&mut self, catalog: &SysCatalog) -> Result<Vec<u32>> {
+        let references = self.string_references_with(catalog)?;
+        let mut used = vec![false; self.strings.len()];
+        for &(_, _, string) in references.iter() {
+            used[string as usize] = true;
+        }
+        let order: Vec<u32> = (0..self.strings.len() as u32).filter(|&i| used[i as usize]).collect();
+        let removed = (0..self.strings.len() as u32).filter(|&i| !used[i as usize]).collect();
+        self.rebuild_strings(&references, &order)?;
+        Ok(removed)
+    }
+
+    /// Merge identical strings into the first copy, returning how many were removed
+    pub fn dedup_strings(&mut self) -> Result<usize> {
+        self.dedup_strings_with(SysCatalog::builtin_shared())
+    }
+
+    /// `dedup_strings` with `Sys` string arguments typed by `catalog`
+    pub fn dedup_strings_with(&mut self, catalog: &SysCatalog) -> Result<usize> {
+        let references = self.string_references_with(catalog)?;
+        let mut first: HashMap<&str, u32> = HashMap::new();
+        let mut order = vec![];
+        let mut map = Vec::with_capacity(self.strings.len());
+        for (i, string) in self.strings.iter().enumerate() {
+            let index = *first.entry(string).or_insert_with(|| {
+                order.push(i as u32);
+                order.len() as u32 - 1
+            });
+            map.push(index);
+        }
+        let removed = self.strings.len() - order.len();
+        self.rewrite_strings(&references, &map)?;
+        self.strings = order.iter().map(|&i| self.strings[i as usize].clone()).collect();
+        Ok(removed)
+    }
+
+    /// Rearrange the string table so the new table is `order[0]`, `order[1]`, ... of the
+    /// old one. `order` must list every index exactly once.
+    pub fn reorder_strings(&mut self, order: &[u32]) -> Result<()> {
+        self.reorder_strings_with(order, SysCatalog::builtin_shared())
+    }
+
+    /// `reorder_strings` with `Sys` string arguments typed by `catalog`
+    pub fn reorder_strings_with(&mut self, order: &[u32], catalog: &SysCatalog) -> Result<()> {
+        let mut seen = vec![false; self.strings.len()];
+        for &i in order {
+            match seen.get_mut(i as usize) {
+                Some(seen) if !*seen => *seen = true,
+                _ => return Err(Error::Format(format!("string {} is out of range or listed twice", i))),
+            }
+        }
+        if order.len() != self.strings.len() {
+            return Err(Error::Format(String::from("order doesn't list every string")));
+        }
+        let references = self.string_references_with(catalog)?;
+        self.rebuild_strings(&references, order)
+    }
+
+    // Keep only the strings in `order`, in that order, rewriting references to match
+    fn rebuild_strings(&mut self, references: &[(usize, usize, u32)], order: &[u32]) -> Result<()> {
+        let mut map = vec![u32::MAX; self.strings.len()];
+        for (new, &old) in order.iter().enumerate() {
+            map[old as usize] = new as u32;
+        }
+        self.rewrite_strings(references, &map)?;
+        self.strings = order.iter().map(|&i| self.strings[i as usize].clone()).collect();
+        Ok(())
+    }
+
+    // Point each reference at `map[old]`, changing nothing unless every one fits
+    fn rewrite_strings(&mut self, references: &[(usize, usize, u32)], map: &[u32]) -> Result<()> {
+        for &(script, command, string) in references {
+            let command = &self.scripts[script].commands[command];
+            if matches!(command.cmd, Cmd::PushShort { .. }) && map[string as usize] > u16::MAX as u32 {
+                return Err(Error::Format(format!(
+                    "string {} can't be moved to {} by the PushShort at 0x{:X}",
+                    string, map[string as usize], command.position
+                )));
+            }
+        }
+        for &(script, command, string) in references {
+            let val = map[string as usize];
+            match self.scripts[script].commands[command].cmd {
+                Cmd::PushInt { val: ref mut old } => *old = val,
+                Cmd::PushShort { val: ref mut old } => *old = val as u16,
+                _ => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A string index argument of a command, see `string_args`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum StringArg {
+    /// Pushed by the command at `source` as the constant `string`
+    Constant { source: usize, string: u32 },
+    /// Argument `arg` isn't pushed as a constant by straight-line code
+    Unknown { arg: usize },
+    /// A `PrintF` format string doesn't parse, so which arguments are strings isn't known
+    BadFormat,
+}
+
+/// The string index arguments of the command at `index`: a `PrintF`'s format string and
+/// `%s` arguments, or a `Sys`'s arguments typed `ArgType::String` by `catalog`. `sources`
+/// is `Script::operand_sources` for the script.
+pub(crate) fn string_args(
+    file: &MscsbFile,
+    catalog: &SysCatalog,
+    script: &Script,
+    sources: &[Vec<Option<usize>>],
+    index: usize,
+) -> Vec<StringArg> {
+    let args: Vec<usize> = match script.commands[index].cmd {
+        Cmd::PrintF { arg_count } if arg_count > 0 => {
+            let string = match printf::format_source(script, sources, index) {
+                Some((_, string)) => string,
+                None => return vec![StringArg::Unknown { arg: 0 }],
+            };
+            let format = match file.strings.get(string as usize).map(|text| FormatString::parse(text)) {
+                Some(Ok(format)) => format,
+                Some(Err(_)) => return vec![StringArg::BadFormat],
+                // Reported as out of range by the caller
+                None => FormatString { specs: vec![] },
+            };
+            std::iter::once(0).chain(format.string_args().map(|arg| arg + 1)).collect()
+        }
+        Cmd::Sys { sys_num, .. } => match catalog.get(sys_num).and_then(|call| call.args.as_ref()) {
+            Some(types) => types.iter().enumerate().filter(|&(_, &t)| t == ArgType::String).map(|(i, _)| i).collect(),
+            None => vec![],
+        },
+        _ => vec![],
+    };
+    args.into_iter()
+        // Arguments past the ones passed are reported by `validate`
+        .filter_map(|arg| sources[index].get(arg).map(|&source| (arg, source)))
+        .map(|(arg, source)| {
+            let string = source.and_then(|source| match script.commands[source].cmd {
+                Cmd::PushInt { val } => Some(val),
+                Cmd::PushShort { val } => Some(val as u32),
+                _ => None,
+            });
+            match (source, string) {
+                (Some(source), Some(string)) => StringArg::Constant { source, string },
+                _ => StringArg::Unknown { arg },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{assemble, ArgType, Returns, SysCall, SysCatalog};
+
+    #[test]
+    fn test_strings() {
+        let mut file = assemble(r#"
+.string "unused"
+.string "%d\n"
+.string "done\n"
+.string "%d\n"
+main:
+    Begin 0, 0
+    PushShort. 0x1
+    PushShort. 0x5
+    PrintF 2
+    PushInt. 0x3
+    PushShort. 0x6
+    PrintF 2
+    PushShort. 0x2
+    PrintF 1
+    End
+"#).unwrap();
+        assert_eq!(file.intern("done\n"), 2);
+        assert_eq!(file.intern("extra"), 4);
+        assert_eq!(file.string_references().unwrap(), vec![(0, 1, 1), (0, 4, 3), (0, 7, 2)]);
+
+        assert_eq!(file.dedup_strings().unwrap(), 1);
+        assert_eq!(file.strings, vec!["unused", "%d\n", "done\n", "extra"]);
+        assert_eq!(file.printf_string(0, 6), Some(1));
+
+        assert_eq!(file.remove_unused_strings().unwrap(), vec![0, 3]);
+        assert_eq!(file.strings, vec!["%d\n", "done\n"]);
+        assert_eq!(file.printf_string(0, 3), Some(0));
+        assert_eq!(file.printf_string(0, 8), Some(1));
+
+        file.reorder_strings(&[1, 0]).unwrap();
+        assert_eq!(file.strings, vec!["done\n", "%d\n"]);
+        assert_eq!(file.printf_string(0, 3), Some(1));
+        assert_eq!(file.printf_string(0, 8), Some(0));
+        assert!(file.validate().is_empty());
+        assert!(file.reorder_strings(&[0, 0]).is_err());
+
+        file.scripts[0].commands[1].cmd = crate::Cmd::PushVar { var_type: 1, var_num: 0 };
+        assert!(file.remove_unused_strings().is_err());
+        assert_eq!(file.strings.len(), 2);
+    }
+
+    #[test]
+    fn test_string_arguments() {
+        let mut file = assemble(r#"
+.string "unused"
+.string "%s=%d\n"
+.string "name"
+.string "flag"
+main:
+    Begin 0, 0
+    PushShort. 0x1
+    PushShort. 0x2
+    PushShort. 0x5
+    PrintF 3
+    End
+other:
+    Begin 0, 0
+    PushShort. 0x7
+    PushShort. 0x3
+    Sys 2, 0x30
+    End
+"#).unwrap();
+        let mut catalog = SysCatalog::new();
+        catalog.insert(0x30, SysCall {
+            name: String::from("set_named"),
+            args: Some(vec![ArgType::Int, ArgType::String]),
+            returns: Returns::Nothing,
+        });
+        assert_eq!(file.string_references().unwrap(), vec![(0, 1, 1), (0, 2, 2)]);
+        assert_eq!(file.string_references_with(&catalog).unwrap(), vec![(0, 1, 1), (0, 2, 2), (1, 2, 3)]);
+
+        assert_eq!(file.remove_unused_strings_with(&catalog).unwrap(), vec![0]);
+        assert_eq!(file.strings, vec!["%s=%d\n", "name", "flag"]);
+        assert!(matches!(file.scripts[0].commands[2].cmd, crate::Cmd::PushShort { val: 1 }));
+        assert!(matches!(file.scripts[1].commands[2].cmd, crate::Cmd::PushShort { val: 2 }));
+        // The unrelated int argument is left alone
+        assert!(matches!(file.scripts[1].commands[1].cmd, crate::Cmd::PushShort { val: 7 }));
+
+        file.scripts[1].commands[2].cmd = crate::Cmd::PushVar { var_type: 1, var_num: 0 };
+        let error = file.string_references_with(&catalog).unwrap_err().to_string();
+        assert!(error.contains("Sys at 0x"), "{}", error);
+        assert!(file.string_references().is_ok());
+    }
+}
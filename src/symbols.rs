@@ -0,0 +1,418 @@
+use super::{MscsbFile, SysCatalog, SysCall, XrefTarget};
+use std::collections::{BTreeMap, HashSet};
+#[cfg(feature = "serde")]
+use {
+    super::error::{Error, Result},
+    super::sys_catalog::parse_number,
+    std::fs,
+    std::path::Path,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Symbol {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub comment: Option<String>,
+}
+
+impl Symbol {
+    pub fn new<S: Into<String>>(name: S) -> Symbol {
+        Symbol { name: name.into(), comment: None }
+    }
+}
+
+/// How a script symbol picks out its script
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScriptKey {
+    Index(usize),
+    /// Start offset of the script, `Script::bounds.0`
+    Offset(u32),
+    /// `MscsbFile::script_hash`, which survives scripts moving between game versions
+    Hash(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptSymbol {
+    pub key: ScriptKey,
+    pub symbol: Symbol,
+    pub locals: BTreeMap<u16, Symbol>,
+}
+
+/// User-supplied names and comments for scripts, variables and sys calls
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    pub scripts: Vec<ScriptSymbol>,
+    pub globals: BTreeMap<u16, Symbol>,
+    pub sys: BTreeMap<u8, Symbol>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SymbolFile {
+    #[serde(default, rename = "script", skip_serializing_if = "Vec::is_empty")]
+    scripts: Vec<ScriptEntry>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    globals: BTreeMap<String, Symbol>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sys: BTreeMap<String, Symbol>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ScriptEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    offset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    locals: BTreeMap<String, Symbol>,
+}
+
+impl SymbolMap {
+    pub fn new() -> SymbolMap {
+        SymbolMap::default()
+    }
+
+    /// Name a script. Replaces any existing symbol with the same key.
+    pub fn set_script(&mut self, key: ScriptKey, symbol: Symbol) {
+        match self.scripts.iter_mut().find(|script| script.key == key) {
+            Some(script) => script.symbol = symbol,
+            None => self.scripts.push(ScriptSymbol { key, symbol, locals: BTreeMap::new() }),
+        }
+    }
+
+    /// Name a local of an already named script
+    pub fn set_local(&mut self, key: ScriptKey, var_num: u16, symbol: Symbol) -> bool {
+        match self.scripts.iter_mut().find(|script| script.key == key) {
+            Some(script) => {
+                script.locals.insert(var_num, symbol);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Symbol for script `index` of `file`. Hash matches win over offsets, which win
+    /// over indices.
+    pub fn script(&self, file: &MscsbFile, index: usize) -> Option<&ScriptSymbol> {
+        let script = file.scripts.get(index)?;
+        let mut hash = None;
+        let mut best: Option<(u8, &ScriptSymbol)> = None;
+        for symbol in self.scripts.iter() {
+            let rank = match symbol.key {
+                ScriptKey::Hash(h) => {
+                    let hash = *hash.get_or_insert_with(|| file.script_hash(index));
+                    if h == hash { 3 } else { continue }
+                }
+                ScriptKey::Offset(offset) if offset == script.bounds.0 => 2,
+                ScriptKey::Index(i) if i == index => 1,
+                _ => continue,
+            };
+            if best.is_none_or(|(best_rank, _)| rank > best_rank) {
+                best = Some((rank, symbol));
+            }
+        }
+        best.map(|(_, symbol)| symbol)
+    }
+
+    pub fn script_name(&self, file: &MscsbFile, index: usize) -> Option<&str> {
+        self.script(file, index).map(|script| &script.symbol.name[..])
+    }
+
+    /// Assembly label for every script of `file`, which `assemble` reads back. Names
+    /// that aren't valid labels fall back to `script_N`, and a name already taken by
+    /// another script gets `_2`, `_3` and so on added.
+    pub fn script_labels(&self, file: &MscsbFile) -> Vec<String> {
+        let names: Vec<Option<&str>> = (0..file.scripts.len())
+            .map(|index| self.script_name(file, index).filter(|name| is_label(name)))
+            .collect();
+        let mut taken: HashSet<String> = names.iter()
+            .enumerate()
+            .filter(|(_, name)| name.is_none())
+            .map(|(index, _)| format!("script_{}", index))
+            .collect();
+        names.iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let name = match *name {
+                    Some(name) => name,
+                    None => return format!("script_{}", index),
+                };
+                let mut label = String::from(name);
+                let mut n = 2;
+                while !taken.insert(label.clone()) {
+                    label = format!("{}_{}", name, n);
+                    n += 1;
+                }
+                label
+            })
+            .collect()
+    }
+
+    pub fn global(&self, var_num: u16) -> Option<&Symbol> {
+        self.globals.get(&var_num)
+    }
+
+    pub fn local(&self, file: &MscsbFile, script: usize, var_num: u16) -> Option<&Symbol> {
+        self.script(file, script)?.locals.get(&var_num)
+    }
+
+    pub fn sys(&self, sys_num: u8) -> Option<&Symbol> {
+        self.sys.get(&sys_num)
+    }
+
+    /// Symbol for the variable addressed by `(var_type, var_num)` inside `script`
+    pub fn variable(&self, file: &MscsbFile, script: usize, var_type: u8, var_num: u16) -> Option<&Symbol> {
+        if var_type == 0 {
+            self.local(file, script, var_num)
+        } else {
+            self.global(var_num)
+        }
+    }
+
+    /// Human readable name of a cross-reference target
+    pub fn describe(&self, file: &MscsbFile, target: XrefTarget) -> String {
+        match target {
+            XrefTarget::Global(var_num) => match self.global(var_num) {
+                Some(symbol) => format!("global {} (0x{:X})", symbol.name, var_num),
+                None => format!("global 0x{:X}", var_num),
+            },
+            XrefTarget::Local { script, var_num } => {
+                let owner = self.script_name(file, script)
+                    .map(String::from)
+                    .unwrap_or_else(|| format!("script_{}", script));
+                match self.local(file, script, var_num) {
+                    Some(symbol) => format!("local {} ({}:{})", symbol.name, owner, var_num),
+                    None => format!("local {}:{}", owner, var_num),
+                }
+            }
+            XrefTarget::String(index) => match file.strings.get(index as usize) {
+                Some(string) => format!("string {} {:?}", index, string),
+                None => format!("string {}", index),
+            },
+            XrefTarget::Script(index) => match self.script_name(file, index) {
+                Some(name) => format!("script {} ({})", name, index),
+                None => format!("script_{}", index),
+            },
+        }
+    }
+
+    /// Rename catalog entries after the sys symbols in this map
+    pub fn apply_to_catalog(&self, catalog: &mut SysCatalog) {
+        for (&sys_num, symbol) in self.sys.iter() {
+            let mut call = catalog.get(sys_num).cloned().unwrap_or_else(|| SysCall {
+                name: String::new(),
+                args: None,
+                returns: Default::default(),
+            });
+            call.name = symbol.name.clone();
+            catalog.insert(sys_num, call);
+        }
+    }
+}
+
+// Whether `name` assembles as a script label: an identifier that isn't a `loc_` label
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_') &&
+        !name.starts_with("loc_")
+}
+
+#[cfg(feature = "serde")]
+impl SymbolMap {
+    /// Load a map from a `.json` or `.toml` file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SymbolMap> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => SymbolMap::from_json(&text),
+            _ => SymbolMap::from_toml(&text),
+        }
+    }
+
+    /// Save to a `.json` or `.toml` file, picked by extension
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let text = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => self.to_json()?,
+            _ => self.to_toml()?,
+        };
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn from_toml(text: &str) -> Result<SymbolMap> {
+        SymbolMap::from_file(toml::from_str(text)?)
+    }
+
+    pub fn from_json(text: &str) -> Result<SymbolMap> {
+        SymbolMap::from_file(serde_json::from_str(text)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(&self.to_file())?)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.to_file())?)
+    }
+
+    fn from_file(file: SymbolFile) -> Result<SymbolMap> {
+        let mut map = SymbolMap::new();
+        for entry in file.scripts {
+            let key = if let Some(ref hash) = entry.hash {
+                let hash = u64::from_str_radix(hash.trim_start_matches("0x"), 16)
+                    .map_err(|_| Error::Format(format!("invalid script hash '{}'", hash)))?;
+                ScriptKey::Hash(hash)
+            } else if let Some(ref offset) = entry.offset {
+                ScriptKey::Offset(number(offset, 0xFFFF_FFFF, "script offset")?)
+            } else if let Some(index) = entry.index {
+                ScriptKey::Index(index)
+            } else {
+                return Err(Error::Format(format!(
+                    "script symbol '{}' needs an index, offset or hash", entry.name
+                )));
+            };
+            let mut locals = BTreeMap::new();
+            for (var, symbol) in entry.locals {
+                locals.insert(number(&var, 0xFFFF, "local")? as u16, symbol);
+            }
+            map.scripts.push(ScriptSymbol {
+                key,
+                symbol: Symbol { name: entry.name, comment: entry.comment },
+                locals,
+            });
+        }
+        for (var, symbol) in file.globals {
+            map.globals.insert(number(&var, 0xFFFF, "global")? as u16, symbol);
+        }
+        for (sys_num, symbol) in file.sys {
+            map.sys.insert(number(&sys_num, 0xFF, "sys number")? as u8, symbol);
+        }
+        Ok(map)
+    }
+
+    fn to_file(&self) -> SymbolFile {
+        SymbolFile {
+            scripts: self.scripts
+                .iter()
+                .map(|script| {
+                    let mut entry = ScriptEntry {
+                        index: None,
+                        offset: None,
+                        hash: None,
+                        name: script.symbol.name.clone(),
+                        comment: script.symbol.comment.clone(),
+                        locals: script.locals
+                            .iter()
+                            .map(|(var, symbol)| (var.to_string(), symbol.clone()))
+                            .collect(),
+                    };
+                    match script.key {
+                        ScriptKey::Index(index) => entry.index = Some(index),
+                        ScriptKey::Offset(offset) => entry.offset = Some(format!("0x{:X}", offset)),
+                        ScriptKey::Hash(hash) => entry.hash = Some(format!("{:016x}", hash)),
+                    }
+                    entry
+                })
+                .collect(),
+            globals: self.globals
+                .iter()
+                .map(|(var, symbol)| (format!("0x{:X}", var), symbol.clone()))
+                .collect(),
+            sys: self.sys
+                .iter()
+                .map(|(num, symbol)| (format!("0x{:X}", num), symbol.clone()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn number(text: &str, max: u32, what: &str) -> Result<u32> {
+    parse_number(text)
+        .filter(|&n| n <= max)
+        .ok_or_else(|| Error::Format(format!("invalid {} '{}'", what, text)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::Cmd;
+    use super::super::test::script;
+
+    #[test]
+    fn test_symbols_follow_moved_scripts() {
+        let body = [
+            Cmd::Begin { arg_count: 0, var_count: 1 },
+            Cmd::PushVar { var_type: 0, var_num: 0 },
+            Cmd::IfNot { loc: 0x14 },
+            Cmd::PushShort { val: 1 },
+            Cmd::SetVar { var_type: 1, var_num: 0x12 },
+            Cmd::End,
+        ];
+        let old = MscsbFile::new(
+            vec![script(0x10, &body)],
+            vec![],
+            0x10,
+        );
+        let mut symbols = SymbolMap::new();
+        symbols.set_script(ScriptKey::Hash(old.script_hash(0)), Symbol::new("init"));
+        symbols.set_local(ScriptKey::Hash(old.script_hash(0)), 0, Symbol::new("frame"));
+        symbols.globals.insert(0x12, Symbol::new("hitstun"));
+        #[cfg(feature = "serde")]
+        let symbols = SymbolMap::from_toml(&symbols.to_toml().unwrap()).unwrap();
+
+        // Same script after a patch moved it
+        let mut moved = body;
+        moved[2] = Cmd::IfNot { loc: 0x54 };
+        let new = MscsbFile::new(
+            vec![script(0x20, &[Cmd::End]), script(0x50, &moved)],
+            vec![],
+            0x50,
+        );
+        assert_eq!(symbols.script_name(&new, 1), Some("init"));
+        assert_eq!(symbols.script_name(&new, 0), None);
+        let text = super::super::Disassembler::new(&new).with_symbols(&symbols).to_string();
+        assert!(text.contains(".entrypoint init"));
+        assert!(text.contains("; frame"));
+        assert!(text.contains("; hitstun"));
+    }
+
+    #[test]
+    fn test_script_labels_round_trip() {
+        let file = crate::assemble("
+script_0:
+    Begin 0, 0
+    PushInt. script_2
+    CallFunc 0
+    End
+script_1:
+    Begin 0, 0
+    End
+script_2:
+    Begin 0, 0
+    End
+script_3:
+    Nop
+    End
+").unwrap();
+        let mut symbols = SymbolMap::new();
+        // Scripts 1 and 2 are identical, so both match by hash
+        symbols.set_script(ScriptKey::Hash(file.script_hash(1)), Symbol::new("helper"));
+        symbols.set_script(ScriptKey::Index(0), Symbol::new("script_3"));
+        symbols.set_script(ScriptKey::Index(3), Symbol::new("bad name"));
+        assert_eq!(symbols.script_labels(&file), vec!["script_3_2", "helper", "helper_2", "script_3"]);
+
+        let text = super::super::Disassembler::new(&file).with_symbols(&symbols).to_string();
+        assert!(text.contains("PushInt. helper_2\n"));
+        assert_eq!(crate::assemble(&text).unwrap().disassemble(), file.disassemble());
+    }
+}
@@ -0,0 +1,279 @@
+use super::{Cmd, Command};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+#[cfg(feature = "serde")]
+use {
+    super::error::{Error, Result},
+    std::fs,
+    std::path::Path,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ArgType {
+    Int,
+    Float,
+    /// Index into `MscsbFile::strings`
+    String,
+    /// Script address
+    Script,
+    Any,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Returns {
+    Nothing,
+    Int,
+    Float,
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SysCall {
+    pub name: String,
+    /// Expected arguments, deepest first. `None` if the arity isn't known.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub args: Option<Vec<ArgType>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub returns: Returns,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SysCatalog {
+    entries: BTreeMap<u8, SysCall>,
+}
+
+// Built-in catalog as `(sys_num, name, args, returns)`. Leave `args` as `None` when the
+// arity isn't known, so calls aren't flagged. Only add entries that have been confirmed
+// against the game; anything speculative belongs in a user catalog.
+const BUILTIN: &[(u8, &str, Option<&[ArgType]>, Returns)] = &[];
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CatalogFile {
+    #[serde(default)]
+    sys: BTreeMap<String, SysCall>,
+}
+
+impl SysCatalog {
+    pub fn new() -> SysCatalog {
+        SysCatalog::default()
+    }
+
+    /// The catalog shipped with the library
+    pub fn builtin() -> SysCatalog {
+        SysCatalog::builtin_shared().clone()
+    }
+
+    // Built once and shared by everything that defaults to the built-in catalog
+    pub(crate) fn builtin_shared() -> &'static SysCatalog {
+        static BUILTIN_CATALOG: OnceLock<SysCatalog> = OnceLock::new();
+        BUILTIN_CATALOG.get_or_init(|| {
+            let mut catalog = SysCatalog::new();
+            for &(sys_num, name, args, returns) in BUILTIN {
+                catalog.insert(sys_num, SysCall {
+                    name: String::from(name),
+                    args: args.map(|args| args.to_vec()),
+                    returns,
+                });
+            }
+            catalog
+        })
+    }
+
+    pub fn get(&self, sys_num: u8) -> Option<&SysCall> {
+        self.entries.get(&sys_num)
+    }
+
+    pub fn insert(&mut self, sys_num: u8, call: SysCall) {
+        self.entries.insert(sys_num, call);
+    }
+
+    /// Add every entry of `other`, replacing existing ones
+    pub fn extend(&mut self, other: SysCatalog) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &SysCall)> {
+        self.entries.iter().map(|(&num, call)| (num, call))
+    }
+
+    /// Catalog name for `sys_num`, or `sys_XX` if it isn't known
+    pub fn name(&self, sys_num: u8) -> String {
+        match self.get(sys_num) {
+            Some(call) => call.name.clone(),
+            None => format!("sys_{:X}", sys_num),
+        }
+    }
+
+    /// Describe how a `Sys` command disagrees with the catalog, if it does
+    pub fn check(&self, command: &Command) -> Option<String> {
+        let (arg_count, sys_num) = match command.cmd {
+            Cmd::Sys { arg_count, sys_num } => (arg_count, sys_num),
+            _ => return None,
+        };
+        let call = self.get(sys_num)?;
+        match call.args {
+            Some(ref args) if args.len() != arg_count as usize => {
+                return Some(format!(
+                    "{} expects {} arg{} but is called with {}",
+                    call.name, args.len(), if args.len() == 1 { "" } else { "s" }, arg_count
+                ));
+            }
+            _ => {}
+        }
+        if command.push_bit && call.returns == Returns::Nothing {
+            return Some(format!("{} returns nothing but its result is pushed", call.name));
+        }
+        None
+    }
+}
+
+#[cfg(feature = "serde")]
+impl SysCatalog {
+    /// The built-in catalog with entries from a user file layered on top
+    pub fn builtin_with<P: AsRef<Path>>(path: P) -> Result<SysCatalog> {
+        let mut catalog = SysCatalog::builtin();
+        catalog.extend(SysCatalog::load(path)?);
+        Ok(catalog)
+    }
+
+    /// Load a catalog from a `.json` or `.toml` file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SysCatalog> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => SysCatalog::from_json(&text),
+            _ => SysCatalog::from_toml(&text),
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<SysCatalog> {
+        SysCatalog::from_file(toml::from_str(text)?)
+    }
+
+    pub fn from_json(text: &str) -> Result<SysCatalog> {
+        SysCatalog::from_file(serde_json::from_str(text)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(&self.to_file())?)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.to_file())?)
+    }
+
+    fn from_file(file: CatalogFile) -> Result<SysCatalog> {
+        let mut entries = BTreeMap::new();
+        for (key, call) in file.sys {
+            let sys_num = parse_number(&key)
+                .filter(|&n| n <= 0xFF)
+                .ok_or_else(|| Error::Format(format!("invalid sys number '{}'", key)))?;
+            entries.insert(sys_num as u8, call);
+        }
+        Ok(SysCatalog { entries })
+    }
+
+    fn to_file(&self) -> CatalogFile {
+        CatalogFile {
+            sys: self.entries
+                .iter()
+                .map(|(num, call)| (format!("0x{:X}", num), call.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// Parse a decimal or `0x`-prefixed hex number
+pub(crate) fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    if text.starts_with("0x") || text.starts_with("0X") {
+        u32::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::MscsbFile;
+    use super::super::test::script;
+
+    #[test]
+    fn test_catalog_override() {
+        let mut catalog = SysCatalog::builtin();
+        let mut user = SysCatalog::new();
+        user.insert(0x2F, SysCall {
+            name: String::from("set_flag"),
+            args: Some(vec![ArgType::Int, ArgType::Int]),
+            returns: Returns::Nothing,
+        });
+        #[cfg(feature = "serde")]
+        {
+            let parsed = SysCatalog::from_toml(r#"
+                [sys.0x2F]
+                name = "set_flag"
+                args = ["int", "int"]
+                returns = "nothing"
+            "#).unwrap();
+            assert_eq!(parsed, user);
+            assert_eq!(SysCatalog::from_json(&user.to_json().unwrap()).unwrap(), user);
+        }
+        catalog.extend(user);
+        assert_eq!(catalog.name(0x2F), "set_flag");
+        assert_eq!(catalog.name(0x30), "sys_30");
+
+        let file = MscsbFile::new(
+            vec![script(0x10, &[
+                Cmd::Begin { arg_count: 0, var_count: 0 },
+                Cmd::Sys { arg_count: 3, sys_num: 0x2F },
+                Cmd::End,
+            ])],
+            vec![],
+            0x10,
+        );
+        let diagnostics = super::super::Validator::new(&catalog).validate(&file);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "set_flag expects 2 args but is called with 3");
+    }
+
+    #[test]
+    fn test_builtin_catalog() {
+        let builtin = SysCatalog::builtin();
+        assert_eq!(&builtin, SysCatalog::builtin_shared());
+        let mut names: Vec<&str> = builtin.iter().map(|(_, call)| &call.name[..]).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), builtin.iter().count());
+        for sys_num in 0..=u8::MAX {
+            match builtin.get(sys_num) {
+                Some(call) => {
+                    assert!(!call.name.is_empty() && !call.name.contains(char::is_whitespace));
+                    assert_eq!(builtin.name(sys_num), call.name);
+                }
+                None => assert_eq!(builtin.name(sys_num), format!("sys_{:X}", sys_num)),
+            }
+        }
+
+        // Calls without a confirmed entry are named by number and never flagged
+        let file = MscsbFile::new(
+            vec![script(0x10, &[
+                Cmd::Begin { arg_count: 0, var_count: 0 },
+                Cmd::Sys { arg_count: 3, sys_num: 0x2F },
+                Cmd::End,
+            ])],
+            vec![],
+            0x10,
+        );
+        if builtin.get(0x2F).is_none() {
+            assert!(super::super::Validator::new(&builtin).validate(&file).is_empty());
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use super::{printf, Cmd, MscsbFile, SysCatalog};
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Script the problem is in, `None` for file-level problems
+    pub script: Option<usize>,
+    pub position: Option<u32>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning")?,
+            Severity::Error => write!(f, "error")?,
+        }
+        if let Some(script) = self.script {
+            write!(f, " [script_{}", script)?;
+            if let Some(position) = self.position {
+                write!(f, " @ 0x{:X}", position)?;
+            }
+            write!(f, "]")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Checks an `MscsbFile` for problems the game or the writer would choke on
+pub struct Validator<'a> {
+    catalog: &'a SysCatalog,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(catalog: &'a SysCatalog) -> Validator<'a> {
+        Validator {
+            catalog,
+            diagnostics: vec![],
+        }
+    }
+
+    pub fn validate(mut self, file: &MscsbFile) -> Vec<Diagnostic> {
+        if file.get_script_from_loc(file.entrypoint).is_none() {
+            self.error(None, None, format!("entrypoint 0x{:X} is not the start of a script", file.entrypoint));
+        }
+        for (index, script) in file.scripts.iter().enumerate() {
+            let positions: HashSet<u32> = script.iter().map(|c| c.position).collect();
+            let var_count = match script.commands.first().map(|c| c.cmd) {
+                Some(Cmd::Begin { var_count, .. }) => Some(var_count),
+                _ => {
+                    self.warning(Some(index), None, String::from("script does not start with Begin"));
+                    None
+                }
+            };
+            let sources = match script.iter().any(|c| matches!(c.cmd, Cmd::PrintF { .. })) {
+                true => script.operand_sources(),
+                false => vec![],
+            };
+            for i in script.dataflow().uninitialized_reads() {
+                if let Some((_, var_num)) = script.commands[i].cmd.variable() {
+                    self.warning(Some(index), Some(script.commands[i].position),
+                                 format!("local {} may be read before it's written", var_num));
+                }
+            }
+            for (i, command) in script.iter().enumerate() {
+                let at = Some(command.position);
+                if let Some(loc) = command.cmd.branch_target() {
+                    if !positions.contains(&loc) {
+                        self.error(Some(index), at, format!(
+                            "{} target 0x{:X} is not an instruction in this script",
+                            command.cmd.name(), loc
+                        ));
+                    }
+                }
+                match (command.cmd.variable(), var_count) {
+                    (Some((0, var_num)), Some(var_count)) if var_num >= var_count => {
+                        self.error(Some(index), at, format!(
+                            "local {} is out of range (Begin declares {})", var_num, var_count
+                        ));
+                    }
+                    _ => {}
+                }
+                match command.cmd {
+                    Cmd::ErrorC | Cmd::Error37 | Cmd::Error4C => {
+                        self.warning(Some(index), at, format!("{} instruction", command.cmd.name()));
+                    }
+                    Cmd::Sys { .. } => {
+                        if let Some(problem) = self.catalog.check(command) {
+                            self.error(Some(index), at, problem);
+                        }
+                    }
+                    Cmd::PrintF { .. } => {
+                        for problem in printf::check(file, script, &sources, i) {
+                            self.error(Some(index), at, problem);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.diagnostics
+    }
+
+    fn warning(&mut self, script: Option<usize>, position: Option<u32>, message: String) {
+        self.diagnostics.push(Diagnostic { severity: Severity::Warning, script, position, message });
+    }
+
+    fn error(&mut self, script: Option<usize>, position: Option<u32>, message: String) {
+        self.diagnostics.push(Diagnostic { severity: Severity::Error, script, position, message });
+    }
+}
+
+impl MscsbFile {
+    /// Validate against the built-in sys call catalog
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        Validator::new(SysCatalog::builtin_shared()).validate(self)
+    }
+}
@@ -0,0 +1,266 @@
+use super::{Cmd, MscsbFile, CallTarget, SysCatalog};
+use super::call_graph::address_push;
+use super::strings::{string_args, StringArg};
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum XrefTarget {
+    Global(u16),
+    Local {
+        script: usize,
+        var_num: u16,
+    },
+    /// Index into `MscsbFile::strings`
+    String(u32),
+    /// Index into `MscsbFile::scripts`
+    Script(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum XrefKind {
+    Read,
+    Write,
+    /// `IncI`, `DecI`, `IncF`, `DecF`
+    IncDec,
+    /// `AddVarBy`, `SubVarByF`, etc. (reads and writes the variable)
+    CompoundAssign,
+    /// Value pushed as a constant, such as a string index or script address
+    Reference,
+    Call,
+}
+
+impl XrefKind {
+    pub fn is_read(self) -> bool {
+        matches!(self, XrefKind::Read | XrefKind::IncDec | XrefKind::CompoundAssign)
+    }
+
+    pub fn is_write(self) -> bool {
+        matches!(self, XrefKind::Write | XrefKind::IncDec | XrefKind::CompoundAssign)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct XrefSite {
+    pub script: usize,
+    /// Index of the command within the script
+    pub command: usize,
+    pub position: u32,
+    pub kind: XrefKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct XrefIndex {
+    to: HashMap<XrefTarget, Vec<XrefSite>>,
+    from: HashMap<(usize, usize), Vec<(XrefTarget, XrefKind)>>,
+}
+
+impl Cmd {
+    /// The `(var_type, var_num)` this command addresses, if any
+    pub fn variable(&self) -> Option<(u8, u16)> {
+        match *self {
+            Cmd::PushVar { var_type, var_num } |
+            Cmd::IncI { var_type, var_num } | Cmd::DecI { var_type, var_num } |
+            Cmd::SetVar { var_type, var_num } |
+            Cmd::AddVarBy { var_type, var_num } | Cmd::SubVarBy { var_type, var_num } |
+            Cmd::MultVarBy { var_type, var_num } | Cmd::DivVarBy { var_type, var_num } |
+            Cmd::ModVarBy { var_type, var_num } | Cmd::AndVarBy { var_type, var_num } |
+            Cmd::OrVarBy { var_type, var_num } | Cmd::XorVarBy { var_type, var_num } |
+            Cmd::IncF { var_type, var_num } | Cmd::DecF { var_type, var_num } |
+            Cmd::VarSetF { var_type, var_num } |
+            Cmd::AddVarByF { var_type, var_num } | Cmd::SubVarByF { var_type, var_num } |
+            Cmd::MultVarByF { var_type, var_num } | Cmd::DivVarByF { var_type, var_num } =>
+                Some((var_type, var_num)),
+            _ => None,
+        }
+    }
+
+    /// Mutable `(var_type, var_num)` of the variable this command addresses, if any
+    pub fn variable_mut(&mut self) -> Option<(&mut u8, &mut u16)> {
+        match *self {
+            Cmd::PushVar { ref mut var_type, ref mut var_num } |
+            Cmd::IncI { ref mut var_type, ref mut var_num } | Cmd::DecI { ref mut var_type, ref mut var_num } |
+            Cmd::SetVar { ref mut var_type, ref mut var_num } |
+            Cmd::AddVarBy { ref mut var_type, ref mut var_num } | Cmd::SubVarBy { ref mut var_type, ref mut var_num } |
+            Cmd::MultVarBy { ref mut var_type, ref mut var_num } | Cmd::DivVarBy { ref mut var_type, ref mut var_num } |
+            Cmd::ModVarBy { ref mut var_type, ref mut var_num } | Cmd::AndVarBy { ref mut var_type, ref mut var_num } |
+            Cmd::OrVarBy { ref mut var_type, ref mut var_num } | Cmd::XorVarBy { ref mut var_type, ref mut var_num } |
+            Cmd::IncF { ref mut var_type, ref mut var_num } | Cmd::DecF { ref mut var_type, ref mut var_num } |
+            Cmd::VarSetF { ref mut var_type, ref mut var_num } |
+            Cmd::AddVarByF { ref mut var_type, ref mut var_num } | Cmd::SubVarByF { ref mut var_type, ref mut var_num } |
+            Cmd::MultVarByF { ref mut var_type, ref mut var_num } | Cmd::DivVarByF { ref mut var_type, ref mut var_num } =>
+                Some((var_type, var_num)),
+            _ => None,
+        }
+    }
+
+    /// How this command accesses the variable returned by `variable`
+    pub fn variable_access(&self) -> Option<XrefKind> {
+        match *self {
+            Cmd::PushVar { .. } => Some(XrefKind::Read),
+            Cmd::SetVar { .. } | Cmd::VarSetF { .. } => Some(XrefKind::Write),
+            Cmd::IncI { .. } | Cmd::DecI { .. } |
+            Cmd::IncF { .. } | Cmd::DecF { .. } => Some(XrefKind::IncDec),
+            _ if self.variable().is_some() => Some(XrefKind::CompoundAssign),
+            _ => None,
+        }
+    }
+}
+
+impl XrefIndex {
+    /// Index `file`, typing `Sys` arguments with the built-in sys call catalog
+    pub fn new(file: &MscsbFile) -> XrefIndex {
+        XrefIndex::with_catalog(file, SysCatalog::builtin_shared())
+    }
+
+    /// Index `file`. String references are the ones `MscsbFile::string_references_with`
+    /// finds, skipping arguments that aren't constant pushes. Script references are the
+    /// constant addresses calls jump to, the same ones the call graph resolves.
+    pub fn with_catalog(file: &MscsbFile, catalog: &SysCatalog) -> XrefIndex {
+        let mut index = XrefIndex::default();
+        for (script_index, script) in file.scripts.iter().enumerate() {
+            let sources = script.operand_sources();
+            for (i, command) in script.commands.iter().enumerate() {
+                if let (Some((var_type, var_num)), Some(kind)) =
+                    (command.cmd.variable(), command.cmd.variable_access()) {
+                    let target = if var_type == 0 {
+                        XrefTarget::Local { script: script_index, var_num }
+                    } else {
+                        XrefTarget::Global(var_num)
+                    };
+                    index.add(target, script_index, i, command.position, kind);
+                }
+                if let Cmd::CallFunc { .. } | Cmd::CallFunc2 { .. } | Cmd::CallFunc3 { .. } = command.cmd {
+                    if let Some((source, val)) = address_push(script, &sources, i) {
+                        if let Some(target) = file.get_script_from_loc(val) {
+                            index.add(XrefTarget::Script(target), script_index, source,
+                                      script.commands[source].position, XrefKind::Reference);
+                        }
+                    }
+                }
+                for arg in string_args(file, catalog, script, &sources, i) {
+                    if let StringArg::Constant { source, string } = arg {
+                        if (string as usize) < file.strings.len() {
+                            index.add(XrefTarget::String(string), script_index, source,
+                                      script.commands[source].position, XrefKind::Reference);
+                        }
+                    }
+                }
+            }
+        }
+        for site in file.call_graph().sites {
+            if let CallTarget::Script(callee) = site.target {
+                index.add(XrefTarget::Script(callee), site.caller, site.command,
+                          site.position, XrefKind::Call);
+            }
+        }
+        index
+    }
+
+    fn add(&mut self, target: XrefTarget, script: usize, command: usize, position: u32, kind: XrefKind) {
+        self.to
+            .entry(target)
+            .or_default()
+            .push(XrefSite { script, command, position, kind });
+        self.from
+            .entry((script, command))
+            .or_default()
+            .push((target, kind));
+    }
+
+    /// Every site referring to `target`
+    pub fn refs_to(&self, target: XrefTarget) -> &[XrefSite] {
+        self.to.get(&target).map(|sites| &sites[..]).unwrap_or(&[])
+    }
+
+    pub fn reads_of(&self, target: XrefTarget) -> impl Iterator<Item = &XrefSite> {
+        self.refs_to(target).iter().filter(|site| site.kind.is_read())
+    }
+
+    pub fn writes_to(&self, target: XrefTarget) -> impl Iterator<Item = &XrefSite> {
+        self.refs_to(target).iter().filter(|site| site.kind.is_write())
+    }
+
+    /// Everything the command at `command` in `script` refers to
+    pub fn refs_from(&self, script: usize, command: usize) -> &[(XrefTarget, XrefKind)] {
+        self.from.get(&(script, command)).map(|refs| &refs[..]).unwrap_or(&[])
+    }
+
+    /// All referenced targets, sorted
+    pub fn targets(&self) -> Vec<XrefTarget> {
+        let mut targets: Vec<XrefTarget> = self.to.keys().cloned().collect();
+        targets.sort();
+        targets
+    }
+}
+
+impl MscsbFile {
+    pub fn xrefs(&self) -> XrefIndex {
+        XrefIndex::new(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::test::script;
+    use super::super::{ArgType, Returns, SysCall};
+
+    #[test]
+    fn test_xrefs() {
+        let file = MscsbFile::new(
+            vec![
+                script(0x10, &[
+                    Cmd::PushVar { var_type: 1, var_num: 0x12 },
+                    Cmd::SetVar { var_type: 0, var_num: 0 },
+                    Cmd::IncI { var_type: 1, var_num: 0x12 },
+                    Cmd::PushShort { val: 1 },
+                    Cmd::PushVar { var_type: 0, var_num: 0 },
+                    Cmd::PrintF { arg_count: 2 },
+                    Cmd::PushInt { val: 0x20 },
+                    Cmd::CallFunc { arg_count: 0 },
+                    Cmd::PushShort { val: 0x20 },
+                    Cmd::CallFunc { arg_count: 0 },
+                    Cmd::PushInt { val: 0x20 },
+                    Cmd::SetVar { var_type: 1, var_num: 0x13 },
+                    Cmd::End,
+                ]),
+                script(0x20, &[
+                    Cmd::PushShort { val: 3 },
+                    Cmd::SetVar { var_type: 1, var_num: 0x12 },
+                    Cmd::PushShort { val: 0 },
+                    Cmd::Sys { arg_count: 1, sys_num: 0x30 },
+                    Cmd::End,
+                ]),
+            ],
+            vec![String::from("a"), String::from("%d")],
+            0x10,
+        );
+        let xrefs = file.xrefs();
+        let global = XrefTarget::Global(0x12);
+        assert_eq!(xrefs.refs_to(global).len(), 3);
+        assert_eq!(xrefs.writes_to(global).map(|site| site.script).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(xrefs.reads_of(XrefTarget::Local { script: 0, var_num: 0 }).count(), 1);
+        assert_eq!(xrefs.refs_to(XrefTarget::String(1))[0].command, 3);
+        assert_eq!(xrefs.refs_to(XrefTarget::Script(1)).len(), 4);
+        assert_eq!(xrefs.refs_from(0, 8), &[(XrefTarget::Script(1), XrefKind::Reference)]);
+        // Stored, not called, so it's only a number that equals script 1's start
+        assert!(xrefs.refs_from(0, 10).is_empty());
+        assert_eq!(xrefs.refs_from(0, 2), &[(global, XrefKind::IncDec)]);
+        assert!(xrefs.refs_to(XrefTarget::String(0)).is_empty());
+
+        let mut cmd = Cmd::AddVarByF { var_type: 1, var_num: 2 };
+        *cmd.variable_mut().unwrap().1 = 5;
+        assert_eq!(cmd.variable(), Some((1, 5)));
+        assert!(Cmd::End.variable_mut().is_none());
+
+        let mut catalog = SysCatalog::new();
+        catalog.insert(0x30, SysCall {
+            name: String::from("log"),
+            args: Some(vec![ArgType::String]),
+            returns: Returns::Nothing,
+        });
+        let xrefs = XrefIndex::with_catalog(&file, &catalog);
+        let sites = xrefs.refs_to(XrefTarget::String(0));
+        assert_eq!(sites.iter().map(|site| (site.script, site.command)).collect::<Vec<_>>(), vec![(1, 2)]);
+    }
+}
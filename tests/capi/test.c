@@ -0,0 +1,98 @@
+/* Exercises the C API, run with `make -C tests/capi` */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include "msc.h"
+
+#define CHECK(expr, expected) do { \
+    MscError e = (expr); \
+    if (e != (expected)) { \
+        fprintf(stderr, "%s:%d: %s returned %s\n", __FILE__, __LINE__, #expr, msc_error_message(e)); \
+        exit(1); \
+    } \
+} while (0)
+
+#define ASSERT(cond) do { \
+    if (!(cond)) { \
+        fprintf(stderr, "%s:%d: assertion failed: %s\n", __FILE__, __LINE__, #cond); \
+        exit(1); \
+    } \
+} while (0)
+
+/* main: Begin 0, 0; PushShort. 0x1; Sys 1, 0x10; End */
+static const uint8_t FILE_BYTES[] = {
+    0xb2, 0xac, 0xbc, 0xba, 0xe6, 0x90, 0x32, 0x01, 0xfd, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x1c, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x8d, 0x00, 0x01, 0x2d, 0x01, 0x10, 0x03, 0x00, 0x00, 0x00, 0x00,
+    0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+};
+
+int main(void) {
+    MscsbFile *file = NULL;
+    CHECK(msc_file_load(FILE_BYTES, 4, &file), MSC_ERROR_INVALID_FILE);
+    ASSERT(file == NULL);
+    CHECK(msc_file_load(NULL, 0, &file), MSC_ERROR_NULL_POINTER);
+
+    CHECK(msc_file_load(FILE_BYTES, sizeof FILE_BYTES, &file), MSC_ERROR_SUCCESS);
+
+    ASSERT(msc_file_script_count(file) == 1);
+    uint32_t start, end;
+    CHECK(msc_file_script_bounds(file, 0, &start, &end), MSC_ERROR_SUCCESS);
+    ASSERT(start == 0x10);
+    CHECK(msc_file_script_bounds(file, 1, &start, &end), MSC_ERROR_OUT_OF_RANGE);
+
+    size_t count, len;
+    CHECK(msc_file_command_count(file, 0, &count), MSC_ERROR_SUCCESS);
+    ASSERT(count == 4);
+    MscCommand command;
+    for (size_t i = 0; i < count; i++) {
+        CHECK(msc_file_get_command(file, 0, i, &command), MSC_ERROR_SUCCESS);
+        printf("0x%X: %s%s", command.position, command.name, command.push_bit ? "." : "");
+        for (uint32_t j = 0; j < command.operand_count; j++) {
+            printf("%s0x%X", j ? ", " : " ", command.operands[j]);
+        }
+        printf("\n");
+    }
+
+    CHECK(msc_file_get_command(file, 0, 2, &command), MSC_ERROR_SUCCESS);
+    ASSERT(strcmp(command.name, "Sys") == 0 && command.operand_count == 2 && command.operands[1] == 0x10);
+    command.operands[1] = 0x20;
+    CHECK(msc_file_set_command(file, 0, 2, &command), MSC_ERROR_SUCCESS);
+    strcpy(command.name, "Bogus");
+    CHECK(msc_file_set_command(file, 0, 2, &command), MSC_ERROR_BAD_COMMAND);
+
+    MscCommand nop = { .name = "Nop", .position = command.position + 3 };
+    CHECK(msc_file_insert_command(file, 0, 3, &nop), MSC_ERROR_SUCCESS);
+    CHECK(msc_file_remove_command(file, 0, 9), MSC_ERROR_OUT_OF_RANGE);
+
+    size_t index;
+    CHECK(msc_file_add_string(file, "hello", &index), MSC_ERROR_SUCCESS);
+    ASSERT(index == 0 && msc_file_string_count(file) == 1);
+    char small[2];
+    CHECK(msc_file_get_string(file, 0, small, sizeof small, &len), MSC_ERROR_BUFFER_TOO_SMALL);
+    ASSERT(len == 6);
+    char string[16];
+    CHECK(msc_file_get_string(file, 0, string, sizeof string, &len), MSC_ERROR_SUCCESS);
+    ASSERT(strcmp(string, "hello") == 0);
+    CHECK(msc_file_set_entrypoint(file, 0x10), MSC_ERROR_SUCCESS);
+
+    CHECK(msc_file_write(file, NULL, 0, &len), MSC_ERROR_BUFFER_TOO_SMALL);
+    uint8_t *written = malloc(len);
+    CHECK(msc_file_write(file, written, len, &len), MSC_ERROR_SUCCESS);
+    msc_file_free(file);
+
+    CHECK(msc_file_load(written, len, &file), MSC_ERROR_SUCCESS);
+    free(written);
+    CHECK(msc_file_command_count(file, 0, &count), MSC_ERROR_SUCCESS);
+    ASSERT(count == 5);
+    CHECK(msc_file_get_command(file, 0, 2, &command), MSC_ERROR_SUCCESS);
+    ASSERT(command.operands[1] == 0x20);
+    CHECK(msc_file_get_command(file, 0, 3, &command), MSC_ERROR_SUCCESS);
+    ASSERT(strcmp(command.name, "Nop") == 0);
+    msc_file_free(file);
+
+    printf("ok\n");
+    return 0;
+}